@@ -0,0 +1,166 @@
+// The robin_gb C API, built with the capi feature. Generated by cbindgen; don't edit by hand.
+
+#ifndef ROBIN_GB_H
+#define ROBIN_GB_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define ROBIN_GB_BUTTON_A 0
+
+#define ROBIN_GB_BUTTON_B 1
+
+#define ROBIN_GB_BUTTON_START 2
+
+#define ROBIN_GB_BUTTON_SELECT 3
+
+#define ROBIN_GB_BUTTON_UP 4
+
+#define ROBIN_GB_BUTTON_DOWN 5
+
+#define ROBIN_GB_BUTTON_LEFT 6
+
+#define ROBIN_GB_BUTTON_RIGHT 7
+
+/**
+ * An emulated Game Boy. Opaque to C.
+ */
+typedef struct robin_gb_t robin_gb_t;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a Game Boy running a copy of the ROM. Returns null if the ROM couldn't be loaded.
+ *
+ * # Safety
+ * rom must point to rom_size readable bytes.
+ */
+struct robin_gb_t *robin_gb_create(const uint8_t *rom, size_t rom_size);
+
+/**
+ * # Safety
+ * gb must come from robin_gb_create(), and not be used again.
+ */
+void robin_gb_destroy(struct robin_gb_t *gb);
+
+/**
+ * The size of a frame: 160x144 pixels, one grayscale byte each.
+ *
+ * # Safety
+ * gb must come from robin_gb_create().
+ */
+size_t robin_gb_frame_size(const struct robin_gb_t *gb);
+
+/**
+ * Emulates up to the next V-blank, and copies the completed frame into buffer. Returns the number of bytes
+ * copied, which is less than a whole frame if buffer_size is too small. Returns 0 without emulating anything
+ * if the Game Boy has crashed.
+ *
+ * # Safety
+ * gb must come from robin_gb_create(), and buffer must point to buffer_size writable bytes or be null.
+ */
+size_t robin_gb_run_frame(struct robin_gb_t *gb,
+                          uint8_t *buffer,
+                          size_t buffer_size);
+
+/**
+ * Returns true once emulation has panicked, for example on an invalid opcode. A crashed Game Boy doesn't
+ * emulate any more, so it should be destroyed, and another one created to start again.
+ *
+ * # Safety
+ * gb must come from robin_gb_create().
+ */
+bool robin_gb_is_crashed(const struct robin_gb_t *gb);
+
+/**
+ * button is one of the ROBIN_GB_BUTTON_ constants. Returns false if it isn't.
+ *
+ * # Safety
+ * gb must come from robin_gb_create().
+ */
+bool robin_gb_set_button(struct robin_gb_t *gb, uint32_t button, bool is_down);
+
+/**
+ * Start producing interleaved stereo samples at this rate, or stop if it's 0. Undrained samples are discarded.
+ *
+ * # Safety
+ * gb must come from robin_gb_create().
+ */
+void robin_gb_set_audio_rate(struct robin_gb_t *gb,
+                             uint32_t sample_rate);
+
+/**
+ * Moves up to max_samples of the samples produced so far into samples, oldest first, and returns how many
+ * were moved. The rest stay for the next call.
+ *
+ * # Safety
+ * gb must come from robin_gb_create(), and samples must point to max_samples writable samples or be null.
+ */
+size_t robin_gb_drain_audio(struct robin_gb_t *gb,
+                            int16_t *samples,
+                            size_t max_samples);
+
+/**
+ * The size of the cartridge RAM that robin_gb_save_sram() writes.
+ *
+ * # Safety
+ * gb must come from robin_gb_create().
+ */
+size_t robin_gb_sram_size(const struct robin_gb_t *gb);
+
+/**
+ * Copies the cartridge RAM into buffer, returning the number of bytes copied.
+ *
+ * # Safety
+ * gb must come from robin_gb_create(), and buffer must point to buffer_size writable bytes or be null.
+ */
+size_t robin_gb_save_sram(const struct robin_gb_t *gb,
+                          uint8_t *buffer,
+                          size_t buffer_size);
+
+/**
+ * Replaces the cartridge RAM. Returns false if sram_size isn't robin_gb_sram_size().
+ *
+ * # Safety
+ * gb must come from robin_gb_create(), and sram must point to sram_size readable bytes.
+ */
+bool robin_gb_load_sram(struct robin_gb_t *gb, const uint8_t *sram, size_t sram_size);
+
+/**
+ * The size of the buffer robin_gb_save_state() needs.
+ *
+ * # Safety
+ * gb must come from robin_gb_create().
+ */
+size_t robin_gb_state_size(const struct robin_gb_t *gb);
+
+/**
+ * Saves the whole emulator state into buffer, returning its size, or 0 if buffer is too small. States can
+ * only be loaded by the same build of the library.
+ *
+ * # Safety
+ * gb must come from robin_gb_create(), and buffer must point to buffer_size writable bytes or be null.
+ */
+size_t robin_gb_save_state(const struct robin_gb_t *gb,
+                           uint8_t *buffer,
+                           size_t buffer_size);
+
+/**
+ * Returns false if the state doesn't fit this Game Boy, in which case nothing is loaded, or if loading it
+ * panicked, in which case the Game Boy has crashed.
+ *
+ * # Safety
+ * gb must come from robin_gb_create(), and state must point to state_size readable bytes.
+ */
+bool robin_gb_load_state(struct robin_gb_t *gb,
+                         const uint8_t *state,
+                         size_t state_size);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ROBIN_GB_H */
@@ -0,0 +1,216 @@
+// The emulator's stereo sample stream, and tools for recording it: a WAV writer, and a hash of the stream for
+// regression tests that run without an audio device.
+//
+// There's no APU yet, so the stream is silence. It's still produced at the right rate, so that recordings
+// have the right length and hashes only change once real samples exist.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// The Game Boy's clock rate, in cycles per second.
+pub const CLOCK_RATE: u32 = 4_194_304;
+
+// Collects interleaved left/right samples at the host's sample rate.
+#[derive(Clone)]
+pub(crate) struct AudioRecorder {
+    sample_rate: u32,
+    // Cycles not yet turned into samples, multiplied by the sample rate so that no precision is lost.
+    elapsed: u64,
+    pub samples: Vec<i16>,
+}
+
+impl AudioRecorder {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            elapsed: 0,
+            samples: vec![],
+        }
+    }
+
+    pub fn update(&mut self, elapsed_cycles: u8) {
+        self.elapsed += u64::from(elapsed_cycles) * u64::from(self.sample_rate);
+
+        while self.elapsed >= u64::from(CLOCK_RATE) {
+            self.elapsed -= u64::from(CLOCK_RATE);
+
+            // rwtodo: mix the 4 channels here, once there's an APU.
+            self.samples.extend_from_slice(&[0, 0]);
+        }
+    }
+}
+
+/// Writes interleaved stereo samples as a 16-bit PCM WAV file. The header's sizes are filled in by finish(),
+/// or when the writer is dropped.
+pub struct WavWriter<W: Write + Seek> {
+    sink: W,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut sink: W, sample_rate: u32) -> io::Result<Self> {
+        const CHANNELS: u16 = 2;
+        const BYTES_PER_SAMPLE: u16 = 2;
+
+        sink.write_all(b"RIFF")?;
+        sink.write_all(&36u32.to_le_bytes())?; // Filled in later.
+        sink.write_all(b"WAVEfmt ")?;
+        sink.write_all(&16u32.to_le_bytes())?;
+        sink.write_all(&1u16.to_le_bytes())?; // PCM.
+        sink.write_all(&CHANNELS.to_le_bytes())?;
+        sink.write_all(&sample_rate.to_le_bytes())?;
+        let block_align = CHANNELS * BYTES_PER_SAMPLE;
+        sink.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+        sink.write_all(&block_align.to_le_bytes())?;
+        sink.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+        sink.write_all(b"data")?;
+        sink.write_all(&0u32.to_le_bytes())?; // Filled in later.
+
+        Ok(Self { sink, data_size: 0 })
+    }
+
+    /// Fails without writing anything if the file would grow past the 4 GiB that its header can describe.
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let data_size = u32::try_from(samples.len())
+            .ok()
+            .and_then(|len| len.checked_mul(2))
+            .and_then(|size| size.checked_add(self.data_size))
+            .filter(|data_size| data_size.checked_add(36).is_some())
+            .ok_or_else(|| io::Error::other("WAV files can't be larger than 4 GiB"))?;
+
+        for sample in samples {
+            self.sink.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size = data_size;
+        Ok(())
+    }
+
+    fn write_sizes(&mut self) -> io::Result<()> {
+        let position = self.sink.stream_position()?;
+
+        self.sink.seek(SeekFrom::Start(4))?;
+        self.sink.write_all(&(36 + self.data_size).to_le_bytes())?;
+        self.sink.seek(SeekFrom::Start(40))?;
+        self.sink.write_all(&self.data_size.to_le_bytes())?;
+
+        self.sink.seek(SeekFrom::Start(position))?;
+        self.sink.flush()
+    }
+
+    /// Fill in the header and flush, reporting any error that dropping the writer would ignore.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_sizes()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.write_sizes();
+    }
+}
+
+/// A hash of a sample stream (64-bit FNV-1a over the little-endian samples), which is the same on every
+/// platform and Rust version, unlike std's hashers.
+pub struct AudioHasher {
+    hash: u64,
+}
+
+impl AudioHasher {
+    pub fn new() -> Self {
+        Self {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    pub fn update(&mut self, samples: &[i16]) {
+        for byte in samples.iter().flat_map(|sample| sample.to_le_bytes()) {
+            self.hash ^= u64::from(byte);
+            self.hash = self.hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl Default for AudioHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameBoy;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_sample_rate() {
+        // loop: JR loop
+        let mut game_boy = GameBoy::new(&crate::test_rom(&[0x18, 0xfe]));
+        game_boy.emulate_next_frame();
+        assert!(game_boy.take_audio_samples().is_empty());
+
+        // Samples are produced for every cycle that's emulated, so a frame of 154 lines of 456 cycles is about
+        // 803.6 samples at 48kHz.
+        game_boy.record_audio(Some(48_000));
+        let start_cycles = game_boy.cycles;
+        let mut sample_count = 0;
+        for _ in 0..5 {
+            game_boy.emulate_next_frame();
+            sample_count += game_boy.take_audio_samples().len() / 2;
+        }
+        let elapsed_cycles = game_boy.cycles - start_cycles;
+        assert_eq!(
+            sample_count as u64,
+            elapsed_cycles * 48_000 / u64::from(CLOCK_RATE)
+        );
+        assert!((4017..=4019).contains(&sample_count));
+    }
+
+    #[test]
+    fn test_wav_writer() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut writer = WavWriter::new(&mut buffer, 48_000).unwrap();
+        writer.write_samples(&[1, -1, 0x1234, 0]).unwrap();
+        writer.finish().unwrap();
+
+        let wav = buffer.into_inner();
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav[4..8], 44u32.to_le_bytes());
+        assert_eq!(wav[24..28], 48_000u32.to_le_bytes());
+        assert_eq!(wav[40..44], 8u32.to_le_bytes());
+        assert_eq!(wav[44..], [0x01, 0x00, 0xff, 0xff, 0x34, 0x12, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_wav_writer_size_limit() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut writer = WavWriter::new(&mut buffer, 48_000).unwrap();
+        writer.data_size = u32::MAX - 36 - 4;
+        assert!(writer.write_samples(&[1, 2, 3]).is_err());
+        writer.write_samples(&[1, 2]).unwrap();
+        assert!(writer.write_samples(&[3]).is_err());
+        drop(writer);
+
+        let wav = buffer.into_inner();
+        assert_eq!(wav.len(), 44 + 4);
+        assert_eq!(wav[4..8], u32::MAX.to_le_bytes());
+    }
+
+    #[test]
+    fn test_audio_hasher() {
+        // Splitting the stream up doesn't change the hash.
+        let mut whole = AudioHasher::new();
+        whole.update(&[1, 2, 3, 4]);
+        let mut split = AudioHasher::new();
+        split.update(&[1, 2]);
+        split.update(&[3, 4]);
+        assert_eq!(whole.finish(), split.finish());
+
+        assert_eq!(AudioHasher::new().finish(), 0xcbf2_9ce4_8422_2325);
+        assert_ne!(whole.finish(), AudioHasher::new().finish());
+    }
+}
@@ -0,0 +1,171 @@
+// Per-rom expectations, checked in as the baseline that CI compares against to find regressions.
+//
+// Each line is a rom file name and a colon, then the expected outcome (pass, fail or skip) and an optional
+// frame budget. Names can contain spaces, so the last colon on the line ends the name. An indented "serial:"
+// line after a rom records its serial output from the run the baseline was made from:
+//
+//     # Comments start with '#'.
+//     cpu_instrs.gb: pass
+//         serial: cpu_instrs 01:ok 02:ok ... Passed all tests
+//     03-op sp,hl.gb: fail
+//     dmg-acid2.gb: pass frames=120
+//     slow_rom.gb: skip
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Clone, Debug)]
+pub struct Expectation {
+    pub outcome: Outcome,
+    pub frames: Option<u32>,
+    pub serial: Option<String>,
+}
+
+// Roms without an entry are expected to pass.
+impl Default for Expectation {
+    fn default() -> Self {
+        Self {
+            outcome: Outcome::Pass,
+            frames: None,
+            serial: None,
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<HashMap<String, Expectation>, String> {
+    let mut expectations = HashMap::new();
+    let mut previous_name: Option<String> = None;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let error = |message: String| format!("Line {}: {}", line_index + 1, message);
+
+        // Serial lines are taken verbatim, since serial output can contain '#'.
+        if line.starts_with(char::is_whitespace) {
+            if let Some(serial) = line.trim_start().strip_prefix("serial:") {
+                let name = previous_name
+                    .as_ref()
+                    .ok_or(error("Serial line without a rom".to_owned()))?;
+                let expectation: &mut Expectation = expectations.get_mut(name).unwrap();
+                expectation.serial = Some(serial.trim().to_owned());
+                continue;
+            }
+        }
+
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, options) = line
+            .rsplit_once(':')
+            .ok_or(error("Expected a rom name followed by ':'".to_owned()))?;
+        let name = name.trim();
+        let mut words = options.split_whitespace();
+
+        let outcome = match words.next() {
+            Some("pass") => Outcome::Pass,
+            Some("fail") => Outcome::Fail,
+            Some("skip") => Outcome::Skip,
+            Some(other) => return Err(error(format!("Unknown outcome '{}'", other))),
+            None => return Err(error("Expected pass, fail or skip".to_owned())),
+        };
+
+        let mut frames = None;
+        for word in words {
+            let value = word
+                .strip_prefix("frames=")
+                .ok_or(error(format!("Unknown option '{}'", word)))?;
+            frames = Some(
+                value
+                    .parse()
+                    .map_err(|e| error(format!("'{}': {}", word, e)))?,
+            );
+        }
+
+        let expectation = Expectation {
+            outcome,
+            frames,
+            serial: None,
+        };
+        expectations.insert(name.to_owned(), expectation);
+        previous_name = Some(name.to_owned());
+    }
+
+    Ok(expectations)
+}
+
+// The inverse of parse, sorted by rom name. Comments aren't preserved.
+pub fn write(expectations: &HashMap<String, Expectation>) -> String {
+    let mut names: Vec<&String> = expectations.keys().collect();
+    names.sort();
+
+    let mut text = String::from("# Generated by ci_rom_test --update-expectations.\n");
+    for name in names {
+        let expectation = &expectations[name];
+        let outcome = match expectation.outcome {
+            Outcome::Pass => "pass",
+            Outcome::Fail => "fail",
+            Outcome::Skip => "skip",
+        };
+
+        text += &format!("{}: {}", name, outcome);
+        if let Some(frames) = expectation.frames {
+            text += &format!(" frames={}", frames);
+        }
+        text += "\n";
+
+        if let Some(serial) = &expectation.serial {
+            text += &format!("    serial: {}\n", serial);
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = "\
+# Generated by ci_rom_test --update-expectations.
+01-special.gb: pass
+    serial: 01-special Passed # not a comment
+03-op sp,hl.gb: fail frames=120
+04-op r,imm.gb: pass
+08-misc instrs.gb: skip
+10-bit ops.gb: pass
+11-op a,(hl).gb: fail
+";
+        let expectations = parse(text).unwrap();
+        assert_eq!(expectations.len(), 6);
+        assert_eq!(expectations["03-op sp,hl.gb"].outcome, Outcome::Fail);
+        assert_eq!(expectations["03-op sp,hl.gb"].frames, Some(120));
+        assert_eq!(expectations["08-misc instrs.gb"].outcome, Outcome::Skip);
+        assert_eq!(
+            expectations["01-special.gb"].serial.as_deref(),
+            Some("01-special Passed # not a comment")
+        );
+
+        assert_eq!(write(&expectations), text);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse("a.gb pass").unwrap_err(),
+            "Line 1: Expected a rom name followed by ':'"
+        );
+        assert_eq!(
+            parse("a.gb: maybe").unwrap_err(),
+            "Line 1: Unknown outcome 'maybe'"
+        );
+    }
+}
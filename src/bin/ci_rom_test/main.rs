@@ -0,0 +1,326 @@
+// This file produces a binary that receives rom files, grades them, and reports the results as an HTML table,
+// JSON or JUnit XML. Roms are graded by comparing against a reference screenshot if there is one, otherwise by
+// mooneye's Fibonacci registers if they stop on LD B,B, otherwise by looking for "passed" in their serial output.
+
+mod expectations;
+#[path = "../shared/json.rs"]
+mod json;
+mod regressions;
+mod report;
+mod screenshot;
+
+use clap::Parser;
+use expectations::{Expectation, Outcome};
+use regex::Regex;
+use report::RomResult;
+use robin_gb::audio::{AudioHasher, WavWriter};
+use robin_gb::debugger::StopReason;
+use robin_gb::trace::Tracer;
+use robin_gb::{GameBoy, Register};
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufWriter;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    /// One or more paths to rom files to test
+    #[arg(value_name = "ROM_PATH", num_args = 1..)]
+    roms: Vec<PathBuf>,
+
+    /// Write a Gameboy Doctor style execution trace for each rom into this directory.
+    /// LY reads are fixed at 0x90 while tracing, to match the reference logs.
+    #[arg(long, value_name = "DIR")]
+    trace_dir: Option<PathBuf>,
+
+    /// Directory of reference screenshots. A rom named foo.gb is compared against foo.png, if it exists.
+    #[arg(long, value_name = "DIR")]
+    references: Option<PathBuf>,
+
+    /// Directory that diff images of failed screenshot comparisons are written to
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    diff_dir: PathBuf,
+
+    /// Record each rom's audio into this directory as 16-bit stereo WAV. A rom named foo.gb is recorded to foo.wav.
+    /// There's no APU yet, so it's silence for now.
+    #[arg(long, value_name = "DIR")]
+    audio_dir: Option<PathBuf>,
+
+    /// Audio sample rate, in Hz. The audio hash in the report depends on it, though the audio is silence for now.
+    #[arg(long, default_value_t = 48_000)]
+    sample_rate: u32,
+
+    /// Maximum number of frames to emulate for each rom, unless its expectation sets one.
+    /// Defaults to 1 minute's worth (Game Boy runs at 60 FPS).
+    #[arg(long, default_value_t = 60 * 60)]
+    frames: u32,
+
+    /// Format of the report printed to stdout
+    #[arg(long, value_enum, default_value_t = report::Format::Html)]
+    format: report::Format,
+
+    /// File of per-rom expectations (pass, fail or skip, and an optional frame budget). Roms not listed are
+    /// expected to pass.
+    #[arg(long, value_name = "PATH")]
+    expectations: Option<PathBuf>,
+
+    /// Rewrite the expectations file with the results of this run, instead of comparing against it
+    #[arg(long, requires = "expectations")]
+    update_expectations: bool,
+}
+
+// Mooneye's test roms signal a pass by loading the first Fibonacci numbers into these registers before LD B,B.
+const FIBONACCI_REGISTERS: [(Register, u16, &str); 6] = [
+    (Register::B, 3, "B"),
+    (Register::C, 5, "C"),
+    (Register::D, 8, "D"),
+    (Register::E, 13, "E"),
+    (Register::H, 21, "H"),
+    (Register::L, 34, "L"),
+];
+
+fn grade_fibonacci_registers(game_boy: &GameBoy) -> Result<String, String> {
+    let registers = FIBONACCI_REGISTERS
+        .iter()
+        .map(|(register, _, name)| format!("{}={}", name, game_boy.register(*register)))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let passed = FIBONACCI_REGISTERS
+        .iter()
+        .all(|(register, expected, _)| game_boy.register(*register) == *expected);
+
+    if passed {
+        Ok(format!("Fibonacci registers {}", registers))
+    } else {
+        Err(format!("Registers {}", registers))
+    }
+}
+
+fn grade_screenshot(
+    frame: &[u8],
+    reference_path: &Path,
+    diff_path: &Path,
+) -> Result<String, String> {
+    let reference = screenshot::load_reference(reference_path)?;
+    let differences = screenshot::count_differences(frame, &reference);
+
+    if differences == 0 {
+        Ok("Screenshot matches".to_owned())
+    } else {
+        screenshot::write_diff(diff_path, frame, &reference)?;
+        Err(format!(
+            "{} pixels differ, see {}",
+            differences,
+            diff_path.display()
+        ))
+    }
+}
+
+// Progress is written to frames, serial and audio_hash as the test runs, so it's still available if the emulator
+// panics.
+fn run_rom_test(
+    path: &Path,
+    args: &CliArgs,
+    frame_budget: u32,
+    frames: &mut u32,
+    serial: &mut String,
+    audio_hash: &mut Option<u64>,
+) -> Result<String, String> {
+    // Validate the path.
+    let extension = path.extension().and_then(std::ffi::OsStr::to_str);
+    let extension = extension.ok_or("No extension found for path")?;
+    if extension.to_lowercase() != "gb" {
+        return Err("Expected extension 'gb' for path".to_owned());
+    }
+
+    // Load the data and boot the Game Boy with serial output enabled.
+    let rom_bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let mut game_boy = GameBoy::new(&rom_bytes);
+    game_boy.record_serial_output(true);
+
+    game_boy.set_break_on_ld_b_b(true);
+
+    let file_stem = path.file_stem().unwrap().to_string_lossy();
+    game_boy.record_audio(Some(args.sample_rate));
+    let mut wav = match &args.audio_dir {
+        Some(audio_dir) => {
+            let wav_file = fs::File::create(audio_dir.join(format!("{}.wav", file_stem)))
+                .map_err(|e| e.to_string())?;
+            Some(
+                WavWriter::new(BufWriter::new(wav_file), args.sample_rate)
+                    .map_err(|e| e.to_string())?,
+            )
+        }
+        None => None,
+    };
+    let mut audio_hasher = AudioHasher::new();
+
+    if let Some(trace_dir) = &args.trace_dir {
+        let log_path = trace_dir.join(path.with_extension("log").file_name().unwrap());
+        let log_file = fs::File::create(log_path).map_err(|e| e.to_string())?;
+        game_boy.set_tracer(Some(Tracer::new(BufWriter::new(log_file))));
+        game_boy.set_ly_override(Some(0x90));
+    }
+
+    let mut stopped_on_ld_b_b = false;
+    for _ in 0..frame_budget {
+        *frames += 1;
+        let reason = game_boy.emulate_next_frame();
+
+        let samples = game_boy.take_audio_samples();
+        audio_hasher.update(&samples);
+        *audio_hash = Some(audio_hasher.finish());
+        if let Some(wav) = &mut wav {
+            wav.write_samples(&samples).map_err(|e| e.to_string())?;
+        }
+
+        if reason == StopReason::SoftwareBreakpoint {
+            stopped_on_ld_b_b = true;
+            break;
+        }
+    }
+
+    if let Some(wav) = wav {
+        wav.finish().map_err(|e| e.to_string())?;
+    }
+
+    if let Some(serial_buffer) = game_boy.serial_buffer() {
+        for serial_byte in serial_buffer {
+            // Grab any ASCII bytes and put them in a string.
+            if *serial_byte < 128 {
+                serial.push(*serial_byte as char);
+            }
+        }
+
+        // Shrink all occurrences of whitespace to one space character, for readability.
+        let re = Regex::new(r"\s+").unwrap();
+        *serial = re.replace_all(serial, " ").trim().to_owned();
+    }
+
+    if let Some(references) = &args.references {
+        let reference_path = references.join(format!("{}.png", file_stem));
+        if reference_path.exists() {
+            let diff_path = args.diff_dir.join(format!("{}.diff.png", file_stem));
+            return grade_screenshot(game_boy.frame(), &reference_path, &diff_path);
+        }
+    }
+
+    if stopped_on_ld_b_b {
+        return grade_fibonacci_registers(&game_boy);
+    }
+
+    if serial.to_lowercase().contains("passed") {
+        Ok(serial.clone())
+    } else if game_boy.serial_buffer().is_none() {
+        Err("Couldn't read serial data".to_owned())
+    } else if serial.is_empty() {
+        Err("No serial output".to_owned())
+    } else {
+        Err(serial.clone())
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_owned()
+    }
+}
+
+fn run_rom(path: &Path, args: &CliArgs, expectation: Expectation) -> RomResult {
+    let name = path
+        .file_name()
+        .and_then(std::ffi::OsStr::to_str)
+        .expect("Expected file name")
+        .to_owned();
+
+    let mut rom_result = RomResult {
+        name,
+        expected: expectation.outcome,
+        result: None,
+        serial: String::new(),
+        frames: 0,
+        audio_hash: None,
+        duration: Default::default(),
+        panicked: false,
+    };
+
+    if expectation.outcome == Outcome::Skip {
+        return rom_result;
+    }
+
+    // Catch panics so that one broken rom doesn't abort the whole suite.
+    let frame_budget = expectation.frames.unwrap_or(args.frames);
+    let start = Instant::now();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        run_rom_test(
+            path,
+            args,
+            frame_budget,
+            &mut rom_result.frames,
+            &mut rom_result.serial,
+            &mut rom_result.audio_hash,
+        )
+    }));
+    rom_result.duration = start.elapsed();
+
+    rom_result.result = Some(result.unwrap_or_else(|payload| {
+        rom_result.panicked = true;
+        Err(format!("Panicked: {}", panic_message(payload.as_ref())))
+    }));
+
+    rom_result
+}
+
+fn main() {
+    let args = CliArgs::parse();
+
+    if let Some(trace_dir) = &args.trace_dir {
+        fs::create_dir_all(trace_dir).expect("Couldn't create the trace directory");
+    }
+    fs::create_dir_all(&args.diff_dir).expect("Couldn't create the diff directory");
+    if let Some(audio_dir) = &args.audio_dir {
+        fs::create_dir_all(audio_dir).expect("Couldn't create the audio directory");
+    }
+
+    let mut expectations = match &args.expectations {
+        Some(path) => {
+            let text = fs::read_to_string(path).expect("Couldn't read the expectations file");
+            expectations::parse(&text).expect("Couldn't parse the expectations file")
+        }
+        None => HashMap::new(),
+    };
+
+    let results: Vec<RomResult> = args
+        .roms
+        .iter()
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            let expectation = expectations.get(name.as_ref()).cloned().unwrap_or_default();
+            run_rom(path, &args, expectation)
+        })
+        .collect();
+
+    println!("{}", report::format(&results, args.format));
+
+    // The comparison goes to stderr, to keep stdout parseable.
+    if let Some(path) = &args.expectations {
+        if args.update_expectations {
+            regressions::update(&results, &mut expectations);
+            fs::write(path, expectations::write(&expectations))
+                .expect("Couldn't write the expectations file");
+        } else {
+            let comparison = regressions::compare(&results, &expectations);
+            eprint!("{}", comparison.summary);
+            if comparison.has_regressions {
+                std::process::exit(1);
+            }
+        }
+    }
+}
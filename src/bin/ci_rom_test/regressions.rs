@@ -0,0 +1,195 @@
+// Comparing a run against the expectations baseline, to report regressions and newly passing roms.
+
+use crate::expectations::{Expectation, Outcome};
+use crate::report::RomResult;
+use std::collections::HashMap;
+
+pub struct Comparison {
+    pub summary: String,
+    pub has_regressions: bool,
+}
+
+// Shows where two single-line serial outputs diverge.
+fn serial_diff(expected: Option<&str>, actual: &str) -> String {
+    let Some(expected) = expected else {
+        if actual.is_empty() {
+            return String::new();
+        }
+        return format!("    + {}\n", actual);
+    };
+
+    if expected == actual {
+        return "    (serial output unchanged)\n".to_owned();
+    }
+
+    let common_prefix = expected
+        .char_indices()
+        .zip(actual.chars())
+        .take_while(|((_, a), b)| a == b)
+        .last()
+        .map_or(0, |((i, c), _)| i + c.len_utf8());
+
+    format!(
+        "    - {}\n    + {}\n      {}^ first difference\n",
+        expected,
+        actual,
+        " ".repeat(expected[..common_prefix].chars().count())
+    )
+}
+
+fn section(
+    title: &str,
+    results: &[&RomResult],
+    expectations: &HashMap<String, Expectation>,
+) -> String {
+    if results.is_empty() {
+        return String::new();
+    }
+
+    let mut text = format!("{} ({}):\n", title, results.len());
+    for result in results {
+        let message = match &result.result {
+            Some(Ok(message)) | Some(Err(message)) => message.as_str(),
+            None => "",
+        };
+        text += &format!("  {}: {}\n", result.name, message);
+
+        let expected_serial = expectations
+            .get(&result.name)
+            .and_then(|expectation| expectation.serial.as_deref());
+        text += &serial_diff(expected_serial, &result.serial);
+    }
+
+    text
+}
+
+pub fn compare(results: &[RomResult], expectations: &HashMap<String, Expectation>) -> Comparison {
+    let mut newly_failing = vec![];
+    let mut newly_passing = vec![];
+    let mut untracked = vec![];
+
+    for result in results {
+        // Roms without an entry are expected to pass, as they are when the roms are run.
+        let expectation = expectations.get(&result.name);
+        match expectation.cloned().unwrap_or_default().outcome {
+            Outcome::Pass if !result.passed() => newly_failing.push(result),
+            Outcome::Fail if result.passed() => newly_passing.push(result),
+            _ => (),
+        }
+        if expectation.is_none() {
+            untracked.push(result);
+        }
+    }
+
+    let mut summary = section("Newly failing", &newly_failing, expectations);
+    summary += &section("Newly passing", &newly_passing, expectations);
+    summary += &section("Not in expectations", &untracked, expectations);
+    if summary.is_empty() {
+        summary = "All roms match their expectations.\n".to_owned();
+    }
+
+    Comparison {
+        summary,
+        has_regressions: !newly_failing.is_empty(),
+    }
+}
+
+// Records the results of this run over the existing expectations, keeping frame budgets and skipped roms.
+pub fn update(results: &[RomResult], expectations: &mut HashMap<String, Expectation>) {
+    for result in results.iter().filter(|result| result.result.is_some()) {
+        let expectation = expectations.entry(result.name.clone()).or_default();
+        expectation.outcome = if result.passed() {
+            Outcome::Pass
+        } else {
+            Outcome::Fail
+        };
+        expectation.serial = Some(result.serial.clone()).filter(|serial| !serial.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn result(name: &str, result: Result<&str, &str>) -> RomResult {
+        RomResult {
+            name: name.to_owned(),
+            expected: Outcome::Pass,
+            result: Some(result.map(str::to_owned).map_err(str::to_owned)),
+            serial: String::new(),
+            frames: 0,
+            audio_hash: None,
+            duration: Duration::ZERO,
+            panicked: false,
+        }
+    }
+
+    fn expectations(entries: &[(&str, Outcome)]) -> HashMap<String, Expectation> {
+        entries
+            .iter()
+            .map(|(name, outcome)| {
+                let expectation = Expectation {
+                    outcome: *outcome,
+                    ..Expectation::default()
+                };
+                (name.to_string(), expectation)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matching() {
+        let expectations = expectations(&[("a.gb", Outcome::Pass), ("b.gb", Outcome::Fail)]);
+        let results = [result("a.gb", Ok("Passed")), result("b.gb", Err("Failed"))];
+
+        let comparison = compare(&results, &expectations);
+        assert!(!comparison.has_regressions);
+        assert_eq!(comparison.summary, "All roms match their expectations.\n");
+    }
+
+    #[test]
+    fn test_regressions() {
+        let expectations = expectations(&[
+            ("a.gb", Outcome::Pass),
+            ("b.gb", Outcome::Fail),
+            ("c.gb", Outcome::Skip),
+        ]);
+        let results = [
+            result("a.gb", Err("Timed out")),
+            result("b.gb", Ok("Passed")),
+            result("c.gb", Err("Failed")),
+        ];
+
+        let comparison = compare(&results, &expectations);
+        assert!(comparison.has_regressions);
+        assert_eq!(
+            comparison.summary,
+            "Newly failing (1):\n  a.gb: Timed out\nNewly passing (1):\n  b.gb: Passed\n"
+        );
+
+        // A newly passing rom on its own isn't a regression.
+        let comparison = compare(&results[1..], &expectations);
+        assert!(!comparison.has_regressions);
+    }
+
+    #[test]
+    fn test_untracked() {
+        // Untracked roms are expected to pass, so one that fails is a regression.
+        let results = [result("new.gb", Ok("Passed"))];
+        let comparison = compare(&results, &HashMap::new());
+        assert!(!comparison.has_regressions);
+        assert_eq!(
+            comparison.summary,
+            "Not in expectations (1):\n  new.gb: Passed\n"
+        );
+
+        let results = [result("new.gb", Err("Failed"))];
+        let comparison = compare(&results, &HashMap::new());
+        assert!(comparison.has_regressions);
+        assert_eq!(
+            comparison.summary,
+            "Newly failing (1):\n  new.gb: Failed\nNot in expectations (1):\n  new.gb: Failed\n"
+        );
+    }
+}
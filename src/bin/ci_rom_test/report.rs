@@ -0,0 +1,221 @@
+// Formatting the results of a test run as HTML, JSON or JUnit XML.
+
+use crate::expectations::Outcome;
+use crate::json::json_string;
+use std::time::Duration;
+
+pub struct RomResult {
+    pub name: String,
+    pub expected: Outcome,
+    // Ok holds a summary of the pass, Err the failure reason. None if the rom was skipped.
+    pub result: Option<Result<String, String>>,
+    pub serial: String,
+    pub frames: u32,
+    // A hash of the audio samples, if any frames were emulated.
+    pub audio_hash: Option<u64>,
+    pub duration: Duration,
+    pub panicked: bool,
+}
+
+impl RomResult {
+    pub fn passed(&self) -> bool {
+        matches!(self.result, Some(Ok(_)))
+    }
+
+    pub fn matches_expectation(&self) -> bool {
+        match self.expected {
+            Outcome::Pass => self.passed(),
+            Outcome::Fail => !self.passed(),
+            Outcome::Skip => true,
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match &self.result {
+            None => "skipped",
+            Some(Ok(_)) => "passed",
+            Some(Err(_)) => "failed",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match &self.result {
+            None => "",
+            Some(Ok(message)) | Some(Err(message)) => message,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    Html,
+    Json,
+    Junit,
+}
+
+pub fn format(results: &[RomResult], format: Format) -> String {
+    match format {
+        Format::Html => html(results),
+        Format::Json => json(results),
+        Format::Junit => junit(results),
+    }
+}
+
+// Escapes text for both HTML and XML.
+fn sanitize_html_text(text: &str) -> String {
+    let text = text.replace('&', "&amp;");
+    let text = text.replace('<', "&lt;");
+    let text = text.replace('>', "&gt;");
+    let text = text.replace('"', "&quot;");
+    text.replace('\'', "&#39;")
+}
+
+// XML 1.0 can't contain most control characters, even escaped, so they're dropped from anything the rom wrote.
+fn strip_xml_invalid(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .collect()
+}
+
+fn html(results: &[RomResult]) -> String {
+    // Open a table in UTF-8 HTML.
+    let mut html =
+        String::from("<html>\n<head>\n<meta charset=\"UTF-8\">\n</head>\n<body>\n<table>\n");
+    html += "<tr>\n<th>Rom</th><th>Result</th><th>Frames</th><th>Seconds</th>\n</tr>\n";
+
+    for result in results {
+        let icon = match (&result.result, result.matches_expectation()) {
+            (None, _) => "⏭️",
+            (Some(Ok(_)), _) => "✅",
+            (Some(Err(_)), true) => "☑️", // An expected failure.
+            (Some(Err(_)), false) => "❌",
+        };
+        let message = sanitize_html_text(&format!("{} {}", icon, result.message()));
+
+        // Add a row to the table.
+        html += &format!(
+            "<tr>\n<td>{}</td><td>{}</td><td>{}</td><td>{:.2}</td>\n</tr>\n",
+            sanitize_html_text(&result.name),
+            message,
+            result.frames,
+            result.duration.as_secs_f64()
+        );
+    }
+
+    // Add all the closing tags.
+    html += "</table>\n</body>\n</html>";
+    html
+}
+
+fn json(results: &[RomResult]) -> String {
+    let roms = results
+        .iter()
+        .map(|result| {
+            let expected = match result.expected {
+                Outcome::Pass => "pass",
+                Outcome::Fail => "fail",
+                Outcome::Skip => "skip",
+            };
+            let failure = match &result.result {
+                Some(Err(reason)) => json_string(reason),
+                _ => "null".to_owned(),
+            };
+            let audio_hash = match result.audio_hash {
+                Some(hash) => format!("\"{:016x}\"", hash),
+                None => "null".to_owned(),
+            };
+
+            format!(
+                "    {{\"name\": {}, \"status\": \"{}\", \"expected\": \"{}\", \"matches_expectation\": {}, \"failure\": {}, \"panicked\": {}, \"frames\": {}, \"duration_secs\": {:.3}, \"serial\": {}, \"audio_hash\": {}}}",
+                json_string(&result.name),
+                result.status(),
+                expected,
+                result.matches_expectation(),
+                failure,
+                result.panicked,
+                result.frames,
+                result.duration.as_secs_f64(),
+                json_string(&result.serial),
+                audio_hash
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+
+    format!("{{\n  \"roms\": [\n{}\n  ]\n}}", roms)
+}
+
+// A testcase only fails when a rom that's expected to pass doesn't, so known failures don't fail the suite.
+fn junit(results: &[RomResult]) -> String {
+    let failures = results
+        .iter()
+        .filter(|r| !r.passed() && !r.matches_expectation())
+        .count();
+    let skipped = results
+        .iter()
+        .filter(|r| !r.passed() && r.matches_expectation())
+        .count();
+    let total_duration: Duration = results.iter().map(|r| r.duration).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += &format!(
+        "<testsuite name=\"ci_rom_test\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        results.len(),
+        failures,
+        skipped,
+        total_duration.as_secs_f64()
+    );
+
+    for result in results {
+        xml += &format!(
+            "  <testcase name=\"{}\" classname=\"ci_rom_test\" time=\"{:.3}\">\n",
+            sanitize_html_text(&result.name),
+            result.duration.as_secs_f64()
+        );
+
+        let message = sanitize_html_text(&strip_xml_invalid(result.message()));
+        match (&result.result, result.matches_expectation()) {
+            (None, _) => xml += "    <skipped/>\n",
+            (Some(Err(_)), false) => {
+                let kind = if result.panicked { "panic" } else { "fail" };
+                xml += &format!("    <failure type=\"{}\" message=\"{}\"/>\n", kind, message)
+            }
+            (Some(Err(_)), true) => {
+                xml += &format!("    <skipped message=\"Expected failure: {}\"/>\n", message)
+            }
+            (Some(Ok(_)), _) => (),
+        }
+
+        xml += &format!(
+            "    <system-out>frames: {}\n{}</system-out>\n  </testcase>\n",
+            result.frames,
+            sanitize_html_text(&strip_xml_invalid(&result.serial))
+        );
+    }
+
+    xml += "</testsuite>\n";
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_junit_control_characters() {
+        let result = RomResult {
+            name: "a.gb".to_owned(),
+            expected: Outcome::Pass,
+            result: Some(Err("Failed\u{7}".to_owned())),
+            serial: "ok\u{0}\u{1b}[0m <done>".to_owned(),
+            frames: 3,
+            audio_hash: None,
+            duration: Duration::ZERO,
+            panicked: false,
+        };
+
+        let xml = junit(&[result]);
+        assert!(xml.contains("<failure type=\"fail\" message=\"Failed\"/>"));
+        assert!(xml.contains("<system-out>frames: 3\nok[0m &lt;done&gt;</system-out>"));
+    }
+}
@@ -0,0 +1,67 @@
+// Comparing frames against reference screenshots, for test roms that are graded by their final image
+// (dmg-acid2, mealybug-tearoom etc.) rather than by serial output.
+
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 144;
+
+// Loads a 160x144 PNG as one grayscale byte per pixel, the default layout of GameBoy::frame.
+pub fn load_reference(path: &Path) -> Result<Vec<u8>, String> {
+    let file = fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+
+    if info.width != WIDTH || info.height != HEIGHT {
+        return Err(format!(
+            "Reference is {}x{}, expected {}x{}",
+            info.width, info.height, WIDTH, HEIGHT
+        ));
+    }
+
+    let samples = info.color_type.samples();
+    let pixels = buffer[..info.buffer_size()].chunks_exact(samples);
+
+    // Colour references are converted with the usual luma weights, which leaves gray pixels unchanged.
+    let gray = pixels.map(|pixel| match info.color_type {
+        png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => pixel[0],
+        _ => {
+            let luma =
+                299 * u32::from(pixel[0]) + 587 * u32::from(pixel[1]) + 114 * u32::from(pixel[2]);
+            (luma / 1000) as u8
+        }
+    });
+
+    Ok(gray.collect())
+}
+
+pub fn count_differences(frame: &[u8], reference: &[u8]) -> usize {
+    frame.iter().zip(reference).filter(|(a, b)| a != b).count()
+}
+
+// Writes an RGB image of the frame, faded, with differing pixels in red.
+pub fn write_diff(path: &Path, frame: &[u8], reference: &[u8]) -> Result<(), String> {
+    let mut rgb = Vec::with_capacity(frame.len() * 3);
+    for (pixel, reference_pixel) in frame.iter().zip(reference) {
+        if pixel == reference_pixel {
+            let faded = 0x80 + pixel / 2;
+            rgb.extend_from_slice(&[faded, faded, faded]);
+        } else {
+            rgb.extend_from_slice(&[0xff, 0x00, 0x00]);
+        }
+    }
+
+    let file = fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&rgb).map_err(|e| e.to_string())
+}
@@ -0,0 +1,31 @@
+// This file produces a binary that loads a rom and waits for a GDB remote serial protocol client
+// (gdb, lldb etc.) to connect and debug it.
+
+use clap::Parser;
+use robin_gb::GameBoy;
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    /// Path to the rom file to debug
+    #[arg(value_name = "ROM_PATH")]
+    rom: PathBuf,
+
+    /// Local TCP port to listen on
+    #[arg(long, default_value_t = 2159)]
+    port: u16,
+}
+
+fn main() {
+    let args = CliArgs::parse();
+
+    let rom_bytes = fs::read(&args.rom).expect("Couldn't read rom file");
+    let mut game_boy = GameBoy::new(&rom_bytes);
+
+    let listener = TcpListener::bind(("127.0.0.1", args.port)).expect("Couldn't bind port");
+    println!("Waiting for a debugger on 127.0.0.1:{}...", args.port);
+
+    robin_gb::gdb::serve(&mut game_boy, &listener).expect("Debugging session failed");
+}
@@ -0,0 +1,434 @@
+// This file produces a binary that runs a rom without a window or GPU, for scripted runs in CI. The rom can be patched first.
+// It can feed in timed button presses or play back an input movie, apply cheats, search RAM, record movies, dump frames as PNG, serial output and audio to files, and prints
+// the final register and memory state as JSON, along with a hash of the audio and any RAM search results.
+
+#[path = "shared/json.rs"]
+mod json;
+
+use clap::Parser;
+use json::json_string;
+use robin_gb::audio::{AudioHasher, WavWriter};
+use robin_gb::cheats;
+use robin_gb::debugger::{Breakpoint, StopReason};
+use robin_gb::movie::Movie;
+use robin_gb::patch;
+use robin_gb::ram_search::{Comparison, RamSearch, ValueKind};
+use robin_gb::{Button, GameBoy, Register};
+use std::fs;
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    /// Path to the rom file to run
+    #[arg(value_name = "ROM_PATH")]
+    rom: PathBuf,
+
+    /// Apply this IPS, UPS or BPS patch to the rom. Can be given more than once, and they're applied in order.
+    #[arg(long = "patch", value_name = "PATH")]
+    patches: Vec<PathBuf>,
+
+    /// Correct the rom's header checksum after patching
+    #[arg(long)]
+    fix_header_checksum: bool,
+
+    /// Maximum number of frames to emulate
+    #[arg(long, default_value_t = 60 * 60)]
+    frames: u32,
+
+    /// Stop once the serial output contains this text
+    #[arg(long, value_name = "TEXT")]
+    until_serial: Option<String>,
+
+    /// Stop when execution reaches this address, in hex
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_hex_u16)]
+    until_pc: Option<u16>,
+
+    /// Input script. Each line is a frame number followed by the buttons held from that frame on,
+    /// e.g. "120 start a". A frame number on its own releases all buttons. '#' starts a comment.
+    #[arg(long, value_name = "PATH")]
+    input: Option<PathBuf>,
+
+    /// Play back this input movie, checking the state after each frame against the recording
+    #[arg(long, value_name = "PATH", conflicts_with = "input")]
+    movie: Option<PathBuf>,
+
+    /// Record the run's input as a movie to this file
+    #[arg(long, value_name = "PATH")]
+    record_movie: Option<PathBuf>,
+
+    /// Apply the cheats in this file. Each line is "on" or "off", a Game Genie or GameShark code, and an
+    /// optional description. '#' starts a comment.
+    #[arg(long, value_name = "PATH")]
+    cheats: Option<PathBuf>,
+
+    /// Start a RAM search before the first frame, reading values as u8, u16, bcd8 or bcd16
+    #[arg(long, value_name = "KIND", value_parser = ValueKind::parse)]
+    search: Option<ValueKind>,
+
+    /// Filter the RAM search once this many frames have run, e.g. "120:decreased" or "300:equal-to=3".
+    /// The comparisons are equal, changed, increased, decreased and equal-to. Can be given more than once.
+    #[arg(long = "search-filter", value_name = "FRAME:COMPARISON", value_parser = parse_search_filter, requires = "search")]
+    search_filters: Vec<(u32, Comparison)>,
+
+    /// Save this frame as a PNG. Can be given more than once. Frames are numbered from 0.
+    #[arg(long = "dump-frame", value_name = "FRAME")]
+    dump_frames: Vec<u32>,
+
+    /// Directory that dumped frames are saved in
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    dump_dir: PathBuf,
+
+    /// Write the raw serial output to this file
+    #[arg(long, value_name = "PATH")]
+    serial: Option<PathBuf>,
+
+    /// Record the audio to this file as 16-bit stereo WAV. There's no APU yet, so it's silence for now.
+    #[arg(long, value_name = "PATH")]
+    wav: Option<PathBuf>,
+
+    /// Audio sample rate, in Hz. The audio hash in the output depends on it, though the audio is silence for now.
+    #[arg(long, default_value_t = 48_000)]
+    sample_rate: u32,
+
+    /// Include this memory range in the final state, e.g. "c000-c0ff". Can be given more than once.
+    #[arg(long = "memory", value_name = "RANGE", value_parser = parse_hex_range)]
+    memory_ranges: Vec<RangeInclusive<u16>>,
+}
+
+const ALL_BUTTONS: [Button; 8] = [
+    Button::A,
+    Button::B,
+    Button::START,
+    Button::SELECT,
+    Button::UP,
+    Button::DOWN,
+    Button::LEFT,
+    Button::RIGHT,
+];
+
+fn parse_hex_u16(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|e| format!("'{}': {}", text, e))
+}
+
+fn parse_hex_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = text
+        .split_once('-')
+        .ok_or(format!("Expected START-END, got '{}'", text))?;
+    Ok(parse_hex_u16(start)?..=parse_hex_u16(end)?)
+}
+
+fn parse_search_filter(text: &str) -> Result<(u32, Comparison), String> {
+    let (frame, comparison) = text
+        .split_once(':')
+        .ok_or(format!("Expected FRAME:COMPARISON, got '{}'", text))?;
+    let frame = frame
+        .parse::<u32>()
+        .map_err(|e| format!("'{}': {}", frame, e))?;
+    Ok((frame, Comparison::parse(comparison)?))
+}
+
+fn parse_button(name: &str) -> Result<Button, String> {
+    match name.to_lowercase().as_str() {
+        "a" => Ok(Button::A),
+        "b" => Ok(Button::B),
+        "start" => Ok(Button::START),
+        "select" => Ok(Button::SELECT),
+        "up" => Ok(Button::UP),
+        "down" => Ok(Button::DOWN),
+        "left" => Ok(Button::LEFT),
+        "right" => Ok(Button::RIGHT),
+        _ => Err(format!("Unknown button '{}'", name)),
+    }
+}
+
+// Returns (frame, held buttons) pairs, sorted by frame.
+fn parse_input_script(text: &str) -> Result<Vec<(u32, Vec<Button>)>, String> {
+    let mut script = vec![];
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let frame = words.next().unwrap();
+        let frame = frame
+            .parse::<u32>()
+            .map_err(|e| format!("Line {}: '{}': {}", line_index + 1, frame, e))?;
+        let buttons = words
+            .map(parse_button)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Line {}: {}", line_index + 1, e))?;
+
+        script.push((frame, buttons));
+    }
+
+    script.sort_by_key(|(frame, _)| *frame);
+    Ok(script)
+}
+
+fn save_png(path: &Path, frame: &[u8]) -> Result<(), String> {
+    let file = fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), 160, 144);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(frame).map_err(|e| e.to_string())
+}
+
+fn state_json(
+    game_boy: &GameBoy,
+    frames: u32,
+    stop: &str,
+    memory_ranges: &[RangeInclusive<u16>],
+    audio_hash: u64,
+    ram_search: Option<&RamSearch>,
+) -> String {
+    // The first frame that didn't match the movie, or null if it all matched or no movie was played.
+    let movie_desync = game_boy
+        .movie_playback_status()
+        .and_then(|status| status.desync_frame)
+        .map_or("null".to_owned(), |frame| frame.to_string());
+
+    const REGISTERS: [(&str, Register); 10] = [
+        ("a", Register::A),
+        ("f", Register::F),
+        ("b", Register::B),
+        ("c", Register::C),
+        ("d", Register::D),
+        ("e", Register::E),
+        ("h", Register::H),
+        ("l", Register::L),
+        ("sp", Register::SP),
+        ("pc", Register::PC),
+    ];
+
+    let registers = REGISTERS
+        .iter()
+        .map(|(name, register)| format!("\"{}\": {}", name, game_boy.register(*register)))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    // Each range is a hex string of its bytes, keyed by the range itself.
+    let memory = memory_ranges
+        .iter()
+        .map(|range| {
+            let bytes: String = range
+                .clone()
+                .map(|address| format!("{:02x}", game_boy.peek(address)))
+                .collect();
+            format!(
+                "\"{:04x}-{:04x}\": \"{}\"",
+                range.start(),
+                range.end(),
+                bytes
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let serial = game_boy.serial_buffer().as_deref().unwrap_or_default();
+    let serial = String::from_utf8_lossy(serial);
+
+    // The surviving candidates, up to a limit, as the full list can be most of RAM.
+    const MAX_SEARCH_RESULTS: usize = 256;
+    let ram_search = ram_search.map_or("null".to_owned(), |search| {
+        let results = search
+            .results()
+            .iter()
+            .take(MAX_SEARCH_RESULTS)
+            .map(|result| {
+                format!(
+                    "{{\"address\": \"{:04x}\", \"bank\": {}, \"value\": {}}}",
+                    result.address, result.bank, result.value
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{{\"candidates\": {}, \"results\": [{}]}}",
+            search.candidate_count(),
+            results
+        )
+    });
+
+    format!(
+        "{{\n  \"frames\": {},\n  \"stop\": {},\n  \"registers\": {{{}}},\n  \"memory\": {{{}}},\n  \"serial\": {},\n  \"audio_hash\": \"{:016x}\",\n  \"movie_desync\": {},\n  \"ram_search\": {}\n}}",
+        frames,
+        json_string(stop),
+        registers,
+        memory,
+        json_string(&serial),
+        audio_hash,
+        movie_desync,
+        ram_search
+    )
+}
+
+fn run(args: CliArgs) -> Result<String, String> {
+    let mut rom_bytes = fs::read(&args.rom).map_err(|e| e.to_string())?;
+    for path in &args.patches {
+        let patch_bytes = fs::read(path).map_err(|e| e.to_string())?;
+        rom_bytes = patch::apply(&rom_bytes, &patch_bytes)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    if args.fix_header_checksum {
+        patch::fix_header_checksum(&mut rom_bytes);
+    }
+    let mut game_boy = GameBoy::new(&rom_bytes);
+    game_boy.record_serial_output(true);
+    game_boy.record_audio(Some(args.sample_rate));
+
+    let mut wav = match &args.wav {
+        Some(path) => {
+            let file = fs::File::create(path).map_err(|e| e.to_string())?;
+            Some(
+                WavWriter::new(BufWriter::new(file), args.sample_rate)
+                    .map_err(|e| e.to_string())?,
+            )
+        }
+        None => None,
+    };
+    let mut audio_hasher = AudioHasher::new();
+
+    if let Some(address) = args.until_pc {
+        game_boy.add_breakpoint(Breakpoint {
+            address,
+            bank: None,
+        });
+    }
+
+    if let Some(path) = &args.cheats {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        for cheat in cheats::parse_file(&text)? {
+            game_boy.add_cheat(cheat);
+        }
+    }
+
+    let script = match &args.input {
+        Some(path) => parse_input_script(&fs::read_to_string(path).map_err(|e| e.to_string())?)?,
+        None => vec![],
+    };
+    let mut script = script.into_iter().peekable();
+
+    if let Some(path) = &args.movie {
+        let movie = Movie::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)?;
+        game_boy.play_movie(movie)?;
+    }
+    if args.record_movie.is_some() {
+        game_boy.start_recording_movie();
+    }
+
+    if !args.dump_frames.is_empty() {
+        fs::create_dir_all(&args.dump_dir).map_err(|e| e.to_string())?;
+    }
+
+    let mut ram_search = args.search.map(|kind| RamSearch::new(&game_boy, kind));
+    let mut search_filters = args.search_filters.clone();
+    search_filters.sort_by_key(|(frame, _)| *frame);
+    let mut search_filters = search_filters.into_iter().peekable();
+
+    let mut frames_emulated = 0;
+    let mut stop = "frame_limit";
+
+    while frames_emulated < args.frames {
+        if let Some(search) = &mut ram_search {
+            while let Some((_, comparison)) =
+                search_filters.next_if(|(frame, _)| *frame <= frames_emulated)
+            {
+                search.filter(&game_boy, comparison);
+            }
+        }
+
+        // Apply every script entry up to this frame; the last one wins.
+        while let Some((_, buttons)) = script.next_if(|(frame, _)| *frame <= frames_emulated) {
+            for button in &ALL_BUTTONS {
+                game_boy.set_button(button, buttons.contains(button));
+            }
+        }
+
+        let reason = game_boy.emulate_next_frame();
+
+        let samples = game_boy.take_audio_samples();
+        audio_hasher.update(&samples);
+        if let Some(wav) = &mut wav {
+            wav.write_samples(&samples).map_err(|e| e.to_string())?;
+        }
+
+        match reason {
+            StopReason::FrameComplete => (),
+            StopReason::Breakpoint(_) => {
+                stop = "pc";
+                break;
+            }
+            // Only --until-pc's breakpoint is set, and whole frames are emulated.
+            StopReason::ScanlineComplete
+            | StopReason::InstructionComplete
+            | StopReason::Watchpoint(_)
+            | StopReason::Condition(_)
+            | StopReason::SoftwareBreakpoint => {
+                panic!("Unexpected stop: {:?}", reason)
+            }
+        }
+
+        if args.dump_frames.contains(&frames_emulated) {
+            let path = args.dump_dir.join(format!("frame_{}.png", frames_emulated));
+            save_png(&path, game_boy.frame())?;
+        }
+
+        frames_emulated += 1;
+
+        if let (Some(text), Some(serial)) = (&args.until_serial, game_boy.serial_buffer()) {
+            if String::from_utf8_lossy(serial).contains(text.as_str()) {
+                stop = "serial";
+                break;
+            }
+        }
+    }
+
+    // Filters for frames after the run stopped are applied to the final state.
+    if let Some(search) = &mut ram_search {
+        for (_, comparison) in search_filters {
+            search.filter(&game_boy, comparison);
+        }
+    }
+
+    if let Some(path) = &args.serial {
+        let serial = game_boy.serial_buffer().as_deref().unwrap_or_default();
+        fs::write(path, serial).map_err(|e| e.to_string())?;
+    }
+
+    if let Some(wav) = wav {
+        wav.finish().map_err(|e| e.to_string())?;
+    }
+
+    if let (Some(path), Some(movie)) = (&args.record_movie, game_boy.stop_recording_movie()) {
+        fs::write(path, movie.write()).map_err(|e| e.to_string())?;
+    }
+
+    Ok(state_json(
+        &game_boy,
+        frames_emulated,
+        stop,
+        &args.memory_ranges,
+        audio_hasher.finish(),
+        ram_search.as_ref(),
+    ))
+}
+
+fn main() {
+    let args = CliArgs::parse();
+
+    match run(args) {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
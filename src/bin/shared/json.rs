@@ -0,0 +1,30 @@
+// JSON output helpers shared by the headless and ci_rom_test binaries, which each include this file with
+// #[path]. It isn't a binary of its own, as the directory has no main.rs.
+
+// Quotes and escapes text as a JSON string.
+pub fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            '\n' => json += "\\n",
+            c if (c as u32) < 0x20 => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json + "\""
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(
+            json_string("a\"b\\c\nd\x01é"),
+            "\"a\\\"b\\\\c\\nd\\u0001é\""
+        );
+    }
+}
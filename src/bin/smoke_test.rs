@@ -1,12 +1,12 @@
 // This file produces a binary that loads multiple roms and emulates them simultaneously,
 // rendering them in a grid using wgpu.
 
-use bytemuck;
+use clap::Parser;
 use glam::f32::Mat4;
-use robin_gb::GameBoy;
+use robin_gb::{patch, GameBoy};
 use std::fs;
+use std::path::PathBuf;
 use std::sync::Arc;
-use wgpu;
 use winit::{
     application::ApplicationHandler,
     dpi::LogicalSize,
@@ -17,6 +17,14 @@ use winit::{
 
 // rwtodo: Put this and winit/wgpu behind a feature, as I don't want users of the robin_gb library to have to download them.
 
+#[derive(Parser, Debug)]
+struct CliArgs {
+    /// Apply this IPS, UPS or BPS patch to every rom. UPS and BPS patches are checked against each rom, and roms
+    /// they aren't for are run unpatched. IPS patches can't be checked, so they're applied to every rom.
+    #[arg(long, value_name = "PATH")]
+    patch: Option<PathBuf>,
+}
+
 const GAME_BOYS_PER_COLUMN: u32 = 4;
 const GAME_BOYS_PER_ROW: u32 = 4;
 const WINDOW_WIDTH: u32 = 160 * GAME_BOYS_PER_COLUMN;
@@ -300,6 +308,7 @@ struct App<'a> {
     state: Option<GpuState<'a>>,
     game_boys: Vec<GameBoy>,
     tile_transforms: Vec<Mat4>,
+    patch: Option<Vec<u8>>,
 }
 
 impl ApplicationHandler for App<'_> {
@@ -341,8 +350,14 @@ impl ApplicationHandler for App<'_> {
                     continue;
                 }
 
-                let bytes = fs::read(path.path()).unwrap();
+                let mut bytes = fs::read(path.path()).unwrap();
                 println!("{}", path.path().display());
+                if let Some(patch_bytes) = &self.patch {
+                    match patch::apply(&bytes, patch_bytes) {
+                        Ok(patched) => bytes = patched,
+                        Err(e) => println!("Couldn't apply the patch, so running unpatched: {}", e),
+                    }
+                }
                 roms.push(bytes);
                 if roms.len() == (GAME_BOYS_PER_ROW * GAME_BOYS_PER_COLUMN) as usize {
                     break;
@@ -352,7 +367,10 @@ impl ApplicationHandler for App<'_> {
         };
 
         // Boot up the game boys.
-        self.game_boys = roms.iter().map(|rom| GameBoy::new(&rom)).collect();
+        self.game_boys = roms
+            .into_iter()
+            .map(|rom| GameBoy::with_shared_rom(rom.into()))
+            .collect();
 
         let fullscreen_transform = {
             let mut m = Mat4::IDENTITY;
@@ -385,10 +403,10 @@ impl ApplicationHandler for App<'_> {
             WindowEvent::CloseRequested => event_loop.exit(),
             WindowEvent::RedrawRequested => {
                 let surface_texture = state.begin_render();
-                let mut screen: [u8; 160 * 144] = [0; 160 * 144];
                 for i in 0..self.game_boys.len() {
-                    self.game_boys[i].emulate_next_frame(&mut screen);
-                    state.render_gb_screen(&surface_texture, &screen, self.tile_transforms[i]);
+                    self.game_boys[i].emulate_next_frame();
+                    let screen = self.game_boys[i].frame();
+                    state.render_gb_screen(&surface_texture, screen, self.tile_transforms[i]);
                 }
 
                 state.finish_render(surface_texture);
@@ -399,10 +417,17 @@ impl ApplicationHandler for App<'_> {
 }
 
 fn main() {
+    let args = CliArgs::parse();
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
     // Run the emulations and render to the grid.
-    let mut app = App::default();
+    let mut app = App {
+        patch: args
+            .patch
+            .map(|path| fs::read(path).expect("Couldn't read patch file")),
+        ..Default::default()
+    };
     let _ = event_loop.run_app(&mut app);
 }
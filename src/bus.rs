@@ -0,0 +1,87 @@
+use crate::{address, make_u16};
+
+/// Everything an SM83 CPU is connected to: memory, cartridge and I/O registers, including the interrupt
+/// flags (0xff0f) and interrupt enable (0xffff) registers. Memory is the Game Boy's implementation.
+///
+/// read takes &self because the CPU reads operands while holding other borrows. Buses that need to
+/// record reads can use interior mutability, as Memory does for watchpoints.
+pub trait Bus {
+    fn read(&self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Called after every instruction with the number of cycles (at 4.19 MHz) that it took.
+    fn tick(&mut self, _cycles: u8) {}
+
+    /// The interrupt flags register, as polled by the CPU after every instruction. Polling isn't an access by the
+    /// program, so buses that record accesses should override this and the two below to not record it.
+    fn interrupt_flags(&self) -> u8 {
+        self.read(address::INTERRUPT_FLAGS)
+    }
+
+    fn set_interrupt_flags(&mut self, value: u8) {
+        self.write(address::INTERRUPT_FLAGS, value)
+    }
+
+    fn interrupt_enable(&self) -> u8 {
+        self.read(address::INTERRUPT_ENABLE)
+    }
+
+    /// Little-endian.
+    fn read_u16(&self, address: u16) -> u16 {
+        make_u16(self.read(address), self.read(address.wrapping_add(1)))
+    }
+
+    /// Little-endian.
+    fn write_u16(&mut self, address: u16, value: u16) {
+        let bytes = value.to_le_bytes();
+        self.write(address, bytes[0]);
+        self.write(address.wrapping_add(1), bytes[1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, Register};
+
+    // 64kB of RAM that records the cycles it's ticked with.
+    struct RecordingBus {
+        bytes: Vec<u8>,
+        ticks: Vec<u8>,
+    }
+
+    impl Bus for RecordingBus {
+        fn read(&self, address: u16) -> u8 {
+            self.bytes[usize::from(address)]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.bytes[usize::from(address)] = value;
+        }
+
+        fn tick(&mut self, cycles: u8) {
+            self.ticks.push(cycles);
+        }
+    }
+
+    #[test]
+    fn test_cpu_on_custom_bus() {
+        let mut bus = RecordingBus {
+            bytes: vec![0; 0x10000],
+            ticks: vec![],
+        };
+
+        // NOP; LD A,0x42; LD (0x1234),A
+        bus.bytes[0x0100..0x0106].copy_from_slice(&[0x00, 0x3e, 0x42, 0xea, 0x34, 0x12]);
+
+        let mut cpu = Cpu::new();
+        for _ in 0..3 {
+            let _ = cpu.execute_next_instruction(&mut bus);
+        }
+
+        assert_eq!(cpu.registers().get(Register::PC), 0x0106);
+        assert_eq!(bus.bytes[0x1234], 0x42);
+        assert_eq!(bus.ticks, [4, 8, 16]);
+    }
+}
@@ -0,0 +1,434 @@
+// A C API, for embedding the emulator in C and C++ programs, built as a static library with
+// `cargo rustc --lib --crate-type staticlib --features capi`, or as a cdylib the same way. The header is
+// include/robin_gb.h, generated from this file by cbindgen (see cbindgen.toml), and examples/capi/example.c
+// shows it in use.
+//
+// Every function takes the robin_gb_t* from robin_gb_create(), and does nothing if it's null. Sizes are in
+// bytes, except for audio, which is counted in samples. Panics can't unwind into C, so they're caught, and the
+// Game Boy is marked as crashed (see robin_gb_is_crashed()).
+
+use crate::{Button, GameBoy};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+pub const ROBIN_GB_BUTTON_A: u32 = 0;
+pub const ROBIN_GB_BUTTON_B: u32 = 1;
+pub const ROBIN_GB_BUTTON_START: u32 = 2;
+pub const ROBIN_GB_BUTTON_SELECT: u32 = 3;
+pub const ROBIN_GB_BUTTON_UP: u32 = 4;
+pub const ROBIN_GB_BUTTON_DOWN: u32 = 5;
+pub const ROBIN_GB_BUTTON_LEFT: u32 = 6;
+pub const ROBIN_GB_BUTTON_RIGHT: u32 = 7;
+
+// Cartridge RAM, which battery-backed carts keep their saves in.
+// rwtodo: this is only the first bank, until RAM banking is emulated.
+const SRAM_START: u16 = 0xa000;
+const SRAM_SIZE: usize = 0x2000;
+
+/// An emulated Game Boy. Opaque to C.
+pub struct RobinGb {
+    game_boy: GameBoy,
+    // Samples taken from the GameBoy but not drained yet.
+    audio: Vec<i16>,
+    // Set when emulation panics, as the GameBoy could be part way through an instruction.
+    crashed: bool,
+}
+
+// Copies as much of source into the caller's buffer as fits, returning how much that was.
+unsafe fn copy_out<T: Copy>(source: &[T], buffer: *mut T, buffer_size: usize) -> usize {
+    if buffer.is_null() {
+        return 0;
+    }
+    let count = source.len().min(buffer_size);
+    slice::from_raw_parts_mut(buffer, count).copy_from_slice(&source[..count]);
+    count
+}
+
+/// Creates a Game Boy running a copy of the ROM. Returns null if the ROM couldn't be loaded.
+///
+/// # Safety
+/// rom must point to rom_size readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_create(rom: *const u8, rom_size: usize) -> *mut RobinGb {
+    if rom.is_null() {
+        return std::ptr::null_mut();
+    }
+    let rom = slice::from_raw_parts(rom, rom_size);
+
+    // Unsupported or truncated ROMs panic while loading.
+    match panic::catch_unwind(AssertUnwindSafe(|| GameBoy::new(rom))) {
+        Ok(game_boy) => Box::into_raw(Box::new(RobinGb {
+            game_boy,
+            audio: vec![],
+            crashed: false,
+        })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// gb must come from robin_gb_create(), and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_destroy(gb: *mut RobinGb) {
+    if !gb.is_null() {
+        drop(Box::from_raw(gb));
+    }
+}
+
+/// The size of a frame: 160x144 pixels, one grayscale byte each.
+///
+/// # Safety
+/// gb must come from robin_gb_create().
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_frame_size(gb: *const RobinGb) -> usize {
+    gb.as_ref().map_or(0, |gb| gb.game_boy.frame().len())
+}
+
+/// Emulates up to the next V-blank, and copies the completed frame into buffer. Returns the number of bytes
+/// copied, which is less than a whole frame if buffer_size is too small. Returns 0 without emulating anything
+/// if the Game Boy has crashed.
+///
+/// # Safety
+/// gb must come from robin_gb_create(), and buffer must point to buffer_size writable bytes or be null.
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_run_frame(
+    gb: *mut RobinGb,
+    buffer: *mut u8,
+    buffer_size: usize,
+) -> usize {
+    let Some(gb) = gb.as_mut() else {
+        return 0;
+    };
+    if gb.crashed {
+        return 0;
+    }
+
+    // Invalid opcodes and unemulated hardware panic.
+    let game_boy = &mut gb.game_boy;
+    if panic::catch_unwind(AssertUnwindSafe(|| game_boy.emulate_next_frame())).is_err() {
+        gb.crashed = true;
+        return 0;
+    }
+    copy_out(gb.game_boy.frame(), buffer, buffer_size)
+}
+
+/// Returns true once emulation has panicked, for example on an invalid opcode. A crashed Game Boy doesn't
+/// emulate any more, so it should be destroyed, and another one created to start again.
+///
+/// # Safety
+/// gb must come from robin_gb_create().
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_is_crashed(gb: *const RobinGb) -> bool {
+    gb.as_ref().is_some_and(|gb| gb.crashed)
+}
+
+/// button is one of the ROBIN_GB_BUTTON_ constants. Returns false if it isn't.
+///
+/// # Safety
+/// gb must come from robin_gb_create().
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_set_button(gb: *mut RobinGb, button: u32, is_down: bool) -> bool {
+    let button = match button {
+        ROBIN_GB_BUTTON_A => Button::A,
+        ROBIN_GB_BUTTON_B => Button::B,
+        ROBIN_GB_BUTTON_START => Button::START,
+        ROBIN_GB_BUTTON_SELECT => Button::SELECT,
+        ROBIN_GB_BUTTON_UP => Button::UP,
+        ROBIN_GB_BUTTON_DOWN => Button::DOWN,
+        ROBIN_GB_BUTTON_LEFT => Button::LEFT,
+        ROBIN_GB_BUTTON_RIGHT => Button::RIGHT,
+        _ => return false,
+    };
+    let Some(gb) = gb.as_mut() else {
+        return false;
+    };
+    gb.game_boy.set_button(&button, is_down);
+    true
+}
+
+/// Start producing interleaved stereo samples at this rate, or stop if it's 0. Undrained samples are discarded.
+///
+/// # Safety
+/// gb must come from robin_gb_create().
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_set_audio_rate(gb: *mut RobinGb, sample_rate: u32) {
+    if let Some(gb) = gb.as_mut() {
+        gb.game_boy
+            .record_audio((sample_rate != 0).then_some(sample_rate));
+        gb.audio.clear();
+    }
+}
+
+/// Moves up to max_samples of the samples produced so far into samples, oldest first, and returns how many
+/// were moved. The rest stay for the next call.
+///
+/// # Safety
+/// gb must come from robin_gb_create(), and samples must point to max_samples writable samples or be null.
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_drain_audio(
+    gb: *mut RobinGb,
+    samples: *mut i16,
+    max_samples: usize,
+) -> usize {
+    let Some(gb) = gb.as_mut() else {
+        return 0;
+    };
+    let new_samples = gb.game_boy.take_audio_samples();
+    gb.audio.extend(new_samples);
+
+    let count = copy_out(&gb.audio, samples, max_samples);
+    gb.audio.drain(..count);
+    count
+}
+
+/// The size of the cartridge RAM that robin_gb_save_sram() writes.
+///
+/// # Safety
+/// gb must come from robin_gb_create().
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_sram_size(gb: *const RobinGb) -> usize {
+    if gb.is_null() {
+        0
+    } else {
+        SRAM_SIZE
+    }
+}
+
+/// Copies the cartridge RAM into buffer, returning the number of bytes copied.
+///
+/// # Safety
+/// gb must come from robin_gb_create(), and buffer must point to buffer_size writable bytes or be null.
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_save_sram(
+    gb: *const RobinGb,
+    buffer: *mut u8,
+    buffer_size: usize,
+) -> usize {
+    let Some(gb) = gb.as_ref() else {
+        return 0;
+    };
+    let sram: Vec<u8> = (0..SRAM_SIZE as u16)
+        .map(|offset| gb.game_boy.peek(SRAM_START + offset))
+        .collect();
+    copy_out(&sram, buffer, buffer_size)
+}
+
+/// Replaces the cartridge RAM. Returns false if sram_size isn't robin_gb_sram_size().
+///
+/// # Safety
+/// gb must come from robin_gb_create(), and sram must point to sram_size readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_load_sram(
+    gb: *mut RobinGb,
+    sram: *const u8,
+    sram_size: usize,
+) -> bool {
+    let Some(gb) = gb.as_mut() else {
+        return false;
+    };
+    if sram.is_null() || sram_size != SRAM_SIZE {
+        return false;
+    }
+    for (offset, byte) in slice::from_raw_parts(sram, sram_size).iter().enumerate() {
+        gb.game_boy.poke(SRAM_START + offset as u16, *byte);
+    }
+    true
+}
+
+/// The size of the buffer robin_gb_save_state() needs.
+///
+/// # Safety
+/// gb must come from robin_gb_create().
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_state_size(gb: *const RobinGb) -> usize {
+    gb.as_ref().map_or(0, |gb| gb.game_boy.save_state().len())
+}
+
+/// Saves the whole emulator state into buffer, returning its size, or 0 if buffer is too small. States can
+/// only be loaded by the same build of the library.
+///
+/// # Safety
+/// gb must come from robin_gb_create(), and buffer must point to buffer_size writable bytes or be null.
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_save_state(
+    gb: *const RobinGb,
+    buffer: *mut u8,
+    buffer_size: usize,
+) -> usize {
+    let Some(gb) = gb.as_ref() else {
+        return 0;
+    };
+    let state = gb.game_boy.save_state();
+    if buffer_size < state.len() {
+        return 0;
+    }
+    copy_out(&state, buffer, buffer_size)
+}
+
+/// Returns false if the state doesn't fit this Game Boy, in which case nothing is loaded, or if loading it
+/// panicked, in which case the Game Boy has crashed.
+///
+/// # Safety
+/// gb must come from robin_gb_create(), and state must point to state_size readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn robin_gb_load_state(
+    gb: *mut RobinGb,
+    state: *const u8,
+    state_size: usize,
+) -> bool {
+    let Some(gb) = gb.as_mut() else {
+        return false;
+    };
+    if state.is_null() {
+        return false;
+    }
+    let state = slice::from_raw_parts(state, state_size);
+    if gb.game_boy.check_state(state).is_err() {
+        return false;
+    }
+
+    // A state from another build can still fail part way through loading.
+    let game_boy = &mut gb.game_boy;
+    if panic::catch_unwind(AssertUnwindSafe(|| game_boy.load_state(state))).is_err() {
+        gb.crashed = true;
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::process::Command;
+    use std::ptr;
+
+    // LD HL,0xc000; loop: INC (HL); JR loop
+    const PROGRAM: [u8; 6] = [0x21, 0x00, 0xc0, 0x34, 0x18, 0xfd];
+
+    #[test]
+    fn test_api() {
+        let rom = crate::test_rom(&PROGRAM);
+        unsafe {
+            assert!(robin_gb_create(rom.as_ptr(), 100).is_null());
+
+            let gb = robin_gb_create(rom.as_ptr(), rom.len());
+            assert!(!gb.is_null());
+            robin_gb_set_audio_rate(gb, 48_000);
+
+            let mut frame = vec![0; robin_gb_frame_size(gb)];
+            assert_eq!(frame.len(), 160 * 144);
+            assert_eq!(
+                robin_gb_run_frame(gb, frame.as_mut_ptr(), frame.len()),
+                frame.len()
+            );
+            assert_eq!(robin_gb_run_frame(gb, ptr::null_mut(), 0), 0);
+
+            assert!(robin_gb_set_button(gb, ROBIN_GB_BUTTON_START, true));
+            assert!(!robin_gb_set_button(gb, 8, true));
+
+            // A stereo sample for every 48kHz tick emulated, drained in 2 parts.
+            let mut samples = vec![1; 10_000];
+            let first = robin_gb_drain_audio(gb, samples.as_mut_ptr(), 1000);
+            let second = robin_gb_drain_audio(gb, samples.as_mut_ptr(), samples.len());
+            assert_eq!(first, 1000);
+            assert_eq!(
+                (first + second) as u64,
+                2 * (*gb).game_boy.cycles * 48_000 / u64::from(crate::audio::CLOCK_RATE)
+            );
+            assert_eq!(
+                robin_gb_drain_audio(gb, samples.as_mut_ptr(), samples.len()),
+                0
+            );
+
+            let mut sram = vec![0x5a; robin_gb_sram_size(gb)];
+            assert!(robin_gb_load_sram(gb, sram.as_ptr(), sram.len()));
+            sram.fill(0);
+            assert_eq!(
+                robin_gb_save_sram(gb, sram.as_mut_ptr(), sram.len()),
+                0x2000
+            );
+            assert!(sram.iter().all(|byte| *byte == 0x5a));
+
+            let mut state = vec![0; robin_gb_state_size(gb)];
+            assert_eq!(robin_gb_save_state(gb, state.as_mut_ptr(), 10), 0);
+            assert_eq!(
+                robin_gb_save_state(gb, state.as_mut_ptr(), state.len()),
+                state.len()
+            );
+            let counter = (*gb).game_boy.peek(0xc000);
+            robin_gb_run_frame(gb, ptr::null_mut(), 0);
+            assert!(robin_gb_load_state(gb, state.as_ptr(), state.len()));
+            assert_eq!((*gb).game_boy.peek(0xc000), counter);
+            assert!(!robin_gb_load_state(gb, state.as_ptr(), 10));
+
+            robin_gb_destroy(gb);
+            robin_gb_destroy(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_crash() {
+        // An invalid opcode.
+        let rom = crate::test_rom(&[0xd3]);
+        unsafe {
+            let gb = robin_gb_create(rom.as_ptr(), rom.len());
+            let mut frame = vec![0; robin_gb_frame_size(gb)];
+            assert!(!robin_gb_is_crashed(gb));
+            assert_eq!(robin_gb_run_frame(gb, frame.as_mut_ptr(), frame.len()), 0);
+            assert!(robin_gb_is_crashed(gb));
+            assert_eq!(robin_gb_run_frame(gb, frame.as_mut_ptr(), frame.len()), 0);
+            robin_gb_destroy(gb);
+            assert!(!robin_gb_is_crashed(ptr::null()));
+        }
+    }
+
+    #[test]
+    fn test_c_example() {
+        // cargo test doesn't build the static library, so build it into the same directory as this test's
+        // binary, one level up from deps/.
+        let test_binary = std::env::current_exe().unwrap();
+        let target_dir = test_binary.parent().unwrap().parent().unwrap();
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut cargo = Command::new(env!("CARGO"));
+        cargo
+            .args([
+                "rustc",
+                "--lib",
+                "--crate-type",
+                "staticlib",
+                "--features",
+                "capi",
+            ])
+            .arg("--manifest-path")
+            .arg(manifest_dir.join("Cargo.toml"))
+            .arg("--target-dir")
+            .arg(target_dir.parent().unwrap());
+        if target_dir.ends_with("release") {
+            cargo.arg("--release");
+        }
+        assert!(cargo.status().expect("Couldn't run cargo").success());
+        let library = target_dir.join("librobin_gb.a");
+
+        let example = target_dir.join("capi_example");
+        let status = Command::new("cc")
+            .arg(manifest_dir.join("examples/capi/example.c"))
+            .arg("-I")
+            .arg(manifest_dir.join("include"))
+            .arg(&library)
+            .args(["-lpthread", "-ldl", "-lm", "-o"])
+            .arg(&example)
+            .status()
+            .expect("Couldn't run cc");
+        assert!(status.success());
+
+        let rom_path = target_dir.join("capi_example.gb");
+        std::fs::write(&rom_path, crate::test_rom(&PROGRAM)).unwrap();
+        let output = Command::new(&example).arg(&rom_path).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Ran 60 frames\nReplayed a frame from a saved state\n"
+        );
+    }
+}
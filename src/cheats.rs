@@ -0,0 +1,301 @@
+// Game Genie and GameShark cheat codes.
+//
+// Game Genie codes patch ROM. They're 9 hex digits, ABC-DEF-GHI, where AB is the new value, FCDE XORed with
+// 0xf000 is the address, and GI is the value being replaced, XORed with 0xba and rotated left by 2. The patch
+// only applies while the ROM holds that value, which is how a code picks out one ROM bank. The 6 digit form,
+// ABC-DEF, has no compare value and patches every bank.
+//
+// GameShark codes write to RAM once per frame, on entering V-blank. They're 8 hex digits, TTVVLLHH: the type,
+// the value, and the address, low byte first. Type 0x01 (or 0x00) writes to whatever is mapped at the address,
+// and 0x8n only writes while external RAM bank n is.
+//
+// Cheat files are text. '#' starts a comment, and each line is
+//
+//     <on|off> <code> <description>
+//
+// where the description is optional and runs to the end of the line.
+
+/// What a cheat code does once decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatEffect {
+    /// Reads of the ROM address return value instead, if the ROM holds compare there (or always, if None).
+    RomPatch {
+        address: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    /// Write value to the address every frame, if bank is None or that external RAM bank is mapped.
+    RamWrite {
+        bank: Option<u8>,
+        address: u16,
+        value: u8,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    /// The code in its normalised form: upper case, with Game Genie codes split by dashes.
+    pub code: String,
+    pub description: String,
+    pub enabled: bool,
+    pub effect: CheatEffect,
+}
+
+/// Upper case with whitespace and dashes removed, which is how codes are compared.
+pub fn normalise_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn decode_game_genie(digits: &[u8]) -> Result<CheatEffect, String> {
+    let [a, b, c, d, e, f] = digits[..6] else {
+        unreachable!()
+    };
+
+    let address =
+        (u16::from(f ^ 0xf) << 12) | (u16::from(c) << 8) | (u16::from(d) << 4) | u16::from(e);
+    if address >= 0x8000 {
+        return Err(format!(
+            "Game Genie code patches {:04x}, which isn't ROM",
+            address
+        ));
+    }
+
+    // digits[7] is unused, and is probably a checksum.
+    let compare = match digits.get(6..9) {
+        Some(&[g, _, i]) => Some(((g << 4) | i).rotate_right(2) ^ 0xba),
+        _ => None,
+    };
+
+    Ok(CheatEffect::RomPatch {
+        address,
+        value: (a << 4) | b,
+        compare,
+    })
+}
+
+fn decode_gameshark(digits: &[u8]) -> Result<CheatEffect, String> {
+    let byte = |index: usize| (digits[index] << 4) | digits[index + 1];
+
+    let bank = match byte(0) {
+        0x00 | 0x01 => None,
+        kind @ 0x80..=0x8f => Some(kind & 0x0f),
+        kind => return Err(format!("Unsupported GameShark code type {:02x}", kind)),
+    };
+
+    let address = u16::from_le_bytes([byte(4), byte(6)]);
+    if address < 0x8000 {
+        return Err(format!(
+            "GameShark code writes to {:04x}, which isn't RAM",
+            address
+        ));
+    }
+    if bank.is_some() && !(0xa000..=0xbfff).contains(&address) {
+        return Err(format!(
+            "GameShark code selects a RAM bank, but {:04x} isn't external RAM",
+            address
+        ));
+    }
+
+    Ok(CheatEffect::RamWrite {
+        bank,
+        address,
+        value: byte(2),
+    })
+}
+
+impl Cheat {
+    /// Decodes a Game Genie or GameShark code, telling them apart by length. The cheat starts enabled.
+    pub fn new(code: &str, description: &str) -> Result<Cheat, String> {
+        let normalised = normalise_code(code);
+        let digits = normalised
+            .chars()
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(format!("'{}' isn't a hex code", code))?;
+
+        let (code, effect) = match digits.len() {
+            6 => (
+                format!("{}-{}", &normalised[..3], &normalised[3..]),
+                decode_game_genie(&digits)?,
+            ),
+            9 => (
+                format!(
+                    "{}-{}-{}",
+                    &normalised[..3],
+                    &normalised[3..6],
+                    &normalised[6..]
+                ),
+                decode_game_genie(&digits)?,
+            ),
+            8 => (normalised, decode_gameshark(&digits)?),
+            _ => {
+                return Err(format!(
+                    "'{}' is neither a Game Genie nor a GameShark code",
+                    code
+                ))
+            }
+        };
+
+        Ok(Cheat {
+            code,
+            description: description.to_owned(),
+            enabled: true,
+            effect,
+        })
+    }
+}
+
+// The value a read of ROM returns once enabled Game Genie codes are applied.
+pub(crate) fn patch_rom_read(cheats: &[Cheat], address: u16, rom_value: u8) -> u8 {
+    for cheat in cheats.iter().filter(|cheat| cheat.enabled) {
+        if let CheatEffect::RomPatch {
+            address: patch_address,
+            value,
+            compare,
+        } = cheat.effect
+        {
+            if patch_address == address && compare.is_none_or(|compare| compare == rom_value) {
+                return value;
+            }
+        }
+    }
+    rom_value
+}
+
+pub fn parse_file(text: &str) -> Result<Vec<Cheat>, String> {
+    let mut cheats = vec![];
+
+    for (line_index, line) in text.lines().enumerate() {
+        let error = |message: String| format!("Line {}: {}", line_index + 1, message);
+
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut words = line.splitn(3, char::is_whitespace);
+        let enabled = match words.next().unwrap() {
+            "on" => true,
+            "off" => false,
+            word => return Err(error(format!("Expected on or off, got '{}'", word))),
+        };
+        let code = words.next().ok_or(error("Missing code".to_owned()))?;
+        let description = words.next().unwrap_or("").trim();
+
+        let mut cheat = Cheat::new(code, description).map_err(error)?;
+        cheat.enabled = enabled;
+        cheats.push(cheat);
+    }
+
+    Ok(cheats)
+}
+
+/// The inverse of parse_file.
+pub fn write_file(cheats: &[Cheat]) -> String {
+    let mut text = String::from("# robin_gb cheats\n");
+    for cheat in cheats {
+        let state = if cheat.enabled { "on" } else { "off" };
+        text += format!("{} {} {}", state, cheat.code, cheat.description).trim_end();
+        text += "\n";
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameBoy;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            Cheat::new("421-04f-aae", "").unwrap().effect,
+            CheatEffect::RomPatch {
+                address: 0x0104,
+                value: 0x42,
+                compare: Some(0x11),
+            }
+        );
+        assert_eq!(
+            Cheat::new("00A17B", "").unwrap().effect,
+            CheatEffect::RomPatch {
+                address: 0x4a17,
+                value: 0x00,
+                compare: None,
+            }
+        );
+        assert_eq!(
+            Cheat::new("01ff16d0", "").unwrap().effect,
+            CheatEffect::RamWrite {
+                bank: None,
+                address: 0xd016,
+                value: 0xff,
+            }
+        );
+        assert_eq!(
+            Cheat::new("82630fa0", "").unwrap().effect,
+            CheatEffect::RamWrite {
+                bank: Some(2),
+                address: 0xa00f,
+                value: 0x63,
+            }
+        );
+
+        assert!(Cheat::new("42104FAAE1", "").is_err());
+        assert!(Cheat::new("421-047-AAE", "").is_err()); // 0x8104 isn't ROM.
+        assert!(Cheat::new("01ff1600", "").is_err()); // 0x0016 isn't RAM.
+        assert!(Cheat::new("8263000c", "").is_err()); // 0x0c00 isn't external RAM.
+        assert!(Cheat::new("91ff16d0", "").is_err());
+    }
+
+    #[test]
+    fn test_cheats_in_game() {
+        // LD HL,0xc000; LD (HL),0x11; loop: JR loop
+        let program = [0x21, 0x00, 0xc0, 0x36, 0x11, 0x18, 0xfe];
+        let mut game_boy = GameBoy::new(&crate::test_rom(&program));
+
+        // Replaces the 0x11 at 0x0104 with 0x42.
+        game_boy.add_cheat(Cheat::new("421-04F-AAE", "").unwrap());
+        // The same address with the wrong compare value does nothing.
+        game_boy.add_cheat(Cheat::new("991-04F-AAA", "").unwrap());
+        game_boy.add_cheat(Cheat::new("017701c1", "").unwrap());
+        game_boy.add_cheat(Cheat::new("805500a0", "").unwrap());
+        game_boy.add_cheat(Cheat::new("816601a0", "").unwrap());
+
+        game_boy.emulate_next_frame();
+        assert_eq!(game_boy.peek(0xc000), 0x42);
+        assert_eq!(game_boy.peek(0xc101), 0x77);
+        // Only external RAM bank 0 is mapped.
+        assert_eq!(game_boy.peek(0xa000), 0x55);
+        assert_eq!(game_boy.peek(0xa001), 0x00);
+
+        // Disabled RAM cheats stop being written.
+        assert!(game_boy.set_cheat_enabled("017701C1", false));
+        game_boy.poke(0xc101, 0);
+        game_boy.emulate_next_frame();
+        assert_eq!(game_boy.peek(0xc101), 0);
+
+        game_boy.remove_cheat("421-04f-aae");
+        assert!(!game_boy.set_cheat_enabled("421-04F-AAE", true));
+        assert_eq!(game_boy.cheats().len(), 4);
+    }
+
+    #[test]
+    fn test_cheat_file() {
+        let text = "# Comment\non 421-04F-AAE Start with 66 lives\n\noff 01ff16d0\n";
+        let cheats = parse_file(text).unwrap();
+        assert_eq!(cheats.len(), 2);
+        assert_eq!(cheats[0].description, "Start with 66 lives");
+        assert!(cheats[0].enabled);
+        assert_eq!(cheats[1].code, "01FF16D0");
+        assert!(!cheats[1].enabled);
+        assert_eq!(parse_file(&write_file(&cheats)).unwrap(), cheats);
+
+        assert!(parse_file("maybe 01ff16d0\n").is_err());
+        assert!(parse_file("on\n").is_err());
+        assert!(parse_file("on 12345\n").is_err());
+    }
+}
@@ -1,8 +1,10 @@
 mod instructions;
+#[cfg(test)]
+mod single_step_tests;
 
-use crate::address;
+use crate::bus::Bus;
 use crate::interrupt;
-use crate::Memory;
+use crate::state;
 use crate::{make_bit, make_u16};
 use instructions::FlagDiff;
 
@@ -10,12 +12,16 @@ use instructions::FlagDiff;
 
 // rwtodo: Apparently STOP is like HALT except the LCD is inoperational as well, and the "stopped" state is only exited when a button is pressed. Look for better documentation on it.
 
-fn stack_push(value_to_push: u16, sp: &mut u16, memory: &mut Memory) {
-    *sp -= 2;
-    memory.write_u16(*sp, value_to_push);
+// The high byte is pushed first, as on hardware.
+fn stack_push(value_to_push: u16, sp: &mut u16, memory: &mut impl Bus) {
+    let [low, high] = value_to_push.to_le_bytes();
+    *sp -= 1;
+    memory.write(*sp, high);
+    *sp -= 1;
+    memory.write(*sp, low);
 }
 
-fn stack_pop(sp: &mut u16, memory: &Memory) -> u16 {
+fn stack_pop(sp: &mut u16, memory: &impl Bus) -> u16 {
     let popped_value = memory.read_u16(*sp);
     *sp += 2;
     popped_value
@@ -23,7 +29,26 @@ fn stack_pop(sp: &mut u16, memory: &Memory) -> u16 {
 
 type CycleCount = u8;
 
-#[derive(Default)]
+/// A CPU register that can be inspected or edited with GameBoy::register() and GameBoy::set_register().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+#[derive(Clone, Default)]
 pub struct Registers {
     // General purpose registers
     a: u8,
@@ -112,7 +137,48 @@ impl Registers {
         self.h = bytes[1];
     }
 
-    fn read_operand_8bit(&mut self, operand_code_3bit: u8, memory: &Memory) -> u8 {
+    pub fn get(&self, register: Register) -> u16 {
+        match register {
+            Register::A => self.a.into(),
+            Register::F => self.f.into(),
+            Register::B => self.b.into(),
+            Register::C => self.c.into(),
+            Register::D => self.d.into(),
+            Register::E => self.e.into(),
+            Register::H => self.h.into(),
+            Register::L => self.l.into(),
+            Register::AF => self.af(),
+            Register::BC => self.bc(),
+            Register::DE => self.de(),
+            Register::HL => self.hl(),
+            Register::SP => self.sp,
+            Register::PC => self.pc,
+        }
+    }
+
+    // 8-bit registers take the lower byte of new_value. The lower nybble of F always stays 0.
+    pub fn set(&mut self, register: Register, new_value: u16) {
+        let lower_byte = new_value.to_le_bytes()[0];
+
+        match register {
+            Register::A => self.a = lower_byte,
+            Register::F => self.f = lower_byte & 0xf0,
+            Register::B => self.b = lower_byte,
+            Register::C => self.c = lower_byte,
+            Register::D => self.d = lower_byte,
+            Register::E => self.e = lower_byte,
+            Register::H => self.h = lower_byte,
+            Register::L => self.l = lower_byte,
+            Register::AF => self.set_af(new_value & 0xfff0),
+            Register::BC => self.set_bc(new_value),
+            Register::DE => self.set_de(new_value),
+            Register::HL => self.set_hl(new_value),
+            Register::SP => self.sp = new_value,
+            Register::PC => self.pc = new_value,
+        }
+    }
+
+    fn read_operand_8bit(&mut self, operand_code_3bit: u8, memory: &impl Bus) -> u8 {
         debug_assert!(operand_code_3bit & 0b11111000 == 0);
         match operand_code_3bit {
             0x00 => self.b,
@@ -131,7 +197,7 @@ impl Registers {
         &mut self,
         operand_value: u8,
         operand_code_3bit: u8,
-        memory: &mut Memory,
+        memory: &mut impl Bus,
     ) {
         debug_assert!(operand_code_3bit & 0b11111000 == 0);
         match operand_code_3bit & 0x0f {
@@ -182,11 +248,20 @@ impl Registers {
     }
 }
 
+/// An SM83 CPU, initialised to the Game Boy's post-boot state. It runs against any Bus passed to
+/// execute_next_instruction.
+#[derive(Clone)]
 pub struct Cpu {
     registers: Registers, // rwtodo maybe just put the registers in the cpu without wrapping them in a struct
     is_halted: bool,
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Cpu {
     pub fn new() -> Self {
         Self {
@@ -195,9 +270,39 @@ impl Cpu {
         }
     }
 
-    fn handle_interrupt_requests(&mut self, memory: &mut Memory) {
-        let mut requested_interrupts = *memory.direct_access(address::INTERRUPT_FLAGS);
-        let enabled_interrupts = *memory.direct_access(address::INTERRUPT_ENABLE);
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    pub(crate) fn save_state(&self, out: &mut Vec<u8>) {
+        let r = &self.registers;
+        out.extend_from_slice(&[r.a, r.b, r.c, r.d, r.e, r.f, r.h, r.l]);
+        out.extend_from_slice(&r.pc.to_le_bytes());
+        out.extend_from_slice(&r.sp.to_le_bytes());
+        out.extend_from_slice(&[u8::from(r.ime), u8::from(self.is_halted)]);
+    }
+
+    pub(crate) fn load_state(&mut self, input: &mut &[u8]) {
+        let r = &mut self.registers;
+        [r.a, r.b, r.c, r.d, r.e, r.f, r.h, r.l] = state::take(input);
+        r.pc = u16::from_le_bytes(state::take(input));
+        r.sp = u16::from_le_bytes(state::take(input));
+        let [ime, is_halted] = state::take(input);
+        r.ime = ime != 0;
+        self.is_halted = is_halted != 0;
+    }
+
+    fn handle_interrupt_requests(&mut self, memory: &mut impl Bus) {
+        let mut requested_interrupts = memory.interrupt_flags();
+        let enabled_interrupts = memory.interrupt_enable();
         let interrupts_to_handle = requested_interrupts & enabled_interrupts;
 
         if interrupts_to_handle != 0x00 {
@@ -229,41 +334,31 @@ impl Cpu {
                     unreachable!("Unexpected interrupts_to_handle value");
                 }
 
-                *memory.direct_access(address::INTERRUPT_FLAGS) = requested_interrupts;
+                memory.set_interrupt_flags(requested_interrupts);
             }
         }
     }
 
     #[must_use] // Returns the number of cycles the instruction took.
-    pub fn execute_next_instruction(&mut self, memory: &mut Memory) -> u8 {
+    pub fn execute_next_instruction(&mut self, memory: &mut impl Bus) -> u8 {
         let cycles = self.execute_next_instruction_inner(memory);
         self.handle_interrupt_requests(memory);
+        memory.tick(cycles);
         cycles
     }
 
     #[must_use] // Returns the number of cycles the instruction took.
-    fn execute_next_instruction_inner(&mut self, memory: &mut Memory) -> u8 {
+    fn execute_next_instruction_inner(&mut self, memory: &mut impl Bus) -> u8 {
         if self.is_halted {
             return 4;
         }
 
-        // Check for unexpected addresses for instructions.
-        debug_assert!(
-            self.registers.pc < 0x8000
-                || (self.registers.pc >= 0xff80 && self.registers.pc < 0xffff)
-                || (self.registers.pc >= 0xa000 && self.registers.pc < 0xfe00)
-        );
-
         let opcode = memory.read(self.registers.pc);
 
         // Getters for the data immediately following the program counter (pc).
         let immediate_u8 = || memory.read(self.registers.pc + 1);
         let immediate_u16 = || memory.read_u16(self.registers.pc + 1);
 
-        // print!("{:#06x} {:#04x}: ", self.registers.pc, opcode);
-        // print_instruction(self.registers.pc, memory);
-        // println!();
-
         use instructions::*;
 
         let diff: CpuDiff = match opcode {
@@ -341,7 +436,12 @@ impl Cpu {
                     .flag_h(false)
                     .flag_c(flag_c)
             } // RRCA
-            0x10 => todo!(), // STOP 0
+            0x10 => {
+                // rwtodo: STOP should also reset DIV, turn the LCD off and wait for a button rather than any interrupt.
+                let _ = immediate_u8();
+                self.is_halted = true;
+                CpuDiff::new(2, 8)
+            } // STOP 0
             0x11 => {
                 self.registers.set_de(immediate_u16());
                 CpuDiff::new(3, 12)
@@ -361,7 +461,7 @@ impl Cpu {
                 let previous_carry = self.registers.f & Registers::FLAG_CARRY != 0;
                 let bit_7 = self.registers.a & make_bit(7) != 0;
 
-                self.registers.a = self.registers.a << 1;
+                self.registers.a <<= 1;
 
                 if previous_carry {
                     self.registers.a |= make_bit(0);
@@ -394,7 +494,7 @@ impl Cpu {
             0x1f => {
                 let previous_carry = self.registers.f & Registers::FLAG_CARRY != 0;
                 let new_carry = self.registers.a & make_bit(0) != 0;
-                self.registers.a = self.registers.a >> 1;
+                self.registers.a >>= 1;
 
                 if previous_carry {
                     self.registers.a |= make_bit(7);
@@ -407,8 +507,10 @@ impl Cpu {
                     .flag_c(new_carry)
             } // RRA
             0x20 => {
+                // The offset is read whether or not the jump is taken.
+                let offset = immediate_u8() as i8;
                 if self.registers.f & Registers::FLAG_ZERO == 0 {
-                    CpuDiff::new(2 + i16::from(immediate_u8() as i8), 12)
+                    CpuDiff::new(2 + i16::from(offset), 12)
                 } else {
                     CpuDiff::new(2, 8)
                 }
@@ -431,14 +533,17 @@ impl Cpu {
             0x26 => ld_reg8_mem8(&mut self.registers.h, immediate_u8()), // LD H,x
             0x27 => {
                 let mut new_a: u16 = self.registers.a.into();
+                // Carry is only ever set, never cleared.
+                let mut flag_c = self.registers.f & Registers::FLAG_CARRY != 0;
 
                 if self.registers.f & Registers::FLAG_SUBTRACTION == 0 {
                     if (self.registers.f & Registers::FLAG_HALFCARRY != 0) || (new_a & 0x0f) > 0x09
                     {
                         new_a += 0x06;
                     }
-                    if (self.registers.f & Registers::FLAG_CARRY != 0) || new_a > 0x9f {
+                    if flag_c || new_a > 0x9f {
                         new_a += 0x60;
+                        flag_c = true;
                     }
                 } else {
                     if self.registers.f & Registers::FLAG_HALFCARRY != 0 {
@@ -458,12 +563,13 @@ impl Cpu {
                 CpuDiff::new(1, 4)
                     .flag_z(self.registers.a == 0)
                     .flag_h(false)
-                    .flag_c(new_a & 0x100 != 0)
+                    .flag_c(flag_c)
             } // DAA
             0x28 => {
+                // The offset is read whether or not the jump is taken.
+                let offset = immediate_u8() as i8;
                 if self.registers.f & Registers::FLAG_ZERO != 0 {
-                    let imm = immediate_u8() as i8;
-                    CpuDiff::new((2 + imm).into(), 12) // rwtodo handle wraparound here.
+                    CpuDiff::new(2 + i16::from(offset), 12)
                 } else {
                     CpuDiff::new(2, 8)
                 }
@@ -491,8 +597,10 @@ impl Cpu {
                 CpuDiff::new(1, 4).flag_n(true).flag_h(true)
             } // CPL
             0x30 => {
+                // The offset is read whether or not the jump is taken.
+                let offset = immediate_u8() as i8;
                 if self.registers.f & Registers::FLAG_CARRY == 0 {
-                    CpuDiff::new(2 + i16::from(immediate_u8() as i8), 12)
+                    CpuDiff::new(2 + i16::from(offset), 12)
                 } else {
                     CpuDiff::new(2, 8)
                 }
@@ -528,8 +636,10 @@ impl Cpu {
             } // LD (HL),x
             0x37 => CpuDiff::new(1, 4).flag_n(false).flag_h(false).flag_c(true), // SCF
             0x38 => {
+                // The offset is read whether or not the jump is taken.
+                let offset = immediate_u8() as i8;
                 if self.registers.f & Registers::FLAG_CARRY != 0 {
-                    CpuDiff::new(2 + i16::from(immediate_u8() as i8), 12)
+                    CpuDiff::new(2 + i16::from(offset), 12)
                 } else {
                     CpuDiff::new(2, 8)
                 }
@@ -687,10 +797,7 @@ impl Cpu {
                 self.registers.a = memory.read(self.registers.hl());
                 CpuDiff::new(1, 8)
             } // LD A,(HL)
-            0x7f => {
-                self.registers.a = self.registers.a;
-                CpuDiff::new(1, 4)
-            } // LD A,A
+            0x7f => nop(), // LD A,A
             0x80 => add_u8(
                 self.registers.b,
                 &mut self.registers.a,
@@ -948,14 +1055,14 @@ impl Cpu {
                 or(x, &mut self.registers.a, 1, 8)
             } // OR (HL)
             0xb7 => or(self.registers.a, &mut self.registers.a, 1, 4), // OR A
-            0xb8 => cp(self.registers.b, &mut self.registers, 1, 4), // CP B // rwtodo &mut? &?
-            0xb9 => cp(self.registers.c, &mut self.registers, 1, 4), // CP C // rwtodo &mut? &?
-            0xba => cp(self.registers.d, &mut self.registers, 1, 4), // CP D // rwtodo &mut? &?
-            0xbb => cp(self.registers.e, &mut self.registers, 1, 4), // CP E // rwtodo &mut? &?
-            0xbc => cp(self.registers.h, &mut self.registers, 1, 4), // CP H // rwtodo &mut? &?
-            0xbd => cp(self.registers.l, &mut self.registers, 1, 4), // CP L // rwtodo &mut? &?
-            0xbe => cp(memory.read(self.registers.hl()), &mut self.registers, 1, 8), // CP (HL)
-            0xbf => cp(self.registers.a, &mut self.registers, 1, 4), // RES 7,A
+            0xb8 => cp(self.registers.b, &self.registers, 1, 4), // CP B // rwtodo &mut? &?
+            0xb9 => cp(self.registers.c, &self.registers, 1, 4), // CP C // rwtodo &mut? &?
+            0xba => cp(self.registers.d, &self.registers, 1, 4), // CP D // rwtodo &mut? &?
+            0xbb => cp(self.registers.e, &self.registers, 1, 4), // CP E // rwtodo &mut? &?
+            0xbc => cp(self.registers.h, &self.registers, 1, 4), // CP H // rwtodo &mut? &?
+            0xbd => cp(self.registers.l, &self.registers, 1, 4), // CP L // rwtodo &mut? &?
+            0xbe => cp(memory.read(self.registers.hl()), &self.registers, 1, 8), // CP (HL)
+            0xbf => cp(self.registers.a, &self.registers, 1, 4), // RES 7,A
             0xc0 => {
                 if self.registers.f & Registers::FLAG_ZERO == 0 {
                     self.registers.pc = stack_pop(&mut self.registers.sp, memory);
@@ -970,8 +1077,10 @@ impl Cpu {
                 CpuDiff::new(1, 12)
             } // POP BC
             0xc2 => {
+                // The target is read whether or not the jump is taken.
+                let target = immediate_u16();
                 if self.registers.f & Registers::FLAG_ZERO == 0 {
-                    self.registers.pc = immediate_u16();
+                    self.registers.pc = target;
                     CpuDiff::new(0, 16)
                 } else {
                     CpuDiff::new(3, 12)
@@ -1011,8 +1120,10 @@ impl Cpu {
                 CpuDiff::new(0, 16)
             } // RET
             0xca => {
+                // The target is read whether or not the jump is taken.
+                let target = immediate_u16();
                 if self.registers.f & Registers::FLAG_ZERO != 0 {
-                    self.registers.pc = immediate_u16();
+                    self.registers.pc = target;
                     CpuDiff::new(0, 16)
                 } else {
                     CpuDiff::new(3, 12)
@@ -1047,8 +1158,10 @@ impl Cpu {
                 CpuDiff::new(1, 12)
             }
             0xd2 => {
+                // The target is read whether or not the jump is taken.
+                let target = immediate_u16();
                 if self.registers.f & Registers::FLAG_CARRY == 0 {
-                    self.registers.pc = immediate_u16();
+                    self.registers.pc = target;
                     CpuDiff::new(0, 16)
                 } else {
                     CpuDiff::new(3, 12)
@@ -1087,8 +1200,10 @@ impl Cpu {
                 CpuDiff::new(0, 16)
             } // RETI
             0xda => {
+                // The target is read whether or not the jump is taken.
+                let target = immediate_u16();
                 if self.registers.f & Registers::FLAG_CARRY != 0 {
-                    self.registers.pc = immediate_u16();
+                    self.registers.pc = target;
                     CpuDiff::new(0, 16)
                 } else {
                     CpuDiff::new(3, 12)
@@ -1158,7 +1273,7 @@ impl Cpu {
                 CpuDiff::new(3, 16)
             } // LD (x),A
             0xeb..=0xed => unreachable!("Invalid opcode"),
-            0xee => xor(immediate_u8(), &mut self.registers.a, 2, 4), // XOR x
+            0xee => xor(immediate_u8(), &mut self.registers.a, 2, 8), // XOR x
             0xef => rst(0x28, &mut self.registers, memory),           // RST 28H
             0xf0 => {
                 self.registers.a = memory.read(0xff00 + u16::from(immediate_u8()));
@@ -1205,7 +1320,7 @@ impl Cpu {
                 CpuDiff::new(1, 8)
             } // LD SP,HL
             0xfa => {
-                let address = u16::from(immediate_u16());
+                let address = immediate_u16();
                 self.registers.a = memory.read(address);
                 CpuDiff::new(3, 16)
             } // LD A,(xx)
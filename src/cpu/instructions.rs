@@ -82,78 +82,6 @@ pub fn addition_produces_half_carry(a: u8, b: u8, register_f: u8, include_carry:
     i16::from(a & 0x0f) + i16::from(b & 0x0f) + optional_carry > 0x0f
 }
 
-pub fn print_instruction(pc: u16, memory: &Memory) {
-    let opcode = memory.read(pc);
-    match opcode {
-        0x00 => println!("NOP"),
-        0x05 => println!("DEC B"),
-        0x06 => println!("LD B,{:#04x}", memory.read(pc + 1)),
-        0x0d => println!("DEC C"),
-        0x0e => println!("LD C,{:#04x}", memory.read(pc + 1)),
-        0x11 => println!("LD DE,{:#06x}", memory.read_u16(pc + 1)),
-        0x20 => println!("JR NZ,{}", 2 + i16::from(memory.read(pc + 1) as i8)),
-        0x21 => println!("LD HL,{:#06x}", memory.read_u16(pc + 1)),
-        0x28 => println!("JR Z,{}", 2 + i16::from(memory.read(pc + 1) as i8)),
-        0x2a => println!("LD A,(HL+)"),
-        0x2c => println!("INC L"),
-        0x32 => println!("LD (HL-),A"),
-        0x36 => println!("LD (HL),{:#04x}", memory.read(pc + 1)),
-        0x3e => println!("LD A,{:#04x}", memory.read(pc + 1)),
-        0x40 => println!("LD B,B"),
-        0x41 => println!("LD B,C"),
-        0x42 => println!("LD B,D"),
-        0x43 => println!("LD B,E"),
-        0x44 => println!("LD B,H"),
-        0x45 => println!("LD B,L"),
-        0x47 => println!("LD B,A"),
-        0x48 => println!("LD C,B"),
-        0x49 => println!("LD C,C"),
-        0x4a => println!("LD C,D"),
-        0x4b => println!("LD C,E"),
-        0x4c => println!("LD C,H"),
-        0x4d => println!("LD C,L"),
-        0x4f => println!("LD C,A"),
-        0x50 => println!("LD D,B"),
-        0x51 => println!("LD D,C"),
-        0x52 => println!("LD D,D"),
-        0x53 => println!("LD D,E"),
-        0x54 => println!("LD D,H"),
-        0x55 => println!("LD D,L"),
-        0x57 => println!("LD D,A"),
-        0x58 => println!("LD E,B"),
-        0x59 => println!("LD E,C"),
-        0x5a => println!("LD E,D"),
-        0x5b => println!("LD E,E"),
-        0x5c => println!("LD E,H"),
-        0x5d => println!("LD E,L"),
-        0x5f => println!("LD E,A"),
-        0x60 => println!("LD H,B"),
-        0x61 => println!("LD H,C"),
-        0x62 => println!("LD H,D"),
-        0x63 => println!("LD H,E"),
-        0x64 => println!("LD H,H"),
-        0x65 => println!("LD H,L"),
-        0x67 => println!("LD H,A"),
-        0x68 => println!("LD L,B"),
-        0x69 => println!("LD L,C"),
-        0x6a => println!("LD L,D"),
-        0x6b => println!("LD L,E"),
-        0x6c => println!("LD L,H"),
-        0x6d => println!("LD L,L"),
-        0x6f => println!("LD L,A"),
-        0xaf => println!("XOR A"),
-        0xc3 => println!("JP {:#06x}", memory.read_u16(pc + 1)),
-        0xc8 => println!("RET Z"),
-        0xcd => println!("CALL {:#06x}", memory.read_u16(pc + 1)),
-        0xe0 => println!("LDH {:#06x},A", 0xff00 + u16::from(memory.read(pc + 1))),
-        0xe6 => println!("AND {:#04x}", memory.read(pc + 1)),
-        0xf0 => println!("LDH A,{:#06x}", 0xff00 + u16::from(memory.read(pc + 1))),
-        0xf3 => println!("DI"),
-        0xfe => println!("CP {:#04x}", memory.read(pc + 1)),
-        _ => println!("op {:#04x} at address {:#06x}", opcode, pc),
-    };
-}
-
 pub fn inc_u8(value_to_increment: &mut u8, register_f: u8, cycles: u8) -> CpuDiff {
     let half_carry = addition_produces_half_carry(*value_to_increment, 1, register_f, false);
     *value_to_increment = value_to_increment.wrapping_add(1);
@@ -235,10 +163,12 @@ pub fn add_reg16(src: u16, dst_register: &mut u16) -> CpuDiff {
         .flag_c(full_carry)
 }
 
-pub fn call(condition: bool, registers: &mut Registers, memory: &mut Memory) -> CpuDiff {
+pub fn call(condition: bool, registers: &mut Registers, memory: &mut impl Bus) -> CpuDiff {
+    // The target is read whether or not the call is made, and before the return address is pushed.
+    let target = memory.read_u16(registers.pc + 1);
     if condition {
         stack_push(registers.pc + 3, &mut registers.sp, memory);
-        registers.pc = memory.read_u16(registers.pc + 1);
+        registers.pc = target;
         CpuDiff::new(0, 24)
     } else {
         CpuDiff::new(3, 12)
@@ -334,7 +264,7 @@ pub fn cp(comparator: u8, registers: &Registers, pc_delta: i16, cycles: u8) -> C
         .flag_c(full_carry)
 }
 
-pub fn rst(address_lower_byte: u8, registers: &mut Registers, memory: &mut Memory) -> CpuDiff {
+pub fn rst(address_lower_byte: u8, registers: &mut Registers, memory: &mut impl Bus) -> CpuDiff {
     stack_push(registers.pc + 1, &mut registers.sp, memory);
     registers.pc = address_lower_byte.into();
     CpuDiff::new(0, 16)
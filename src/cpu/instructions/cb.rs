@@ -1,12 +1,12 @@
 use super::*;
+use crate::bus::Bus;
 use crate::cpu::Registers;
-use crate::Memory;
 
 fn get_bit_index_from_immediate_byte(immediate_byte: u8) -> u8 {
     (immediate_byte / 8) % 8
 }
 
-pub fn execute_cb_instruction(registers: &mut Registers, memory: &mut Memory) -> CpuDiff {
+pub fn execute_cb_instruction(registers: &mut Registers, memory: &mut impl Bus) -> CpuDiff {
     let immediate_byte = memory.read(registers.pc + 1);
 
     let bit_index = get_bit_index_from_immediate_byte(immediate_byte);
@@ -29,13 +29,17 @@ pub fn execute_cb_instruction(registers: &mut Registers, memory: &mut Memory) ->
         0xc0..=0xff => set(&mut operand, bit_index),
     };
 
-    registers.write_operand_8bit(operand, operand_id, memory);
+    // BIT only reads its operand, so (HL) isn't written back and takes a cycle less.
+    let is_bit = matches!(immediate_byte, 0x40..=0x7f);
+    if !is_bit {
+        registers.write_operand_8bit(operand, operand_id, memory);
+    }
 
     let cycle_nybble = immediate_byte & 0x0f;
-    let cycles = if cycle_nybble == 0x06 || cycle_nybble == 0x0e {
-        16
-    } else {
-        8
+    let cycles = match (cycle_nybble == 0x06 || cycle_nybble == 0x0e, is_bit) {
+        (true, true) => 12,
+        (true, false) => 16,
+        (false, _) => 8,
     };
     CpuDiff {
         flag_diff,
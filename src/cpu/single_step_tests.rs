@@ -0,0 +1,210 @@
+// Runs SM83 single-step test vectors against the CPU, on a flat 64kB RAM bus.
+//
+// The vectors are in the format of the public SingleStepTests/sm83 suite: one JSON file per opcode in
+// test_data/sm83/v1, named like "3c.json" or "cb 37.json", each holding an array of tests with an initial
+// state, a final state and one entry per machine cycle. There's a file for every opcode, generated from SameBoy's
+// CPU core by test_data/sm83/generate.c, and files from the full suite can be dropped in as they are.
+// Registers, memory, the cycle count and the bus activity in each cycle are checked. IME isn't, as EI's delay
+// isn't emulated.
+
+use super::*;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+// No mapping, mirroring or I/O registers, as the vectors expect. Every access is logged, to compare against
+// the vectors' cycles.
+struct FlatBus {
+    bytes: Vec<u8>,
+    accesses: RefCell<Vec<Access>>,
+}
+
+impl Bus for FlatBus {
+    fn read(&self, address: u16) -> u8 {
+        let value = self.bytes[usize::from(address)];
+        self.accesses.borrow_mut().push(Access {
+            address,
+            value,
+            write: false,
+        });
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.bytes[usize::from(address)] = value;
+        self.accesses.get_mut().push(Access {
+            address,
+            value,
+            write: true,
+        });
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Access {
+    address: u16,
+    value: u8,
+    write: bool,
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: u16,
+    sp: u16,
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    f: u8,
+    h: u8,
+    l: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+#[derive(Deserialize)]
+struct Test {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    final_state: State,
+    // One entry per machine cycle: the address, the value and the pins ("r-m" for a read, "-wm" for a write),
+    // or null for a cycle without a memory access.
+    cycles: Vec<Option<(u16, u8, String)>>,
+}
+
+// The accesses that the vector expects, in order.
+fn expected_accesses(test: &Test) -> Vec<Access> {
+    test.cycles
+        .iter()
+        .flatten()
+        .map(|(address, value, pins)| Access {
+            address: *address,
+            value: *value,
+            write: pins.contains('w'),
+        })
+        .collect()
+}
+
+fn register_values(state: &State) -> [(Register, u16); 10] {
+    [
+        (Register::PC, state.pc),
+        (Register::SP, state.sp),
+        (Register::A, state.a.into()),
+        (Register::B, state.b.into()),
+        (Register::C, state.c.into()),
+        (Register::D, state.d.into()),
+        (Register::E, state.e.into()),
+        (Register::F, state.f.into()),
+        (Register::H, state.h.into()),
+        (Register::L, state.l.into()),
+    ]
+}
+
+// Returns a description of every difference from the final state.
+fn run_test(test: &Test) -> Vec<String> {
+    let mut memory = FlatBus {
+        bytes: vec![0; 0x10000],
+        accesses: RefCell::new(vec![]),
+    };
+    for &(address, value) in &test.initial.ram {
+        memory.bytes[usize::from(address)] = value;
+    }
+
+    let mut cpu = Cpu::new();
+    for (register, value) in register_values(&test.initial) {
+        cpu.registers.set(register, value);
+    }
+
+    let cycles = match panic::catch_unwind(AssertUnwindSafe(|| {
+        cpu.execute_next_instruction_inner(&mut memory)
+    })) {
+        Ok(cycles) => cycles,
+        Err(_) => return vec!["panicked".to_owned()],
+    };
+
+    let mut differences = vec![];
+
+    for (register, expected) in register_values(&test.final_state) {
+        let actual = cpu.registers.get(register);
+        if actual != expected {
+            differences.push(format!(
+                "{:?} is {:#x}, expected {:#x}",
+                register, actual, expected
+            ));
+        }
+    }
+
+    let accesses = memory.accesses.take();
+    let expected_accesses = expected_accesses(test);
+    if accesses != expected_accesses {
+        differences.push(format!(
+            "accessed {:?}, expected {:?}",
+            accesses, expected_accesses
+        ));
+    }
+
+    for &(address, expected) in &test.final_state.ram {
+        let actual = memory.bytes[usize::from(address)];
+        if actual != expected {
+            differences.push(format!(
+                "[{:#06x}] is {:#x}, expected {:#x}",
+                address, actual, expected
+            ));
+        }
+    }
+
+    let expected_cycles = test.cycles.len() * 4;
+    if usize::from(cycles) != expected_cycles {
+        differences.push(format!(
+            "took {} cycles, expected {}",
+            cycles, expected_cycles
+        ));
+    }
+
+    differences
+}
+
+#[test]
+fn test_single_step_vectors() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/sm83/v1");
+    let mut paths: Vec<_> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+    // Every opcode but the 11 invalid ones and the CB prefix itself, and every CB-prefixed opcode.
+    assert_eq!(paths.len(), 244 + 256);
+
+    // Silence the panic messages of vectors that panic, as they're reported below.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failures = vec![];
+
+    for path in paths {
+        let tests: Vec<Test> = serde_json::from_str(&fs::read_to_string(&path).unwrap())
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+
+        for test in tests {
+            let differences = run_test(&test);
+            if !differences.is_empty() {
+                failures.push(format!("{}: {}", test.name, differences.join(", ")));
+            }
+        }
+    }
+
+    panic::set_hook(default_hook);
+
+    assert!(
+        failures.is_empty(),
+        "Failing vectors:\n{}",
+        failures.join("\n")
+    );
+}
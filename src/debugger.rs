@@ -0,0 +1,282 @@
+// Types for pausing and inspecting emulation. The debugging methods themselves live on GameBoy.
+
+use crate::cpu::{Register, Registers};
+use crate::Memory;
+
+/// Stop before executing the instruction at `address`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: u16,
+    /// The ROM bank that must be mapped at `address` for the breakpoint to trigger. None matches any bank.
+    pub bank: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // Either a read or a write.
+}
+
+/// Stop after any instruction that accesses `address` in the manner described by `kind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub address: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn matches(&self, address: u16, kind: WatchKind) -> bool {
+        self.address == address && (self.kind == WatchKind::Access || self.kind == kind)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchpointHit {
+    pub watchpoint: Watchpoint,
+    /// Either WatchKind::Read or WatchKind::Write, depending on the access that triggered the watchpoint.
+    pub access: WatchKind,
+    /// The byte that was read, or the byte that was written.
+    pub value: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Stop before executing an instruction if a register compares to `value` as described.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BreakCondition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl BreakCondition {
+    fn is_met(&self, registers: &Registers) -> bool {
+        let register_value = registers.get(self.register);
+
+        match self.comparison {
+            Comparison::Equal => register_value == self.value,
+            Comparison::NotEqual => register_value != self.value,
+            Comparison::Less => register_value < self.value,
+            Comparison::LessOrEqual => register_value <= self.value,
+            Comparison::Greater => register_value > self.value,
+            Comparison::GreaterOrEqual => register_value >= self.value,
+        }
+    }
+}
+
+/// Why emulation returned control to the caller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    FrameComplete,
+    ScanlineComplete,
+    InstructionComplete,
+    /// The instruction at the breakpoint has not been executed yet.
+    Breakpoint(Breakpoint),
+    /// The instruction that triggered the watchpoint has been executed.
+    Watchpoint(WatchpointHit),
+    /// The instruction at PC has not been executed yet.
+    Condition(BreakCondition),
+    /// PC is at an LD B,B, which test roms use as a software breakpoint. It has not been executed yet.
+    SoftwareBreakpoint,
+}
+
+#[derive(Clone, Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub conditions: Vec<BreakCondition>,
+    pub break_on_ld_b_b: bool,
+
+    // Set when stopping before an instruction, so that resuming executes it rather than stopping again.
+    pub skip_next_check: bool,
+}
+
+impl Debugger {
+    // Returns Some if emulation should stop before executing the instruction at PC.
+    pub fn check(&self, registers: &Registers, memory: &Memory) -> Option<StopReason> {
+        let pc = registers.get(Register::PC);
+
+        // 0x40 is LD B,B.
+        if self.break_on_ld_b_b && memory.peek(pc) == 0x40 {
+            return Some(StopReason::SoftwareBreakpoint);
+        }
+
+        for breakpoint in &self.breakpoints {
+            let bank_matches = match breakpoint.bank {
+                Some(bank) => memory.rom_bank_at(pc) == Some(bank),
+                None => true,
+            };
+
+            if breakpoint.address == pc && bank_matches {
+                return Some(StopReason::Breakpoint(*breakpoint));
+            }
+        }
+
+        self.conditions
+            .iter()
+            .find(|condition| condition.is_met(registers))
+            .map(|condition| StopReason::Condition(*condition))
+    }
+}
+
+// The watchpoint checks run on every memory access, so they're kept in Memory rather than here.
+pub fn find_watchpoint(
+    watchpoints: &[Watchpoint],
+    address: u16,
+    access: WatchKind,
+    value: u8,
+) -> Option<WatchpointHit> {
+    watchpoints
+        .iter()
+        .find(|watchpoint| watchpoint.matches(address, access))
+        .map(|watchpoint| WatchpointHit {
+            watchpoint: *watchpoint,
+            access,
+            value,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{address, test_rom, GameBoy, Register};
+
+    // LD A,5; LD (0xc000),A; loop: INC A; JR loop
+    const PROGRAM: [u8; 8] = [0x3e, 0x05, 0xea, 0x00, 0xc0, 0x3c, 0x18, 0xfd];
+
+    fn new_game_boy() -> GameBoy {
+        GameBoy::new(&test_rom(&PROGRAM))
+    }
+
+    #[test]
+    fn test_step_instruction() {
+        let mut game_boy = new_game_boy();
+        assert_eq!(game_boy.register(Register::PC), 0x0100);
+
+        let reason = game_boy.step_instruction();
+        assert_eq!(reason, StopReason::InstructionComplete);
+        assert_eq!(game_boy.register(Register::PC), 0x0102);
+        assert_eq!(game_boy.register(Register::A), 0x05);
+    }
+
+    #[test]
+    fn test_breakpoint_stops_before_instruction() {
+        let mut game_boy = new_game_boy();
+        let breakpoint = Breakpoint {
+            address: 0x0105,
+            bank: None,
+        };
+        game_boy.add_breakpoint(breakpoint);
+
+        let reason = game_boy.emulate_next_frame();
+        assert_eq!(reason, StopReason::Breakpoint(breakpoint));
+        assert_eq!(game_boy.register(Register::PC), 0x0105);
+        assert_eq!(game_boy.register(Register::A), 0x05);
+
+        // Resuming executes the instruction at the breakpoint instead of stopping again.
+        assert_eq!(game_boy.step_instruction(), StopReason::InstructionComplete);
+        assert_eq!(game_boy.register(Register::A), 0x06);
+    }
+
+    #[test]
+    fn test_breakpoint_in_other_bank_is_ignored() {
+        let mut game_boy = new_game_boy();
+        game_boy.add_breakpoint(Breakpoint {
+            address: 0x0105,
+            bank: Some(1),
+        });
+
+        let reason = game_boy.emulate_next_frame();
+        assert_eq!(reason, StopReason::FrameComplete);
+    }
+
+    #[test]
+    fn test_break_on_ld_b_b() {
+        // LD A,5; LD B,B; INC A
+        let mut game_boy = GameBoy::new(&test_rom(&[0x3e, 0x05, 0x40, 0x3c]));
+        game_boy.set_break_on_ld_b_b(true);
+
+        let reason = game_boy.emulate_next_frame();
+        assert_eq!(reason, StopReason::SoftwareBreakpoint);
+        assert_eq!(game_boy.register(Register::PC), 0x0102);
+
+        // Resuming executes the LD B,B rather than stopping on it again.
+        let _ = game_boy.step_instruction();
+        assert_eq!(game_boy.register(Register::PC), 0x0103);
+    }
+
+    #[test]
+    fn test_write_watchpoint() {
+        let mut game_boy = new_game_boy();
+        let watchpoint = Watchpoint {
+            address: 0xc000,
+            kind: WatchKind::Write,
+        };
+        game_boy.add_watchpoint(watchpoint);
+
+        let reason = game_boy.emulate_next_frame();
+        assert_eq!(
+            reason,
+            StopReason::Watchpoint(WatchpointHit {
+                watchpoint,
+                access: WatchKind::Write,
+                value: 0x05,
+            })
+        );
+        assert_eq!(game_boy.register(Register::PC), 0x0105);
+        assert_eq!(game_boy.peek(0xc000), 0x05);
+    }
+
+    #[test]
+    fn test_interrupt_polling_skips_watchpoints() {
+        // LD A,1; LDH (0xff),A; EI; JR -2. The V-blank handler is NOPs back to 0x0100.
+        let mut game_boy = GameBoy::new(&test_rom(&[0x3e, 0x01, 0xe0, 0xff, 0xfb, 0x18, 0xfe]));
+        game_boy.add_watchpoint(Watchpoint {
+            address: address::INTERRUPT_FLAGS,
+            kind: WatchKind::Access,
+        });
+
+        // The CPU polls IF after every instruction and clears V-blank's flag to handle it, but the program
+        // never accesses it.
+        for _ in 0..2 {
+            assert_eq!(game_boy.emulate_next_frame(), StopReason::FrameComplete);
+        }
+        assert!(game_boy.register(Register::SP) < 0xfffe);
+    }
+
+    #[test]
+    fn test_break_condition() {
+        let mut game_boy = new_game_boy();
+        let condition = BreakCondition {
+            register: Register::A,
+            comparison: Comparison::GreaterOrEqual,
+            value: 0x20,
+        };
+        game_boy.add_break_condition(condition);
+
+        let reason = game_boy.emulate_next_frame();
+        assert_eq!(reason, StopReason::Condition(condition));
+        assert_eq!(game_boy.register(Register::A), 0x20);
+    }
+
+    #[test]
+    fn test_edit_registers_and_memory() {
+        let mut game_boy = new_game_boy();
+        game_boy.set_register(Register::PC, 0x0105);
+        game_boy.set_register(Register::A, 0x41);
+        game_boy.poke(0xc123, 0x99);
+
+        let _ = game_boy.step_instruction();
+        assert_eq!(game_boy.register(Register::A), 0x42);
+        assert_eq!(game_boy.peek(0xc123), 0x99);
+        assert_eq!(game_boy.peek(0xe123), 0x99); // Echo RAM mirrors work RAM.
+    }
+}
@@ -0,0 +1,522 @@
+// An SM83 disassembler that decodes instructions into a structured form and formats them in RGBDS syntax.
+
+use std::collections::HashMap;
+use std::fmt;
+
+const ROM_BANK_SIZE: usize = 16384;
+
+const REGISTERS_8BIT: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const REGISTERS_16BIT: [&str; 4] = ["bc", "de", "hl", "sp"];
+const STACK_REGISTERS: [&str; 4] = ["bc", "de", "hl", "af"];
+const CONDITIONS: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU_MNEMONICS: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ROTATE_MNEMONICS: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// A register or register pair, e.g. "a" or "hl".
+    Register(&'static str),
+    /// A memory access through a register, e.g. "[hl+]" or "[c]".
+    Indirect(&'static str),
+    /// A branch condition, e.g. "nz".
+    Condition(&'static str),
+    Immediate8(u8),
+    Immediate16(u16),
+    /// A memory access at an absolute address, e.g. "[$c000]".
+    Address(u16),
+    /// The destination of a jump, call or relative jump.
+    Target(u16),
+    /// The signed offset in "add sp, e8".
+    Offset(i8),
+    /// The "sp + e8" in "ld hl, sp + e8".
+    StackOffset(i8),
+    /// The bit index of bit, res and set.
+    Bit(u8),
+    /// The destination of rst.
+    Vector(u8),
+}
+
+impl Operand {
+    fn format(&self, symbols: Option<(&SymbolTable, u8)>) -> String {
+        let symbol = |address: u16| symbols.and_then(|(table, bank)| table.lookup(bank, address));
+
+        match *self {
+            Operand::Register(name) | Operand::Indirect(name) | Operand::Condition(name) => {
+                name.to_owned()
+            }
+            Operand::Immediate8(value) => format!("${:02x}", value),
+            Operand::Immediate16(value) => format!("${:04x}", value),
+            Operand::Address(address) => match symbol(address) {
+                Some(name) => format!("[{}]", name),
+                None => format!("[${:04x}]", address),
+            },
+            Operand::Target(address) => match symbol(address) {
+                Some(name) => name.to_owned(),
+                None => format!("${:04x}", address),
+            },
+            Operand::Offset(offset) => offset.to_string(),
+            Operand::StackOffset(offset) if offset < 0 => format!("sp - {}", offset.unsigned_abs()),
+            Operand::StackOffset(offset) => format!("sp + {}", offset),
+            Operand::Bit(index) => index.to_string(),
+            Operand::Vector(address) => format!("${:02x}", address),
+        }
+    }
+}
+
+/// A decoded instruction. Invalid opcodes decode to a "db" directive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    /// Length in bytes, including the 0xcb prefix if present.
+    pub length: u8,
+    /// Cycles taken to execute the instruction, or to execute it with the branch taken if it's conditional.
+    pub cycles: u8,
+    /// Cycles taken if the instruction is conditional and the branch isn't taken.
+    pub cycles_not_taken: Option<u8>,
+}
+
+impl Instruction {
+    fn new(address: u16, mnemonic: &'static str, length: u8, cycles: u8) -> Self {
+        Self {
+            address,
+            mnemonic,
+            operands: vec![],
+            length,
+            cycles,
+            cycles_not_taken: None,
+        }
+    }
+
+    fn operand(mut self, operand: Operand) -> Self {
+        self.operands.push(operand);
+        self
+    }
+
+    fn not_taken(mut self, cycles: u8) -> Self {
+        self.cycles_not_taken = Some(cycles);
+        self
+    }
+
+    /// Format in RGBDS syntax, replacing addresses with labels where the symbol table has them.
+    /// `bank` is the ROM bank that's mapped at 0x4000-0x7fff.
+    pub fn format_with_symbols(&self, symbols: &SymbolTable, bank: u8) -> String {
+        self.format(Some((symbols, bank)))
+    }
+
+    fn format(&self, symbols: Option<(&SymbolTable, u8)>) -> String {
+        let operands: Vec<String> = self.operands.iter().map(|o| o.format(symbols)).collect();
+
+        if operands.is_empty() {
+            self.mnemonic.to_owned()
+        } else {
+            format!("{} {}", self.mnemonic, operands.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.format(None))
+    }
+}
+
+/// Decode the instruction at the start of `bytes`, which is located at `address`. If `bytes` is too
+/// short to hold the whole instruction, the first byte is decoded as "db".
+pub fn decode(bytes: &[u8], address: u16) -> Instruction {
+    let Some(&opcode) = bytes.first() else {
+        return Instruction::new(address, "db", 1, 0);
+    };
+
+    let instruction = if opcode == 0xcb {
+        match bytes.get(1) {
+            Some(&cb_opcode) => decode_cb(cb_opcode, address),
+            None => invalid(opcode, address),
+        }
+    } else {
+        let immediate_u8 = bytes.get(1).copied().unwrap_or(0);
+        let immediate_u16 = u16::from_le_bytes([immediate_u8, bytes.get(2).copied().unwrap_or(0)]);
+        decode_unprefixed(opcode, address, immediate_u8, immediate_u16)
+    };
+
+    if usize::from(instruction.length) > bytes.len() {
+        invalid(opcode, address)
+    } else {
+        instruction
+    }
+}
+
+fn invalid(opcode: u8, address: u16) -> Instruction {
+    Instruction::new(address, "db", 1, 0).operand(Operand::Immediate8(opcode))
+}
+
+fn decode_unprefixed(
+    opcode: u8,
+    address: u16,
+    immediate_u8: u8,
+    immediate_u16: u16,
+) -> Instruction {
+    use Operand::*;
+
+    // Many opcodes encode their operands in these bit fields.
+    let y = usize::from((opcode >> 3) & 0x07);
+    let z = usize::from(opcode & 0x07);
+    let pair = usize::from((opcode >> 4) & 0x03);
+    let condition = Condition(CONDITIONS[usize::from((opcode >> 3) & 0x03)]);
+
+    let relative_target = address
+        .wrapping_add(2)
+        .wrapping_add_signed((immediate_u8 as i8).into());
+
+    // Operands that access (HL) take more cycles.
+    let register_8bit = |index: usize| {
+        if index == 6 {
+            Indirect(REGISTERS_8BIT[index])
+        } else {
+            Register(REGISTERS_8BIT[index])
+        }
+    };
+    let hl_penalty = |index: usize, cycles: u8| if index == 6 { cycles } else { 0 };
+
+    let new = |mnemonic, length, cycles| Instruction::new(address, mnemonic, length, cycles);
+
+    match opcode {
+        0x00 => new("nop", 1, 4),
+        0x01 | 0x11 | 0x21 | 0x31 => new("ld", 3, 12)
+            .operand(Register(REGISTERS_16BIT[pair]))
+            .operand(Immediate16(immediate_u16)),
+        0x02 | 0x12 | 0x22 | 0x32 => {
+            let destination = ["[bc]", "[de]", "[hl+]", "[hl-]"][pair];
+            new("ld", 1, 8)
+                .operand(Indirect(destination))
+                .operand(Register("a"))
+        }
+        0x03 | 0x13 | 0x23 | 0x33 => new("inc", 1, 8).operand(Register(REGISTERS_16BIT[pair])),
+        0x0b | 0x1b | 0x2b | 0x3b => new("dec", 1, 8).operand(Register(REGISTERS_16BIT[pair])),
+        0x09 | 0x19 | 0x29 | 0x39 => new("add", 1, 8)
+            .operand(Register("hl"))
+            .operand(Register(REGISTERS_16BIT[pair])),
+        0x0a | 0x1a | 0x2a | 0x3a => {
+            let source = ["[bc]", "[de]", "[hl+]", "[hl-]"][pair];
+            new("ld", 1, 8)
+                .operand(Register("a"))
+                .operand(Indirect(source))
+        }
+        0x04 | 0x0c | 0x14 | 0x1c | 0x24 | 0x2c | 0x34 | 0x3c => {
+            new("inc", 1, 4 + hl_penalty(y, 8)).operand(register_8bit(y))
+        }
+        0x05 | 0x0d | 0x15 | 0x1d | 0x25 | 0x2d | 0x35 | 0x3d => {
+            new("dec", 1, 4 + hl_penalty(y, 8)).operand(register_8bit(y))
+        }
+        0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e => new("ld", 2, 8 + hl_penalty(y, 4))
+            .operand(register_8bit(y))
+            .operand(Immediate8(immediate_u8)),
+        0x07 => new("rlca", 1, 4),
+        0x0f => new("rrca", 1, 4),
+        0x17 => new("rla", 1, 4),
+        0x1f => new("rra", 1, 4),
+        0x27 => new("daa", 1, 4),
+        0x2f => new("cpl", 1, 4),
+        0x37 => new("scf", 1, 4),
+        0x3f => new("ccf", 1, 4),
+        0x08 => new("ld", 3, 20)
+            .operand(Address(immediate_u16))
+            .operand(Register("sp")),
+        0x10 => new("stop", 2, 4),
+        0x18 => new("jr", 2, 12).operand(Target(relative_target)),
+        0x20 | 0x28 | 0x30 | 0x38 => new("jr", 2, 12)
+            .not_taken(8)
+            .operand(condition)
+            .operand(Target(relative_target)),
+        0x76 => new("halt", 1, 4),
+        0x40..=0x7f => new("ld", 1, 4 + hl_penalty(y, 4) + hl_penalty(z, 4))
+            .operand(register_8bit(y))
+            .operand(register_8bit(z)),
+        0x80..=0xbf => alu(
+            new(ALU_MNEMONICS[y], 1, 4 + hl_penalty(z, 4)),
+            register_8bit(z),
+        ),
+        0xc6 | 0xce | 0xd6 | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe => {
+            alu(new(ALU_MNEMONICS[y], 2, 8), Immediate8(immediate_u8))
+        }
+        0xc0 | 0xc8 | 0xd0 | 0xd8 => new("ret", 1, 20).not_taken(8).operand(condition),
+        0xc2 | 0xca | 0xd2 | 0xda => new("jp", 3, 16)
+            .not_taken(12)
+            .operand(condition)
+            .operand(Target(immediate_u16)),
+        0xc4 | 0xcc | 0xd4 | 0xdc => new("call", 3, 24)
+            .not_taken(12)
+            .operand(condition)
+            .operand(Target(immediate_u16)),
+        0xc1 | 0xd1 | 0xe1 | 0xf1 => new("pop", 1, 12).operand(Register(STACK_REGISTERS[pair])),
+        0xc5 | 0xd5 | 0xe5 | 0xf5 => new("push", 1, 16).operand(Register(STACK_REGISTERS[pair])),
+        0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => {
+            new("rst", 1, 16).operand(Vector(opcode & 0x38))
+        }
+        0xc3 => new("jp", 3, 16).operand(Target(immediate_u16)),
+        0xc9 => new("ret", 1, 16),
+        0xd9 => new("reti", 1, 16),
+        0xcd => new("call", 3, 24).operand(Target(immediate_u16)),
+        0xe0 => new("ldh", 2, 12)
+            .operand(Address(0xff00 | u16::from(immediate_u8)))
+            .operand(Register("a")),
+        0xf0 => new("ldh", 2, 12)
+            .operand(Register("a"))
+            .operand(Address(0xff00 | u16::from(immediate_u8))),
+        0xe2 => new("ldh", 1, 8)
+            .operand(Indirect("[c]"))
+            .operand(Register("a")),
+        0xf2 => new("ldh", 1, 8)
+            .operand(Register("a"))
+            .operand(Indirect("[c]")),
+        0xe8 => new("add", 2, 16)
+            .operand(Register("sp"))
+            .operand(Offset(immediate_u8 as i8)),
+        0xe9 => new("jp", 1, 4).operand(Register("hl")),
+        0xea => new("ld", 3, 16)
+            .operand(Address(immediate_u16))
+            .operand(Register("a")),
+        0xfa => new("ld", 3, 16)
+            .operand(Register("a"))
+            .operand(Address(immediate_u16)),
+        0xf3 => new("di", 1, 4),
+        0xfb => new("ei", 1, 4),
+        0xf8 => new("ld", 2, 12)
+            .operand(Register("hl"))
+            .operand(StackOffset(immediate_u8 as i8)),
+        0xf9 => new("ld", 1, 8)
+            .operand(Register("sp"))
+            .operand(Register("hl")),
+        0xcb | 0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb | 0xec | 0xed | 0xf4 | 0xfc | 0xfd => {
+            invalid(opcode, address)
+        }
+    }
+}
+
+// RGBDS writes the accumulator explicitly for add, adc and sbc only.
+fn alu(instruction: Instruction, operand: Operand) -> Instruction {
+    match instruction.mnemonic {
+        "add" | "adc" | "sbc" => instruction.operand(Operand::Register("a")).operand(operand),
+        _ => instruction.operand(operand),
+    }
+}
+
+fn decode_cb(cb_opcode: u8, address: u16) -> Instruction {
+    let y = (cb_opcode >> 3) & 0x07;
+    let z = usize::from(cb_opcode & 0x07);
+
+    let operand = if z == 6 {
+        Operand::Indirect(REGISTERS_8BIT[z])
+    } else {
+        Operand::Register(REGISTERS_8BIT[z])
+    };
+
+    let (mnemonic, bit) = match cb_opcode {
+        0x00..=0x3f => (ROTATE_MNEMONICS[usize::from(y)], None),
+        0x40..=0x7f => ("bit", Some(y)),
+        0x80..=0xbf => ("res", Some(y)),
+        0xc0..=0xff => ("set", Some(y)),
+    };
+
+    // bit only reads (HL), so it's faster than the instructions that also write it back.
+    let cycles = match (z, mnemonic) {
+        (6, "bit") => 12,
+        (6, _) => 16,
+        _ => 8,
+    };
+
+    let instruction = Instruction::new(address, mnemonic, 2, cycles);
+    match bit {
+        Some(bit) => instruction.operand(Operand::Bit(bit)).operand(operand),
+        None => instruction.operand(operand),
+    }
+}
+
+/// Labels loaded from an RGBDS symbol file (.sym), keyed by bank and address.
+#[derive(Default)]
+pub struct SymbolTable {
+    symbols: HashMap<(u8, u16), String>,
+}
+
+impl SymbolTable {
+    /// Parse lines in the form "BB:AAAA Name". Comments (;) and malformed lines are ignored.
+    pub fn parse(text: &str) -> Self {
+        let mut symbols = HashMap::new();
+
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            let Some((location, name)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let Some((bank, address)) = location.split_once(':') else {
+                continue;
+            };
+
+            if let (Ok(bank), Ok(address)) = (
+                u8::from_str_radix(bank, 16),
+                u16::from_str_radix(address, 16),
+            ) {
+                // Keep the first label at each location, which is usually the global one.
+                symbols
+                    .entry((bank, address))
+                    .or_insert_with(|| name.trim().to_owned());
+            }
+        }
+
+        Self { symbols }
+    }
+
+    /// Find the label for address, with `bank` as the ROM bank mapped at 0x4000-0x7fff.
+    pub fn lookup(&self, bank: u8, address: u16) -> Option<&str> {
+        let bank = if (0x4000..0x8000).contains(&address) {
+            bank
+        } else {
+            0
+        };
+        self.symbols.get(&(bank, address)).map(String::as_str)
+    }
+}
+
+/// Disassemble a whole ROM bank into an RGBDS-style listing, with labels from `symbols` if given.
+/// Decoding is a linear sweep, so data inside the bank will be shown as instructions.
+pub fn disassemble_bank(rom: &[u8], bank: u8, symbols: Option<&SymbolTable>) -> String {
+    let bank_start = usize::from(bank) * ROM_BANK_SIZE;
+    let bank_end = (bank_start + ROM_BANK_SIZE).min(rom.len());
+    let bank_bytes = rom.get(bank_start..bank_end).unwrap_or_default();
+
+    // Bank 0 is always mapped at 0x0000, and every other bank at 0x4000.
+    let base_address: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+    let empty_symbols = SymbolTable::default();
+    let symbols = symbols.unwrap_or(&empty_symbols);
+
+    let mut listing = if bank == 0 {
+        "SECTION \"ROM bank 0\", ROM0[$0000]\n".to_owned()
+    } else {
+        format!(
+            "SECTION \"ROM bank {}\", ROMX[$4000], BANK[{}]\n",
+            bank, bank
+        )
+    };
+
+    let mut offset = 0;
+    while offset < bank_bytes.len() {
+        let address = base_address + offset as u16;
+
+        if let Some(label) = symbols.lookup(bank, address) {
+            listing += &format!("{}:\n", label);
+        }
+
+        let instruction = decode(&bank_bytes[offset..], address);
+        let encoding: Vec<String> = bank_bytes[offset..offset + usize::from(instruction.length)]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        listing += &format!(
+            "    {:<24}; {:02x}:{:04x} {}\n",
+            instruction.format_with_symbols(symbols, bank),
+            bank,
+            address,
+            encoding.join(" ")
+        );
+
+        offset += usize::from(instruction.length);
+    }
+
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let instruction = decode(&[0x3e, 0x05], 0x0100);
+        assert_eq!(instruction.to_string(), "ld a, $05");
+        assert_eq!(instruction.length, 2);
+        assert_eq!(instruction.cycles, 8);
+
+        assert_eq!(decode(&[0x2a], 0).to_string(), "ld a, [hl+]");
+        assert_eq!(decode(&[0x36, 0x7f], 0).to_string(), "ld [hl], $7f");
+        assert_eq!(decode(&[0xe0, 0x44], 0).to_string(), "ldh [$ff44], a");
+        assert_eq!(decode(&[0xe2], 0).to_string(), "ldh [c], a");
+        assert_eq!(decode(&[0xf8, 0xfe], 0).to_string(), "ld hl, sp - 2");
+        assert_eq!(decode(&[0xe8, 0x10], 0).to_string(), "add sp, 16");
+        assert_eq!(decode(&[0x86], 0).to_string(), "add a, [hl]");
+        assert_eq!(decode(&[0xa8], 0).to_string(), "xor b");
+        assert_eq!(decode(&[0xff], 0).to_string(), "rst $38");
+        assert_eq!(decode(&[0xd3], 0).to_string(), "db $d3");
+    }
+
+    #[test]
+    fn test_decode_branches() {
+        let jr = decode(&[0x20, 0xfe], 0x0150);
+        assert_eq!(jr.to_string(), "jr nz, $0150");
+        assert_eq!((jr.cycles, jr.cycles_not_taken), (12, Some(8)));
+
+        let call = decode(&[0xcd, 0x34, 0x12], 0x0150);
+        assert_eq!(call.to_string(), "call $1234");
+        assert_eq!((call.cycles, call.cycles_not_taken), (24, None));
+
+        let ret = decode(&[0xd8], 0);
+        assert_eq!(ret.to_string(), "ret c");
+        assert_eq!((ret.cycles, ret.cycles_not_taken), (20, Some(8)));
+    }
+
+    #[test]
+    fn test_decode_cb() {
+        let swap = decode(&[0xcb, 0x37], 0);
+        assert_eq!(swap.to_string(), "swap a");
+        assert_eq!((swap.length, swap.cycles), (2, 8));
+
+        let bit = decode(&[0xcb, 0x7e], 0);
+        assert_eq!(bit.to_string(), "bit 7, [hl]");
+        assert_eq!(bit.cycles, 12);
+
+        let res = decode(&[0xcb, 0x86], 0);
+        assert_eq!(res.to_string(), "res 0, [hl]");
+        assert_eq!(res.cycles, 16);
+    }
+
+    #[test]
+    fn test_truncated_instruction() {
+        assert_eq!(decode(&[0xc3, 0x00], 0).to_string(), "db $c3");
+        assert_eq!(decode(&[0xcb], 0).to_string(), "db $cb");
+    }
+
+    #[test]
+    fn test_every_opcode_decodes() {
+        for opcode in 0x00..=0xff {
+            let instruction = decode(&[opcode, 0x00, 0x00], 0);
+            assert!((1..=3).contains(&instruction.length));
+            let cb_instruction = decode(&[0xcb, opcode], 0);
+            assert_eq!(cb_instruction.length, 2);
+        }
+    }
+
+    #[test]
+    fn test_symbols() {
+        let symbols = SymbolTable::parse(
+            "; File generated by rgblink\n00:0150 Main\n02:4000 BankedRoutine\n00:c000 wCounter\n",
+        );
+
+        let call = decode(&[0xcd, 0x00, 0x40], 0x0150);
+        assert_eq!(call.format_with_symbols(&symbols, 2), "call BankedRoutine");
+        assert_eq!(call.format_with_symbols(&symbols, 1), "call $4000");
+
+        let load = decode(&[0xfa, 0x00, 0xc0], 0x0150);
+        assert_eq!(load.format_with_symbols(&symbols, 2), "ld a, [wCounter]");
+    }
+
+    #[test]
+    fn test_disassemble_bank() {
+        let mut rom = vec![0; ROM_BANK_SIZE * 2];
+        rom[0x0150..0x0153].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        let symbols = SymbolTable::parse("00:0150 Main");
+
+        let listing = disassemble_bank(&rom, 0, Some(&symbols));
+        assert!(listing.contains("Main:\n    jp Main"));
+        assert!(listing.contains("; 00:0150 c3 50 01"));
+    }
+}
@@ -0,0 +1,376 @@
+// An environment for reinforcement learning, in the style of Gym: reset() to a stored start state, then step()
+// with the buttons to hold and for how many frames, getting back an observation and a reward. VecEnv runs a
+// batch of environments across threads, each starting from a cheap clone of the same GameBoy.
+
+use crate::lcd::Lcd;
+use crate::{Button, GameBoy};
+use std::sync::Arc;
+use std::thread;
+
+const ALL_BUTTONS: [Button; 8] = [
+    Button::A,
+    Button::B,
+    Button::START,
+    Button::SELECT,
+    Button::UP,
+    Button::DOWN,
+    Button::LEFT,
+    Button::RIGHT,
+];
+
+/// A copy of memory from 0x8000 to 0xffff (VRAM, external RAM, work RAM, OAM, IO registers and high RAM), for
+/// reading game variables from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RamView {
+    bytes: Vec<u8>,
+}
+
+impl RamView {
+    pub const START: u16 = 0x8000;
+
+    fn new(game_boy: &GameBoy) -> Self {
+        Self {
+            bytes: (Self::START..=0xffff)
+                .map(|address| game_boy.peek(address))
+                .collect(),
+        }
+    }
+
+    /// Panics if address is below RamView::START.
+    pub fn read(&self, address: u16) -> u8 {
+        self.bytes[usize::from(address - Self::START)]
+    }
+
+    /// A little-endian 16-bit value.
+    pub fn read_u16(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read(address), self.read(address + 1)])
+    }
+
+    /// All of it, starting from RamView::START.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Computes a step's reward from RAM before and after the step.
+pub type RewardFn = Arc<dyn Fn(&RamView, &RamView) -> f32 + Send + Sync>;
+
+/// Decides whether the episode is over, from RAM after a step.
+pub type DoneFn = Arc<dyn Fn(&RamView) -> bool + Send + Sync>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observation {
+    /// The latest frame in the GameBoy's pixel format, downsampled if the Env was set up to.
+    pub frame: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub ram: RamView,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepResult {
+    pub observation: Observation,
+    pub reward: f32,
+    pub done: bool,
+}
+
+// Averages each channel over downsample x downsample blocks. Pixels past the last whole block are dropped.
+fn downsample_frame(
+    frame: &[u8],
+    bytes_per_pixel: usize,
+    downsample: usize,
+) -> (Vec<u8>, usize, usize) {
+    let width = Lcd::WIDTH / downsample;
+    let height = Lcd::HEIGHT / downsample;
+    let mut output = Vec::with_capacity(width * height * bytes_per_pixel);
+
+    for y in 0..height {
+        for x in 0..width {
+            for channel in 0..bytes_per_pixel {
+                let mut total = 0;
+                for block_y in 0..downsample {
+                    for block_x in 0..downsample {
+                        let pixel =
+                            (y * downsample + block_y) * Lcd::WIDTH + x * downsample + block_x;
+                        total += usize::from(frame[pixel * bytes_per_pixel + channel]);
+                    }
+                }
+                output.push((total / (downsample * downsample)) as u8);
+            }
+        }
+    }
+
+    (output, width, height)
+}
+
+#[derive(Clone)]
+pub struct Env {
+    start: GameBoy,
+    game_boy: GameBoy,
+    downsample: usize,
+    reward: Option<RewardFn>,
+    done: Option<DoneFn>,
+}
+
+impl Env {
+    /// The environment resets to a clone of start, which can be set up beforehand, e.g. by loading past a
+    /// game's title screen, or by setting its pixel format.
+    pub fn new(start: GameBoy) -> Self {
+        Self {
+            game_boy: start.clone(),
+            start,
+            downsample: 1,
+            reward: None,
+            done: None,
+        }
+    }
+
+    /// Shrink observed frames by this factor in each direction, averaging blocks of pixels. 1 leaves them alone.
+    pub fn with_downsample(mut self, downsample: usize) -> Self {
+        self.downsample = downsample.max(1);
+        self
+    }
+
+    /// Without a reward hook, every reward is 0.
+    pub fn with_reward(mut self, reward: RewardFn) -> Self {
+        self.reward = Some(reward);
+        self
+    }
+
+    /// Without a done hook, episodes never end by themselves.
+    pub fn with_done(mut self, done: DoneFn) -> Self {
+        self.done = Some(done);
+        self
+    }
+
+    pub fn game_boy(&self) -> &GameBoy {
+        &self.game_boy
+    }
+
+    /// Make the current state the one reset() goes back to.
+    pub fn store_start_state(&mut self) {
+        self.start = self.game_boy.clone();
+    }
+
+    pub fn reset(&mut self) -> Observation {
+        self.game_boy = self.start.clone();
+        self.observe(RamView::new(&self.game_boy))
+    }
+
+    /// Hold exactly the buttons in action_set for frames_to_hold frames.
+    pub fn step(&mut self, action_set: &[Button], frames_to_hold: u32) -> StepResult {
+        let ram_before = RamView::new(&self.game_boy);
+
+        for button in &ALL_BUTTONS {
+            self.game_boy
+                .set_button(button, action_set.contains(button));
+        }
+        for _ in 0..frames_to_hold {
+            self.game_boy.emulate_next_frame();
+        }
+
+        let ram = RamView::new(&self.game_boy);
+        let reward = self
+            .reward
+            .as_ref()
+            .map_or(0.0, |reward| reward(&ram_before, &ram));
+        let done = self.done.as_ref().is_some_and(|done| done(&ram));
+
+        StepResult {
+            observation: self.observe(ram),
+            reward,
+            done,
+        }
+    }
+
+    fn observe(&self, ram: RamView) -> Observation {
+        let frame = self.game_boy.frame();
+
+        let (frame, width, height) = if self.downsample == 1 {
+            (frame.to_vec(), Lcd::WIDTH, Lcd::HEIGHT)
+        } else {
+            let bytes_per_pixel = self.game_boy.lcd.pixel_format().bytes_per_pixel();
+            downsample_frame(frame, bytes_per_pixel, self.downsample)
+        };
+
+        Observation {
+            frame,
+            width,
+            height,
+            ram,
+        }
+    }
+}
+
+/// A batch of environments, stepped in parallel.
+pub struct VecEnv {
+    envs: Vec<Env>,
+    thread_count: usize,
+}
+
+impl VecEnv {
+    /// count clones of env. The work is split across as many threads as the machine has cores.
+    pub fn new(env: Env, count: usize) -> Self {
+        let thread_count = thread::available_parallelism().map_or(1, |count| count.get());
+        Self {
+            envs: vec![env; count],
+            thread_count,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.envs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.envs.is_empty()
+    }
+
+    pub fn envs(&self) -> &[Env] {
+        &self.envs
+    }
+
+    pub fn envs_mut(&mut self) -> &mut [Env] {
+        &mut self.envs
+    }
+
+    // Runs f on every env, with its index, across the threads, and collects the results in order.
+    fn map_parallel<T: Send>(&mut self, f: impl Fn(usize, &mut Env) -> T + Sync) -> Vec<T> {
+        let chunk_size = self.envs.len().div_ceil(self.thread_count).max(1);
+        let f = &f;
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .envs
+                .chunks_mut(chunk_size)
+                .enumerate()
+                .map(|(chunk_index, chunk)| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter_mut()
+                            .enumerate()
+                            .map(|(index, env)| f(chunk_index * chunk_size + index, env))
+                            .collect::<Vec<T>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    pub fn reset(&mut self) -> Vec<Observation> {
+        self.map_parallel(|_, env| env.reset())
+    }
+
+    /// Steps each environment with its own action set. Environments aren't reset when they're done; call
+    /// reset_env() for those.
+    pub fn step(&mut self, action_sets: &[Vec<Button>], frames_to_hold: u32) -> Vec<StepResult> {
+        assert_eq!(
+            action_sets.len(),
+            self.envs.len(),
+            "Expected an action set for each environment"
+        );
+        self.map_parallel(|index, env| env.step(&action_sets[index], frames_to_hold))
+    }
+
+    pub fn reset_env(&mut self, index: usize) -> Observation {
+        self.envs[index].reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads the joypad into 0xc000 in a loop, and counts frames in 0xc001 via the V-blank interrupt.
+    // 0x0040: INC (HL) ; RETI
+    // 0x0100: LD HL,0xc001; XOR A; LDH (0x0f),A; INC A; LDH (0xff),A; EI; LD A,0x10; LDH (0x00),A
+    //  loop:  LDH A,(0x00); LD (0xc000),A; JR loop
+    fn game_boy() -> GameBoy {
+        let program = [
+            0x21, 0x01, 0xc0, 0xaf, 0xe0, 0x0f, 0x3c, 0xe0, 0xff, 0xfb, 0x3e, 0x10, 0xe0, 0x00,
+            0xf0, 0x00, 0xea, 0x00, 0xc0, 0x18, 0xf9,
+        ];
+        let mut rom = crate::test_rom(&program);
+        rom[0x0040..0x0042].copy_from_slice(&[0x34, 0xd9]);
+        GameBoy::new(&rom)
+    }
+
+    fn frame_count_reward() -> RewardFn {
+        Arc::new(|before: &RamView, after: &RamView| {
+            f32::from(after.read(0xc001).wrapping_sub(before.read(0xc001)))
+        })
+    }
+
+    #[test]
+    fn test_env() {
+        let mut env = Env::new(game_boy())
+            .with_downsample(4)
+            .with_reward(frame_count_reward())
+            .with_done(Arc::new(|ram: &RamView| ram.read(0xc001) >= 10));
+
+        let observation = env.reset();
+        assert_eq!((observation.width, observation.height), (40, 36));
+        assert_eq!(observation.frame.len(), 40 * 36);
+
+        let result = env.step(&[Button::A], 4);
+        assert_eq!(result.reward, 4.0);
+        assert!(!result.done);
+        // The A button reads as 0 in the low bit.
+        assert_eq!(result.observation.ram.read(0xc000) & 0x01, 0x00);
+
+        let result = env.step(&[], 6);
+        assert!(result.done);
+        assert_eq!(result.observation.ram.read(0xc000) & 0x01, 0x01);
+
+        // Resetting goes back to the start.
+        assert_eq!(env.reset().ram.read(0xc001), 0);
+    }
+
+    #[test]
+    fn test_vec_env() {
+        let env = Env::new(game_boy()).with_reward(frame_count_reward());
+        let mut envs = VecEnv::new(env, 5);
+        envs.reset();
+
+        let action_sets: Vec<Vec<Button>> = (0..5)
+            .map(|index| {
+                if index % 2 == 0 {
+                    vec![Button::A]
+                } else {
+                    vec![]
+                }
+            })
+            .collect();
+        let results = envs.step(&action_sets, 3);
+
+        assert_eq!(results.len(), 5);
+        for (index, result) in results.iter().enumerate() {
+            assert_eq!(result.reward, 3.0);
+            let a_is_down = result.observation.ram.read(0xc000) & 0x01 == 0;
+            assert_eq!(a_is_down, index % 2 == 0);
+        }
+
+        // Each environment matches the same steps run on its own.
+        let mut single = Env::new(game_boy());
+        single.reset();
+        let expected = single.step(&[Button::A], 3);
+        assert_eq!(results[0].observation, expected.observation);
+    }
+
+    #[test]
+    fn test_downsample() {
+        // Averages each channel separately.
+        let mut frame = vec![0; Lcd::PIXEL_COUNT * 2];
+        frame[0] = 40;
+        frame[1] = 8;
+        frame[Lcd::WIDTH * 2 + 2] = 40;
+        let (output, width, height) = downsample_frame(&frame, 2, 2);
+        assert_eq!((width, height), (80, 72));
+        assert_eq!(&output[..4], [20, 2, 0, 0]);
+    }
+}
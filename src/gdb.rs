@@ -0,0 +1,526 @@
+// A GDB remote serial protocol (RSP) stub, so that gdb, lldb and other front ends can debug a
+// running ROM over TCP. Only one client is served at a time.
+//
+// Breakpoint addresses above 0xffff are bank-qualified: 0x34000 is address 0x4000 in ROM bank 3.
+
+use crate::debugger::{Breakpoint, StopReason, WatchKind, Watchpoint, WatchpointHit};
+use crate::{GameBoy, Register};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// The order that registers appear in 'g' packets and in the target description.
+const REGISTERS: [(Register, &str, usize); 10] = [
+    (Register::A, "a", 8),
+    (Register::F, "f", 8),
+    (Register::B, "b", 8),
+    (Register::C, "c", 8),
+    (Register::D, "d", 8),
+    (Register::E, "e", 8),
+    (Register::H, "h", 8),
+    (Register::L, "l", 8),
+    (Register::SP, "sp", 16),
+    (Register::PC, "pc", 16),
+];
+
+const SIGINT: u8 = 0x02;
+const SIGTRAP: u8 = 0x05;
+const INTERRUPT_BYTE: u8 = 0x03; // Sent by the client (Ctrl-C) to stop a running target.
+
+// Lengths in memory and watchpoint packets are clamped to this, as nothing longer fits in the address space.
+const ADDRESS_SPACE_SIZE: u32 = 0x10000;
+
+// The largest packet, advertised in qSupported. Memory reads are clamped to fit, at 2 hex digits per byte.
+const PACKET_SIZE: u32 = 0x4000;
+
+/// Accept one client on the listener and serve it until it detaches, kills the target or disconnects.
+pub fn serve(game_boy: &mut GameBoy, listener: &TcpListener) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+
+    let mut stub = GdbStub { game_boy, stream };
+    stub.run()
+}
+
+struct GdbStub<'a> {
+    game_boy: &'a mut GameBoy,
+    stream: TcpStream,
+}
+
+impl GdbStub<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'D') => {
+                    self.send_packet("OK")?;
+                    return Ok(());
+                }
+                Some(b'k') => return Ok(()),
+                Some(b'c') => self.resume(&packet[1..], false)?,
+                Some(b's') => self.resume(&packet[1..], true)?,
+                _ => self.handle_query(&packet),
+            };
+
+            self.send_packet(&reply)?;
+        }
+
+        Ok(())
+    }
+
+    // Handles every packet that doesn't resume or end the session. Unsupported packets get an empty reply.
+    fn handle_query(&mut self, packet: &str) -> String {
+        let command = packet.get(..1).unwrap_or_default();
+        let args = packet.get(1..).unwrap_or_default();
+
+        let reply = match command {
+            "?" => Some(stop_reply_signal(SIGTRAP)),
+            "g" => Some(self.read_registers()),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" => self.set_stop_point(args, true),
+            "z" => self.set_stop_point(args, false),
+            "H" | "T" => Some("OK".to_owned()),
+            "q" => self.handle_general_query(args),
+            _ => Some(String::new()),
+        };
+
+        reply.unwrap_or_else(|| "E01".to_owned())
+    }
+
+    fn handle_general_query(&self, args: &str) -> Option<String> {
+        if args.starts_with("Supported") {
+            Some(format!(
+                "PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+",
+                PACKET_SIZE
+            ))
+        } else if args == "Attached" {
+            Some("1".to_owned())
+        } else if let Some(annex) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, length) = annex.split_once(',')?;
+            let offset = usize::from_str_radix(offset, 16).ok()?;
+            let length = usize::from_str_radix(length, 16).ok()?;
+            Some(xfer_chunk(&target_description(), offset, length))
+        } else {
+            Some(String::new())
+        }
+    }
+
+    fn resume(&mut self, args: &str, single_step: bool) -> io::Result<String> {
+        // An optional address to resume from.
+        if !args.is_empty() {
+            match parse_hex(args) {
+                Some(address) => self.game_boy.set_register(Register::PC, address as u16),
+                None => return Ok("E01".to_owned()),
+            }
+        }
+
+        if single_step {
+            let reason = self.game_boy.step_instruction();
+            return Ok(stop_reply(reason));
+        }
+
+        // Run whole frames, checking for an interrupt from the client between them.
+        loop {
+            let reason = self.game_boy.emulate_next_frame();
+            if reason != StopReason::FrameComplete {
+                return Ok(stop_reply(reason));
+            }
+
+            if self.interrupt_requested()? {
+                return Ok(stop_reply_signal(SIGINT));
+            }
+        }
+    }
+
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0u8];
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(byte[0] == INTERRUPT_BYTE),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        REGISTERS
+            .iter()
+            .map(|(register, _, bits)| encode_register(self.game_boy.register(*register), *bits))
+            .collect()
+    }
+
+    fn write_registers(&mut self, hex: &str) -> Option<String> {
+        let bytes = decode_hex(hex)?;
+        let mut bytes = bytes.iter();
+
+        for (register, _, bits) in REGISTERS {
+            let value = if bits == 8 {
+                u16::from(*bytes.next()?)
+            } else {
+                u16::from_le_bytes([*bytes.next()?, *bytes.next()?])
+            };
+            self.game_boy.set_register(register, value);
+        }
+
+        Some("OK".to_owned())
+    }
+
+    fn read_register(&self, args: &str) -> Option<String> {
+        let (register, _, bits) = REGISTERS.get(parse_hex(args)? as usize)?;
+        Some(encode_register(self.game_boy.register(*register), *bits))
+    }
+
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (index, value) = args.split_once('=')?;
+        let (register, _, _) = REGISTERS.get(parse_hex(index)? as usize)?;
+
+        // Values are sent in target byte order (little-endian).
+        let bytes = decode_hex(value)?;
+        let value = bytes
+            .iter()
+            .rev()
+            .fold(0u16, |value, byte| (value << 8) | u16::from(*byte));
+        self.game_boy.set_register(*register, value);
+
+        Some("OK".to_owned())
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (address, length) = args.split_once(',')?;
+        let address = parse_hex(address)? as u16;
+        let length = parse_hex(length)?.min(PACKET_SIZE / 2);
+
+        let reply = (0..length)
+            .map(|offset| {
+                format!(
+                    "{:02x}",
+                    self.game_boy.peek(address.wrapping_add(offset as u16))
+                )
+            })
+            .collect();
+        Some(reply)
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (location, data) = args.split_once(':')?;
+        let (address, length) = location.split_once(',')?;
+        let address = parse_hex(address)? as u16;
+        let bytes = decode_hex(data)?;
+
+        if bytes.len() != parse_hex(length)? as usize {
+            return None;
+        }
+
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.game_boy
+                .poke(address.wrapping_add(offset as u16), *byte);
+        }
+
+        Some("OK".to_owned())
+    }
+
+    // Handles "Z" (insert) and "z" (remove) packets, in the form "type,address,kind".
+    fn set_stop_point(&mut self, args: &str, insert: bool) -> Option<String> {
+        let mut fields = args.split(',');
+        let point_type = fields.next()?;
+        let address = parse_hex(fields.next()?)?;
+        let length = parse_hex(fields.next()?)?;
+
+        let watch_kind = match point_type {
+            "0" | "1" => {
+                let bank = address >> 16;
+                let breakpoint = Breakpoint {
+                    address: address as u16,
+                    bank: if bank == 0 { None } else { Some(bank as u8) },
+                };
+
+                if insert {
+                    self.game_boy.add_breakpoint(breakpoint);
+                } else {
+                    self.game_boy.remove_breakpoint(breakpoint);
+                }
+
+                return Some("OK".to_owned());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Some(String::new()),
+        };
+
+        for offset in 0..length.clamp(1, ADDRESS_SPACE_SIZE) {
+            let watchpoint = Watchpoint {
+                address: (address as u16).wrapping_add(offset as u16),
+                kind: watch_kind,
+            };
+
+            if insert {
+                self.game_boy.add_watchpoint(watchpoint);
+            } else {
+                self.game_boy.remove_watchpoint(watchpoint);
+            }
+        }
+
+        Some("OK".to_owned())
+    }
+
+    // Returns None when the client disconnects.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acknowledgements and stray interrupts until the start of a packet.
+            let mut byte = [0u8];
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = vec![];
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+            if expected == Some(checksum_of(&data)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+
+            // Ask the client to retransmit.
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+
+        // Resend until the client acknowledges the packet.
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+
+            let mut ack = [0u8];
+            loop {
+                if self.stream.read(&mut ack)? == 0 {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                match ack[0] {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint(WatchpointHit { watchpoint, .. }) => {
+            let name = match watchpoint.kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, name, watchpoint.address)
+        }
+        StopReason::Breakpoint(_) => format!("T{:02x}swbreak:;", SIGTRAP),
+        _ => stop_reply_signal(SIGTRAP),
+    }
+}
+
+fn stop_reply_signal(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n<feature name=\"org.gnu.gdb.sm83.core\">\n",
+    );
+
+    for (_, name, bits) in REGISTERS {
+        let reg_type = match name {
+            "sp" => "data_ptr",
+            "pc" => "code_ptr",
+            _ => "uint8",
+        };
+        xml += &format!(
+            "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>\n",
+            name, bits, reg_type
+        );
+    }
+
+    xml + "</feature>\n</target>\n"
+}
+
+// Replies to qXfer reads are prefixed with 'm' if there's more data to read, or 'l' for the last chunk.
+fn xfer_chunk(document: &str, offset: usize, length: usize) -> String {
+    let start = offset.min(document.len());
+    let end = offset.saturating_add(length).min(document.len());
+    let prefix = if end < document.len() { 'm' } else { 'l' };
+    format!("{}{}", prefix, &document[start..end])
+}
+
+fn encode_register(value: u16, bits: usize) -> String {
+    let bytes = value.to_le_bytes();
+    bytes[..bits / 8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom;
+    use std::thread;
+
+    // LD A,5; LD (0xc000),A; loop: INC A; JR loop
+    const PROGRAM: [u8; 8] = [0x3e, 0x05, 0xea, 0x00, 0xc0, 0x3c, 0x18, 0xfd];
+
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn connect() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+
+            thread::spawn(move || {
+                let mut game_boy = GameBoy::new(&test_rom(&PROGRAM));
+                serve(&mut game_boy, &listener).unwrap();
+            });
+
+            Self {
+                stream: TcpStream::connect(address).unwrap(),
+            }
+        }
+
+        // Sends a packet and returns the reply's data.
+        fn request(&mut self, data: &str) -> String {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+
+            let mut ack = [0u8];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+
+            let mut reply = vec![];
+            let mut byte = [0u8];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+
+            assert_eq!(reply[0], b'$');
+            String::from_utf8(reply[1..].to_vec()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut client = Client::connect();
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request("g"), "01b0001300d8014dfeff0001");
+        assert_eq!(client.request("p9"), "0001");
+
+        assert_eq!(client.request("P0=42"), "OK");
+        assert_eq!(client.request("p0"), "42");
+        assert_eq!(client.request("D"), "OK");
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut client = Client::connect();
+        assert_eq!(client.request("m100,3"), "3e05ea");
+        assert_eq!(client.request("Mc000,2:abcd"), "OK");
+        assert_eq!(client.request("mc000,2"), "abcd");
+
+        // Reads are cut short to fit in a packet, and addresses past the end of the address space wrap around
+        // rather than overflowing.
+        assert_eq!(client.request("mffff,ffffffff").len(), 0x4000);
+        assert_eq!(client.request("mffff,2").len(), 4);
+        assert_eq!(client.request("Mffff,2:1234"), "OK");
+        assert_eq!(client.request("m0,1"), "34");
+        assert_eq!(client.request("Z2,ffff,ffffffff"), "OK");
+        assert_eq!(client.request("D"), "OK");
+    }
+
+    #[test]
+    fn test_step_and_breakpoint() {
+        let mut client = Client::connect();
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p9"), "0201");
+
+        assert_eq!(client.request("Z0,105,1"), "OK");
+        assert_eq!(client.request("c"), "T05swbreak:;");
+        assert_eq!(client.request("p9"), "0501");
+        assert_eq!(client.request("z0,105,1"), "OK");
+        assert_eq!(client.request("D"), "OK");
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut client = Client::connect();
+        assert_eq!(client.request("Z2,c000,1"), "OK");
+        assert_eq!(client.request("c"), "T05watch:c000;");
+        assert_eq!(client.request("p0"), "05");
+        assert_eq!(client.request("D"), "OK");
+    }
+
+    #[test]
+    fn test_target_description() {
+        let mut client = Client::connect();
+        let reply = client.request("qXfer:features:read:target.xml:0,fff");
+        assert!(reply.starts_with('l'));
+        assert!(reply.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+        assert_eq!(xfer_chunk("abc", 1, usize::MAX), "lbc");
+        assert_eq!(client.request("D"), "OK");
+    }
+}
@@ -1,9 +1,15 @@
 use crate::address;
 use crate::interrupt;
+use crate::state;
 use crate::Memory;
 
+mod output;
 mod render;
+mod vram;
+pub use output::{Palette, PixelFormat};
+pub use render::RenderOptions;
 use render::Renderer;
+pub use vram::{oam, Image, OamEntry, TileMap};
 
 /* TODO: "Each bit is set to 1 automatically when an internal signal from that subsystem goes from '0' to '1', it doesn't matter if the corresponding bit in IE is set. This is specially important in the case of LCD STAT interrupt, as it will be explained in the video controller chapter." */
 
@@ -22,9 +28,21 @@ This signal is set to 1 if:
 -If LCD is off, the signal is 0.
 -It seems that this interrupt needs less time to execute in DMG than in CGB? -DMG bug?"
 */
+/// Called with LY and the pixels of each line, in the output pixel format, as soon as it's rendered.
+/// Changes to the pixels are kept.
+pub type ScanlineCallback = Box<dyn FnMut(u8, &mut [u8]) + Send>;
+
 pub struct Lcd {
     renderer: Renderer,
     elapsed_cycles: u32, // rwtodo if this needs to be i32, fine.
+
+    // Lines are rendered into the back buffer, which becomes the front buffer on entering V-blank.
+    front_buffer: Vec<u8>,
+    back_buffer: Vec<u8>,
+    pixel_format: PixelFormat,
+    palette: Palette,
+    pub frame_ready: bool,
+    pub scanline_callback: Option<ScanlineCallback>,
 }
 
 impl Lcd {
@@ -37,11 +55,106 @@ impl Lcd {
             // rwtodo: Not sure what the shades should initialize to.
             renderer: render::Renderer::new(),
             elapsed_cycles: 0,
+            front_buffer: vec![Self::WHITE; Self::PIXEL_COUNT],
+            back_buffer: vec![Self::WHITE; Self::PIXEL_COUNT],
+            pixel_format: PixelFormat::Gray8,
+            palette: Palette::GRAYSCALE,
+            frame_ready: false,
+            scanline_callback: None,
+        }
+    }
+
+    const WHITE: u8 = 0xff;
+
+    // Clones everything but the scanline callback, which belongs to whoever set it.
+    pub fn clone_without_callback(&self) -> Self {
+        Self {
+            renderer: self.renderer.clone(),
+            elapsed_cycles: self.elapsed_cycles,
+            front_buffer: self.front_buffer.clone(),
+            back_buffer: self.back_buffer.clone(),
+            pixel_format: self.pixel_format,
+            palette: self.palette,
+            frame_ready: self.frame_ready,
+            scanline_callback: None,
+        }
+    }
+
+    // Resizes both buffers for the new format and clears them, since their old contents can't be converted.
+    pub fn set_output(&mut self, pixel_format: PixelFormat, palette: Palette) {
+        self.pixel_format = pixel_format;
+        self.palette = palette;
+
+        let size = Self::PIXEL_COUNT * pixel_format.bytes_per_pixel();
+        for buffer in [&mut self.front_buffer, &mut self.back_buffer] {
+            buffer.resize(size, 0);
+            output::clear(pixel_format, &palette, buffer);
         }
     }
 
-    // rwtodo return an Option, "Some" if rendered?
-    pub fn update(&mut self, newly_elapsed_cycles: u8, memory: &mut Memory, frame: &mut [u8]) {
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn render_options(&self) -> RenderOptions {
+        self.renderer.options
+    }
+
+    pub fn set_render_options(&mut self, options: RenderOptions) {
+        self.renderer.options = options;
+    }
+
+    // The completed frame is saved too, so that it's on screen straight after loading.
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        self.save_emulated_state(out);
+        out.extend_from_slice(&(self.front_buffer.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.front_buffer);
+    }
+
+    // Everything but the completed frame, whose bytes depend on the host's pixel format and palette.
+    pub fn save_emulated_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.elapsed_cycles.to_le_bytes());
+        out.push(self.renderer.window_line());
+    }
+
+    pub fn load_state(&mut self, input: &mut &[u8]) {
+        self.elapsed_cycles = u32::from_le_bytes(state::take(input));
+        let [window_line] = state::take(input);
+        self.renderer.set_window_line(window_line);
+        let frame_size = u32::from_le_bytes(state::take(input)) as usize;
+        let (frame, rest) = input.split_at(frame_size);
+        *input = rest;
+
+        // The frame can't be used if the pixel format has changed since it was saved.
+        if frame.len() == self.front_buffer.len() {
+            self.front_buffer.copy_from_slice(frame);
+        } else {
+            output::clear(self.pixel_format, &self.palette, &mut self.front_buffer);
+        }
+        output::clear(self.pixel_format, &self.palette, &mut self.back_buffer);
+    }
+
+    // The most recently completed frame.
+    pub fn frame(&self) -> &[u8] {
+        &self.front_buffer
+    }
+
+    // The frame being rendered. Lines that haven't been rendered yet are shade 0.
+    pub fn frame_in_progress(&self) -> &[u8] {
+        &self.back_buffer
+    }
+
+    fn present_frame(&mut self) {
+        std::mem::swap(&mut self.front_buffer, &mut self.back_buffer);
+        output::clear(self.pixel_format, &self.palette, &mut self.back_buffer);
+        self.frame_ready = true;
+    }
+
+    pub fn update(&mut self, newly_elapsed_cycles: u8, memory: &mut Memory) {
         const LCDC_ENABLED_BIT: u8 = 0x01 << 7;
         const NUM_CYCLES_PER_FULL_SCREEN_REFRESH: u32 = 70224; // Approximately 59.7275Hz
         const NUM_CYCLES_PER_LY_INCREMENT: u32 = 456;
@@ -75,7 +188,7 @@ impl Lcd {
         }
 
         // Handle LYC.
-        if memory.read(address::LCD_LY) == memory.read(address::LCD_LYC) {
+        if memory.peek(address::LCD_LY) == memory.read(address::LCD_LYC) {
             *memory.direct_access(address::LCD_STATUS) |= 0x04;
             if memory.read(address::LCD_STATUS) & 0x40 != 0 {
                 interrupt::make_request(interrupt::FLAG_LCD_STAT, memory);
@@ -92,7 +205,7 @@ impl Lcd {
             *status &= 0xfc; // Discard the old mode.
         }
 
-        if memory.read(address::LCD_LY) < LY_VBLANK_ENTRY_VALUE {
+        if memory.peek(address::LCD_LY) < LY_VBLANK_ENTRY_VALUE {
             /*
             Approx mode graph:
             Mode 2  2_____2_____2_____2_____2_____2___________________2____
@@ -114,10 +227,22 @@ impl Lcd {
                 if previous_mode != 0x03 {
                     // rwtodo: just write directly to the line-slice of the frame.
                     let screen_line = self.renderer.render_screen_line(memory);
-                    let ly = usize::from(*memory.direct_access(address::LCD_LY));
+                    let ly = *memory.direct_access(address::LCD_LY);
+
+                    let line_size = Lcd::WIDTH * self.pixel_format.bytes_per_pixel();
+                    let line_start = usize::from(ly) * line_size;
+                    let line = &mut self.back_buffer[line_start..line_start + line_size];
+                    let layer_colours = self.renderer.options.layer_colours;
+                    output::convert_line(
+                        &screen_line,
+                        self.pixel_format,
+                        &self.palette,
+                        layer_colours,
+                        line,
+                    );
 
-                    for x in 0..Lcd::WIDTH {
-                        frame[ly * Lcd::WIDTH + x] = screen_line[x];
+                    if let Some(callback) = &mut self.scanline_callback {
+                        callback(ly, line);
                     }
                 }
             } else {
@@ -132,6 +257,9 @@ impl Lcd {
             *memory.direct_access(address::LCD_STATUS) |= 0x01; // V-blank.
 
             if previous_mode != 0x01 {
+                self.renderer.reset_window_line();
+                self.present_frame();
+                memory.apply_ram_cheats();
                 interrupt::make_request(interrupt::FLAG_VBLANK, memory);
 
                 if (memory.read(address::LCD_STATUS) & 0x10) != 0 {
@@ -0,0 +1,238 @@
+// Converting rendered lines into the host's chosen pixel format and palette.
+
+use super::render;
+
+/// How each pixel of the frame is stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// One byte per pixel: the raw 2-bit shade, from 0 (lightest) to 3 (darkest). The palette isn't applied.
+    Shades,
+    /// One byte per pixel: the brightness of the palette colour.
+    #[default]
+    Gray8,
+    /// Four bytes per pixel: red, green, blue, then 0xff alpha.
+    Rgba8888,
+    /// Two bytes per pixel: a little-endian u16 with 5 bits of red at the top, 6 of green, then 5 of blue.
+    Rgb565,
+    /// Four bytes per pixel: blue, green, red, then 0xff alpha.
+    Bgra8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Shades | PixelFormat::Gray8 => 1,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Rgba8888 | PixelFormat::Bgra8888 => 4,
+        }
+    }
+}
+
+/// The RGB colours of the 4 shades, from lightest to darkest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    pub const GRAYSCALE: Palette = Palette([
+        [0xff, 0xff, 0xff],
+        [0xaa, 0xaa, 0xaa],
+        [0x55, 0x55, 0x55],
+        [0x00, 0x00, 0x00],
+    ]);
+
+    /// The original Game Boy's green LCD.
+    pub const DMG_GREEN: Palette = Palette([
+        [0x9b, 0xbc, 0x0f],
+        [0x8b, 0xac, 0x0f],
+        [0x30, 0x62, 0x30],
+        [0x0f, 0x38, 0x0f],
+    ]);
+
+    /// The Game Boy Pocket's gray LCD.
+    pub const POCKET: Palette = Palette([
+        [0xc4, 0xcf, 0xa1],
+        [0x8b, 0x95, 0x6d],
+        [0x4d, 0x53, 0x3c],
+        [0x1f, 0x1f, 0x1f],
+    ]);
+
+    /// The Game Boy Light's backlit LCD.
+    pub const LIGHT: Palette = Palette([
+        [0x00, 0xd8, 0xb8],
+        [0x00, 0xa8, 0x8c],
+        [0x00, 0x68, 0x58],
+        [0x00, 0x30, 0x28],
+    ]);
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::GRAYSCALE
+    }
+}
+
+// The colour of a rendered pixel, with any debug overlay or layer colouring applied.
+fn colour(pixel: u8, palette: &Palette, layer_colours: bool) -> [u8; 3] {
+    if pixel & render::OVERLAY_OBJECT_BOX != 0 {
+        return [0xff, 0x00, 0xff];
+    }
+    if pixel & render::OVERLAY_WINDOW_ORIGIN != 0 {
+        return [0x00, 0xff, 0xff];
+    }
+
+    let colour = palette.0[usize::from(pixel & 0x03)];
+    if !layer_colours {
+        return colour;
+    }
+
+    // Tint the background blue, the window green and objects red.
+    let tint: [u8; 3] = match pixel & render::LAYER_MASK {
+        render::LAYER_BACKGROUND => [0x60, 0x80, 0xff],
+        render::LAYER_WINDOW => [0x60, 0xff, 0x60],
+        render::LAYER_OBJECTS => [0xff, 0x60, 0x60],
+        _ => [0xff, 0xff, 0xff],
+    };
+    let mut tinted = colour;
+    for (c, t) in tinted.iter_mut().zip(tint) {
+        *c = (u16::from(*c) * u16::from(t) / 0xff) as u8;
+    }
+    tinted
+}
+
+// Writes one rendered pixel to out, which is bytes_per_pixel long.
+fn convert_pixel(
+    pixel: u8,
+    format: PixelFormat,
+    palette: &Palette,
+    layer_colours: bool,
+    out: &mut [u8],
+) {
+    if format == PixelFormat::Shades {
+        out[0] = pixel & 0x03;
+        return;
+    }
+
+    let [r, g, b] = colour(pixel, palette, layer_colours);
+    match format {
+        PixelFormat::Shades => unreachable!(),
+        PixelFormat::Gray8 => {
+            // The usual luma weights, which leave gray colours unchanged.
+            let luma = 299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b);
+            out[0] = (luma / 1000) as u8;
+        }
+        PixelFormat::Rgba8888 => out.copy_from_slice(&[r, g, b, 0xff]),
+        PixelFormat::Rgb565 => {
+            let rgb565 = (u16::from(r) >> 3) << 11 | (u16::from(g) >> 2) << 5 | (u16::from(b) >> 3);
+            out.copy_from_slice(&rgb565.to_le_bytes());
+        }
+        PixelFormat::Bgra8888 => out.copy_from_slice(&[b, g, r, 0xff]),
+    }
+}
+
+// Converts a line from the Renderer. Debug overlays and layer colours are ignored by PixelFormat::Shades.
+pub fn convert_line(
+    pixels: &[u8],
+    format: PixelFormat,
+    palette: &Palette,
+    layer_colours: bool,
+    out: &mut [u8],
+) {
+    let out_pixels = out.chunks_exact_mut(format.bytes_per_pixel());
+    for (pixel, out_pixel) in pixels.iter().zip(out_pixels) {
+        convert_pixel(*pixel, format, palette, layer_colours, out_pixel);
+    }
+}
+
+// Fills a whole buffer with shade 0, which is what the LCD shows when nothing is drawn.
+pub fn clear(format: PixelFormat, palette: &Palette, buffer: &mut [u8]) {
+    let mut blank = [0; 4];
+    let blank = &mut blank[..format.bytes_per_pixel()];
+    convert_pixel(0, format, palette, false, blank);
+
+    for pixel in buffer.chunks_exact_mut(blank.len()) {
+        pixel.copy_from_slice(blank);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lcd::Lcd;
+
+    fn convert(format: PixelFormat, palette: &Palette) -> Vec<u8> {
+        let mut shades = [0; Lcd::WIDTH];
+        shades[..4].copy_from_slice(&[0, 1, 2, 3]);
+
+        let mut out = vec![0; Lcd::WIDTH * format.bytes_per_pixel()];
+        convert_line(&shades, format, palette, false, &mut out);
+        out.truncate(4 * format.bytes_per_pixel());
+        out
+    }
+
+    #[test]
+    fn test_pixel_formats() {
+        let grayscale = Palette::GRAYSCALE;
+        assert_eq!(convert(PixelFormat::Shades, &grayscale), [0, 1, 2, 3]);
+        assert_eq!(
+            convert(PixelFormat::Gray8, &grayscale),
+            [0xff, 0xaa, 0x55, 0x00]
+        );
+
+        let green = Palette::DMG_GREEN;
+        assert_eq!(
+            convert(PixelFormat::Rgba8888, &green)[..8],
+            [0x9b, 0xbc, 0x0f, 0xff, 0x8b, 0xac, 0x0f, 0xff]
+        );
+        assert_eq!(
+            convert(PixelFormat::Bgra8888, &green)[..8],
+            [0x0f, 0xbc, 0x9b, 0xff, 0x0f, 0xac, 0x8b, 0xff]
+        );
+
+        // White is all ones, and black all zeros.
+        assert_eq!(
+            convert(PixelFormat::Rgb565, &grayscale),
+            [0xff, 0xff, 0x55, 0xad, 0xaa, 0x52, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_overlays_and_layer_colours() {
+        let mut pixels = [0; Lcd::WIDTH];
+        pixels[..3].copy_from_slice(&[
+            render::LAYER_BACKGROUND,
+            3 | render::LAYER_OBJECTS | render::OVERLAY_OBJECT_BOX,
+            3 | render::LAYER_OBJECTS,
+        ]);
+
+        let mut out = vec![0; Lcd::WIDTH * 4];
+        convert_line(
+            &pixels,
+            PixelFormat::Rgba8888,
+            &Palette::GRAYSCALE,
+            true,
+            &mut out,
+        );
+        assert_eq!(
+            out[..12],
+            [0x60, 0x80, 0xff, 0xff, 0xff, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0xff]
+        );
+
+        // Raw shades don't show any of it.
+        let mut out = vec![0; Lcd::WIDTH];
+        convert_line(
+            &pixels,
+            PixelFormat::Shades,
+            &Palette::GRAYSCALE,
+            true,
+            &mut out,
+        );
+        assert_eq!(out[..3], [0, 3, 3]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut buffer = vec![0; 8];
+        clear(PixelFormat::Rgba8888, &Palette::POCKET, &mut buffer);
+        assert_eq!(buffer, [0xc4, 0xcf, 0xa1, 0xff, 0xc4, 0xcf, 0xa1, 0xff]);
+    }
+}
@@ -16,10 +16,46 @@ const NUM_BYTES_PER_TILE_LINE: i32 = 2;
 const NUM_TILES_PER_BG_LINE: u8 = 32;
 const TILE_WIDTH: u8 = 8;
 const TILE_HEIGHT: u8 = 8;
-type TileLine = [u8; TILE_WIDTH as usize];
+pub(super) type TileLine = [u8; TILE_WIDTH as usize];
 
 const SHADE_0_FLAG: u8 = 0x04;
 
+// Besides the 2-bit shade, each pixel of a rendered line records the layer that drew it and any debug overlay.
+pub const LAYER_MASK: u8 = 0x30;
+pub const LAYER_BACKGROUND: u8 = 0x10;
+pub const LAYER_WINDOW: u8 = 0x20;
+pub const LAYER_OBJECTS: u8 = 0x30;
+pub const OVERLAY_OBJECT_BOX: u8 = 0x40;
+pub const OVERLAY_WINDOW_ORIGIN: u8 = 0x80;
+
+/// Debugging switches for the renderer. They don't affect LCDC or anything else the game can see.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub background: bool,
+    pub window: bool,
+    pub objects: bool,
+    /// Outline every object on the screen, including hidden ones.
+    pub object_boxes: bool,
+    /// Outline the top and left edges of the window, wherever WX and WY put it.
+    pub window_origin: bool,
+    /// Tint each pixel by the layer that drew it: blue for the background, green for the window and red for
+    /// objects. It's ignored by PixelFormat::Shades.
+    pub layer_colours: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            background: true,
+            window: true,
+            objects: true,
+            object_boxes: false,
+            window_origin: false,
+            layer_colours: false,
+        }
+    }
+}
+
 // rwtodo: investigate how best to remove the unwrap()s in this file.
 // rwtodo: Look at how to minimize the integer casts. I can probably just have most stuff as usize.
 
@@ -33,12 +69,25 @@ fn tile_line_ref(offset: usize, screen_line: &mut [u8; Lcd::WIDTH]) -> &mut Tile
         .expect("Tile destination should be of size TILE_WIDTH=8")
 }
 
+pub(super) fn bg_and_window_tile_data_address(control: u8) -> u16 {
+    if (control & LCDC_BG_AND_WINDOW_TILE_DATA_SELECT) != 0 {
+        0x8000
+    } else {
+        0x9000
+    }
+}
+
+#[derive(Clone)]
 pub struct Renderer {
     // rwtodo Do we really need a Renderer struct with state? or just shade state? I also don't like the naming of render::Renderer.
     shade_0: u8,
     shade_1: u8,
     shade_2: u8,
     shade_3: u8,
+    // The window's own line counter, which only advances on lines where the window is drawn. A window that's
+    // hidden for some lines carries on from where it left off, rather than skipping ahead.
+    window_line: u8,
+    pub options: RenderOptions,
 }
 
 impl Renderer {
@@ -48,10 +97,25 @@ impl Renderer {
             shade_1: 0x00,
             shade_2: 0x00,
             shade_3: 0x00,
+            window_line: 0,
+            options: RenderOptions::default(),
         }
     }
 
-    fn set_palette(&mut self, palette: u8) {
+    pub(super) fn window_line(&self) -> u8 {
+        self.window_line
+    }
+
+    pub(super) fn set_window_line(&mut self, window_line: u8) {
+        self.window_line = window_line;
+    }
+
+    // Called on entering V-blank.
+    pub(super) fn reset_window_line(&mut self) {
+        self.window_line = 0;
+    }
+
+    pub(super) fn set_palette(&mut self, palette: u8) {
         // SHADE_0_FLAG ensures shade_0 is unique, which streamlines the process of
         // shade-0-dependent blitting. The flag is discarded in the final step of the render.
         self.shade_0 = (palette & 0x03) | SHADE_0_FLAG;
@@ -61,7 +125,7 @@ impl Renderer {
     }
 
     // rwtodo: Unsure of the int types here.
-    fn get_tile_line(
+    pub(super) fn get_tile_line(
         &self,
         memory: &Memory,
         tile_bank_address: u16,
@@ -163,7 +227,8 @@ impl Renderer {
         }
     }
 
-    fn get_bg_tile_line(
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn get_bg_tile_line(
         &self,
         memory: &Memory,
         coord_x: u8,
@@ -173,9 +238,6 @@ impl Renderer {
         tile_line_index: u8,
         line_out: &mut TileLine,
     ) {
-        // Convert to i32 to do arithmetic
-        let tile_map_address_space = tile_map_address_space as u16;
-
         let tile_map_index: u16 =
             u16::from(coord_x) + u16::from(coord_y) * u16::from(NUM_TILES_PER_BG_LINE);
         let address = tile_map_address_space + tile_map_index;
@@ -202,7 +264,7 @@ impl Renderer {
     }
 
     fn render_background_line(&mut self, memory: &Memory) -> [u8; Lcd::WIDTH] {
-        let ly = memory.read(address::LCD_LY);
+        let ly = memory.peek(address::LCD_LY);
         let control = memory.read(address::LCD_CONTROL);
         let bg_scroll_y = memory.read(0xff42); // rwtodo const
         let bg_scroll_x = memory.read(0xff43); // rwtodo const
@@ -218,11 +280,7 @@ impl Renderer {
             0x9800
         };
 
-        let tile_data_address_space: u16 = if (control & LCDC_BG_AND_WINDOW_TILE_DATA_SELECT) != 0 {
-            0x8000
-        } else {
-            0x9000
-        };
+        let tile_data_address_space = bg_and_window_tile_data_address(control);
 
         let mut screen_line: [u8; Lcd::WIDTH] = [42; Lcd::WIDTH]; // rwtodo 42
 
@@ -272,7 +330,7 @@ impl Renderer {
 
     fn render_objects(&mut self, screen_line: &mut [u8; Lcd::WIDTH], memory: &Memory) {
         let control = memory.read(address::LCD_CONTROL);
-        let ly = memory.read(address::LCD_LY);
+        let ly = memory.peek(address::LCD_LY);
 
         let object_height = if control & LCDC_DOUBLE_HEIGHT_OBJECTS != 0 {
             16
@@ -310,9 +368,13 @@ impl Renderer {
 
                 let mut tile_line: TileLine = [0; TILE_WIDTH as usize];
                 {
-                    // rwtodo: redo this whole block, could have esoteric i8/u8 casting.
-                    // int8_t tile_line_index = flip_y ? (translate_y+7 - *ly) : *ly - translate_y; rwtodo
-                    let tile_line_index = (ly - translate_y) as u8; // rwtodo wrong; see above
+                    // Double-height objects flip as a whole, so their bottom tile's lines come first.
+                    let object_line = ly - translate_y;
+                    let tile_line_index = if flip_y {
+                        object_height - 1 - object_line
+                    } else {
+                        object_line
+                    } as u8;
                     self.get_tile_line(
                         memory,
                         0x8000,
@@ -362,7 +424,7 @@ impl Renderer {
                             if (tile_pixel & SHADE_0_FLAG) == 0
                                 && screen_line[screen_x as usize] & SHADE_0_FLAG != 0
                             {
-                                screen_line[screen_x as usize] = tile_pixel;
+                                screen_line[screen_x as usize] = tile_pixel | LAYER_OBJECTS;
                             }
                         }
                     } else {
@@ -371,7 +433,7 @@ impl Renderer {
                             tile_pixel_index += 1;
 
                             if (tile_pixel & SHADE_0_FLAG) == 0 {
-                                screen_line[screen_x as usize] = tile_pixel;
+                                screen_line[screen_x as usize] = tile_pixel | LAYER_OBJECTS;
                             }
                         }
                     }
@@ -380,35 +442,143 @@ impl Renderer {
         }
     }
 
+    // The window line counter advances even if the window is hidden by the render options, as the game would
+    // have drawn it.
+    fn render_window_line(&mut self, screen_line: &mut [u8; Lcd::WIDTH], memory: &Memory) {
+        let ly = memory.peek(address::LCD_LY);
+        let control = memory.read(address::LCD_CONTROL);
+        let window_y = memory.read(0xff4a); // rwtodo const
+        let window_x = memory.read(0xff4b); // rwtodo const
+
+        if ly < window_y || window_x > 166 {
+            return;
+        }
+
+        let window_line = self.window_line;
+        self.window_line = self.window_line.wrapping_add(1);
+        if !self.options.window {
+            return;
+        }
+
+        let tile_map_address_space: u16 = if (control & LCDC_WINDOW_TILE_MAP_SELECT) != 0 {
+            0x9c00
+        } else {
+            0x9800
+        };
+        let tile_data_address_space = bg_and_window_tile_data_address(control);
+
+        // WX is the window's left edge plus 7.
+        let window_screen_x = i16::from(window_x) - 7;
+        let mut tile_line: TileLine = [0; TILE_WIDTH as usize];
+
+        for screen_x in window_screen_x.max(0)..Lcd::WIDTH as i16 {
+            let window_line_x = (screen_x - window_screen_x) as u8;
+            let tile_pixel_index = window_line_x % TILE_WIDTH;
+
+            if tile_pixel_index == 0 || screen_x == 0 {
+                self.get_bg_tile_line(
+                    memory,
+                    window_line_x / TILE_WIDTH,
+                    window_line / TILE_HEIGHT,
+                    tile_map_address_space,
+                    tile_data_address_space,
+                    window_line % TILE_HEIGHT,
+                    &mut tile_line,
+                );
+            }
+
+            screen_line[screen_x as usize] =
+                tile_line[usize::from(tile_pixel_index)] | LAYER_WINDOW;
+        }
+    }
+
+    fn draw_object_boxes(&self, screen_line: &mut [u8; Lcd::WIDTH], memory: &Memory) {
+        let control = memory.read(address::LCD_CONTROL);
+        let ly = i16::from(memory.peek(address::LCD_LY));
+
+        let object_height = if control & LCDC_DOUBLE_HEIGHT_OBJECTS != 0 {
+            16
+        } else {
+            8
+        };
+
+        let mut mark = |screen_x: i16| {
+            if (0..Lcd::WIDTH as i16).contains(&screen_x) {
+                screen_line[screen_x as usize] |= OVERLAY_OBJECT_BOX;
+            }
+        };
+
+        for object_address in (0xfe00..=0xfe9c).step_by(4) {
+            let top = i16::from(memory.read(object_address)) - i16::from(TILE_HEIGHT) * 2;
+            let left = i16::from(memory.read(object_address + 1)) - i16::from(TILE_WIDTH);
+            let right = left + i16::from(TILE_WIDTH) - 1;
+
+            if ly == top || ly == top + object_height - 1 {
+                (left..=right).for_each(&mut mark);
+            } else if ly > top && ly < top + object_height {
+                mark(left);
+                mark(right);
+            }
+        }
+    }
+
+    fn draw_window_origin(&self, screen_line: &mut [u8; Lcd::WIDTH], memory: &Memory) {
+        let ly = memory.peek(address::LCD_LY);
+        let window_y = memory.read(0xff4a); // rwtodo const
+        let window_screen_x = i16::from(memory.read(0xff4b)) - 7; // rwtodo const
+
+        if ly == window_y {
+            for screen_x in window_screen_x.max(0)..Lcd::WIDTH as i16 {
+                screen_line[screen_x as usize] |= OVERLAY_WINDOW_ORIGIN;
+            }
+        } else if ly > window_y && (0..Lcd::WIDTH as i16).contains(&window_screen_x) {
+            screen_line[window_screen_x as usize] |= OVERLAY_WINDOW_ORIGIN;
+        }
+    }
+
+    // Returns the line's 2-bit shades, tagged with the LAYER_ and OVERLAY_ bits.
     pub fn render_screen_line(&mut self, memory: &Memory) -> [u8; Lcd::WIDTH] {
         let lcd_control = memory.read(address::LCD_CONTROL);
+        let bg_and_window_enabled = (lcd_control & LCDC_BG_AND_WINDOW_ENABLED) != 0;
 
-        let mut screen_line = if (lcd_control & LCDC_BG_AND_WINDOW_ENABLED) != 0 {
+        let mut screen_line = if bg_and_window_enabled && self.options.background {
             let bg_palette = memory.read(0xff47); // rwtodo const
             self.set_palette(bg_palette);
 
-            self.render_background_line(memory)
-
-            // if (lcd_control & LCDC_WINDOW_ENABLED) render_window_line(); rwtodo
+            let mut screen_line = self.render_background_line(memory);
+            for pixel in &mut screen_line {
+                *pixel = (*pixel & (SHADE_0_FLAG | 0x03)) | LAYER_BACKGROUND;
+            }
+            screen_line
+        } else if bg_and_window_enabled {
+            // Hidden by the render options, so draw shade 0 that objects can still appear behind.
+            [SHADE_0_FLAG; Lcd::WIDTH]
         } else {
             // rwtodo: render white here.
             [0; Lcd::WIDTH]
         };
 
-        if lcd_control & LCDC_OBJECTS_ENABLED != 0 {
-            self.render_objects(&mut screen_line, memory);
+        if bg_and_window_enabled && (lcd_control & LCDC_WINDOW_ENABLED) != 0 {
+            let bg_palette = memory.read(0xff47); // rwtodo const
+            self.set_palette(bg_palette);
+            self.render_window_line(&mut screen_line, memory);
         }
 
-        // Convert from game boy 2-bit (with SHADE_0_FLAG) to target 8-bit.
-        for pixel in &mut screen_line {
-            // The '& 0x03' below is to discard the SHADE_0_FLAG bit, which has already served its purpose in render_objects(). rwtodo move this to render_objects()?
-            let mut pixel_i16 = i16::from(*pixel & 0x03);
+        if lcd_control & LCDC_OBJECTS_ENABLED != 0 && self.options.objects {
+            self.render_objects(&mut screen_line, memory);
+        }
 
-            // Flip the values and multiply to make white == 255.
-            pixel_i16 -= 3;
-            pixel_i16 *= -85;
+        if self.options.object_boxes {
+            self.draw_object_boxes(&mut screen_line, memory);
+        }
+        if self.options.window_origin {
+            self.draw_window_origin(&mut screen_line, memory);
+        }
 
-            *pixel = pixel_i16 as u8;
+        // Discard the SHADE_0_FLAG bit, which has already served its purpose in render_objects(). The Lcd
+        // converts what's left to the host's pixel format.
+        for pixel in &mut screen_line {
+            *pixel &= !SHADE_0_FLAG;
         }
 
         screen_line
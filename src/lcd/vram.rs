@@ -0,0 +1,238 @@
+// Debugging views of VRAM and OAM, for inspecting tiles, tile maps and objects without another emulator.
+
+use super::output::{self, Palette};
+use super::render::{self, Renderer, TileLine};
+use crate::address;
+use crate::lcd::Lcd;
+use crate::make_bit;
+use crate::Memory;
+
+const TILE_SIZE: usize = 8;
+const NUM_TILES: usize = 384;
+const NUM_TILES_PER_IMAGE_ROW: usize = 16;
+const NUM_TILES_PER_MAP_LINE: usize = 32;
+
+/// An image for debugging, row by row, in the GameBoy's pixel format.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// One of the two 32x32 tile maps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileMap {
+    Map9800,
+    Map9c00,
+}
+
+/// An object's OAM entry, decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OamEntry {
+    /// The screen position of the object's top left corner, which can be partly or entirely off screen.
+    pub x: i16,
+    pub y: i16,
+    pub tile: u8,
+    /// 0 for OBP0, 1 for OBP1.
+    pub palette: u8,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub behind_background: bool,
+    /// The raw flags byte, including the bits only used by the Game Boy Color.
+    pub flags: u8,
+}
+
+impl OamEntry {
+    fn read(memory: &Memory, address: u16) -> Self {
+        let flags = memory.read(address + 3);
+
+        Self {
+            y: i16::from(memory.read(address)) - 16,
+            x: i16::from(memory.read(address + 1)) - 8,
+            tile: memory.read(address + 2),
+            palette: (flags & make_bit(4)) >> 4,
+            flip_x: flags & make_bit(5) != 0,
+            flip_y: flags & make_bit(6) != 0,
+            behind_background: flags & make_bit(7) != 0,
+            flags,
+        }
+    }
+}
+
+pub fn oam(memory: &Memory) -> [OamEntry; 40] {
+    std::array::from_fn(|index| OamEntry::read(memory, 0xfe00 + 4 * index as u16))
+}
+
+impl Lcd {
+    fn image(&self, width: usize, height: usize, shades: &[u8], palette: &Palette) -> Image {
+        let mut pixels = vec![0; width * height * self.pixel_format.bytes_per_pixel()];
+        output::convert_line(shades, self.pixel_format, palette, false, &mut pixels);
+
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // All 384 tiles from 0x8000-0x97ff, 16 to a row. The raw colour numbers are shown, not BGP's shades.
+    pub fn tile_data_image(&self, memory: &Memory, palette: &Palette) -> Image {
+        let mut renderer = Renderer::new();
+        renderer.set_palette(0xe4); // Colour 0 is shade 0, colour 1 is shade 1 etc.
+
+        let width = NUM_TILES_PER_IMAGE_ROW * TILE_SIZE;
+        let height = NUM_TILES / NUM_TILES_PER_IMAGE_ROW * TILE_SIZE;
+        let mut shades = vec![0; width * height];
+        let mut tile_line: TileLine = [0; TILE_SIZE];
+
+        for tile_index in 0..NUM_TILES {
+            let image_x = tile_index % NUM_TILES_PER_IMAGE_ROW * TILE_SIZE;
+            let image_y = tile_index / NUM_TILES_PER_IMAGE_ROW * TILE_SIZE;
+
+            for line_index in 0..TILE_SIZE {
+                renderer.get_tile_line(
+                    memory,
+                    0x8000,
+                    tile_index as i16,
+                    line_index as u8,
+                    &mut tile_line,
+                );
+
+                let start = (image_y + line_index) * width + image_x;
+                shades[start..start + TILE_SIZE].copy_from_slice(&tile_line);
+            }
+        }
+
+        self.image(width, height, &strip_flags(shades), palette)
+    }
+
+    // The whole 256x256 map, using BGP and LCDC's current tile data, with the SCX/SCY viewport outlined.
+    pub fn tile_map_image(&self, memory: &Memory, map: TileMap, palette: &Palette) -> Image {
+        let control = memory.read(address::LCD_CONTROL);
+        let tile_map_address_space = match map {
+            TileMap::Map9800 => 0x9800,
+            TileMap::Map9c00 => 0x9c00,
+        };
+        let tile_data_address_space = render::bg_and_window_tile_data_address(control);
+
+        let mut renderer = Renderer::new();
+        renderer.set_palette(memory.read(0xff47)); // rwtodo const
+
+        let size = NUM_TILES_PER_MAP_LINE * TILE_SIZE;
+        let mut shades = vec![0; size * size];
+        let mut tile_line: TileLine = [0; TILE_SIZE];
+
+        for map_y in 0..size {
+            for map_x in (0..size).step_by(TILE_SIZE) {
+                renderer.get_bg_tile_line(
+                    memory,
+                    (map_x / TILE_SIZE) as u8,
+                    (map_y / TILE_SIZE) as u8,
+                    tile_map_address_space,
+                    tile_data_address_space,
+                    (map_y % TILE_SIZE) as u8,
+                    &mut tile_line,
+                );
+
+                let start = map_y * size + map_x;
+                shades[start..start + TILE_SIZE].copy_from_slice(&tile_line);
+            }
+        }
+
+        let mut shades = strip_flags(shades);
+
+        // Outline the viewport, which wraps around the edges of the map.
+        let scroll_y = usize::from(memory.read(0xff42)); // rwtodo const
+        let scroll_x = usize::from(memory.read(0xff43)); // rwtodo const
+        let mut mark = |x: usize, y: usize| {
+            shades[(scroll_y + y) % size * size + (scroll_x + x) % size] |=
+                render::OVERLAY_OBJECT_BOX;
+        };
+        for x in 0..Lcd::WIDTH {
+            mark(x, 0);
+            mark(x, Lcd::HEIGHT - 1);
+        }
+        for y in 0..Lcd::HEIGHT {
+            mark(0, y);
+            mark(Lcd::WIDTH - 1, y);
+        }
+
+        self.image(size, size, &shades, palette)
+    }
+}
+
+// Leaves just the 2-bit shades from the Renderer's tile lines.
+fn strip_flags(mut shades: Vec<u8>) -> Vec<u8> {
+    for shade in &mut shades {
+        *shade &= 0x03;
+    }
+    shades
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lcd::PixelFormat;
+
+    #[test]
+    fn test_tile_data_image() {
+        let mut memory = Memory::new(crate::test_rom(&[]).into());
+        let mut lcd = Lcd::new();
+        lcd.set_output(PixelFormat::Shades, Palette::GRAYSCALE);
+
+        // The first line of tile 17 has colours 0, 1, 2, 3, 3, 2, 1, 0.
+        memory.write(0x8000 + 17 * 16, 0b0101_1010);
+        memory.write(0x8000 + 17 * 16 + 1, 0b0011_1100);
+
+        let image = lcd.tile_data_image(&memory, &Palette::GRAYSCALE);
+        assert_eq!((image.width, image.height), (128, 192));
+
+        let start = 8 * 128 + 8;
+        assert_eq!(image.pixels[start..start + 8], [0, 1, 2, 3, 3, 2, 1, 0]);
+        assert!(image.pixels[..start].iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn test_tile_map_image() {
+        let mut memory = Memory::new(crate::test_rom(&[]).into());
+        let mut lcd = Lcd::new();
+        lcd.set_output(PixelFormat::Rgba8888, Palette::GRAYSCALE);
+        memory.write(0xff43, 250);
+
+        let image = lcd.tile_map_image(&memory, TileMap::Map9800, &Palette::GRAYSCALE);
+        assert_eq!((image.width, image.height), (256, 256));
+
+        // The viewport starts at x=250 and wraps around to the left edge.
+        let pixel = |x: usize, y: usize| &image.pixels[(y * 256 + x) * 4..(y * 256 + x + 1) * 4];
+        assert_eq!(pixel(250, 0), [0xff, 0x00, 0xff, 0xff]);
+        assert_eq!(pixel(5, 0), [0xff, 0x00, 0xff, 0xff]);
+        assert_eq!(pixel(153, 143), [0xff, 0x00, 0xff, 0xff]);
+        assert_eq!(pixel(154, 143), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(pixel(100, 100), [0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_oam() {
+        let mut memory = Memory::new(crate::test_rom(&[]).into());
+        memory.write(0xfe04, 20);
+        memory.write(0xfe05, 4);
+        memory.write(0xfe06, 0x42);
+        memory.write(0xfe07, 0xb0);
+
+        let oam = oam(&memory);
+        assert_eq!(
+            oam[1],
+            OamEntry {
+                x: -4,
+                y: 4,
+                tile: 0x42,
+                palette: 1,
+                flip_x: true,
+                flip_y: false,
+                behind_background: true,
+                flags: 0xb0,
+            }
+        );
+        assert_eq!(oam[0].y, -16);
+    }
+}
@@ -1,12 +1,40 @@
 #![allow(dead_code)] // rwtodo: remove.
 
+pub mod audio;
+pub mod bus;
+#[cfg(feature = "capi")]
+mod capi;
+pub mod cheats;
 mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod env;
+pub mod gdb;
 mod lcd;
+#[cfg(feature = "libretro")]
+mod libretro;
 mod memory;
-
-use cpu::Cpu;
+pub mod movie;
+pub mod patch;
+#[cfg(feature = "python")]
+mod python;
+pub mod ram_search;
+mod rewind;
+mod state;
+pub mod trace;
+
+use audio::AudioRecorder;
+use cheats::Cheat;
+use debugger::{BreakCondition, Breakpoint, Debugger, StopReason, Watchpoint};
 use lcd::Lcd;
+pub use lcd::{Image, OamEntry, Palette, PixelFormat, RenderOptions, ScanlineCallback, TileMap};
 use memory::Memory;
+use movie::{Movie, MoviePlayer, MovieRecorder, PlaybackStatus};
+use rewind::RewindBuffer;
+use std::sync::Arc;
+use trace::Tracer;
+
+pub use cpu::{Cpu, Register, Registers};
 
 fn make_u16(lower_nibble: u8, upper_nibble: u8) -> u16 {
     let lower_nibble = u16::from(lower_nibble);
@@ -49,6 +77,7 @@ mod address {
     pub const SERIAL_CONTROL: u16 = 0xff02;
 }
 
+#[derive(Clone)]
 struct Timer {
     cycles_since_last_tima_increment: u16, // rwtodo: rename to cycles_since_last_counter_increment? search everywhere for "tima".
     incrementer_every_cycle: u16,
@@ -78,6 +107,16 @@ impl Timer {
         new_timer
     }
 
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.cycles_since_last_tima_increment.to_le_bytes());
+        out.extend_from_slice(&self.incrementer_every_cycle.to_le_bytes());
+    }
+
+    fn load_state(&mut self, input: &mut &[u8]) {
+        self.cycles_since_last_tima_increment = u16::from_le_bytes(state::take(input));
+        self.incrementer_every_cycle = u16::from_le_bytes(state::take(input));
+    }
+
     fn update(&mut self, elapsed_cycles: u8, memory: &mut Memory) {
         let elapsed_cycles: u16 = elapsed_cycles.into();
         let control_value = memory.read(Self::CONTROL_ADDRESS);
@@ -119,6 +158,8 @@ impl Timer {
     }
 }
 
+// Each button is one bit, in the same layout as the joypad register. Pressed buttons are 0.
+#[derive(Clone)]
 struct Joypad {
     action_buttons: u8,
     direction_buttons: u8,
@@ -132,11 +173,16 @@ impl Joypad {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Button {
     A,
     B,
     START,
     SELECT,
+    UP,
+    DOWN,
+    LEFT,
+    RIGHT,
 }
 
 pub struct GameBoy {
@@ -144,11 +190,53 @@ pub struct GameBoy {
     memory: Memory,
     cpu: Cpu,
     timer: Timer,
+    debugger: Debugger,
+    tracer: Option<Tracer>,
+    audio: Option<AudioRecorder>,
+    rewind: Option<RewindBuffer>,
+
+    // Cycles emulated since power-on, which movie inputs are timed by.
+    cycles: u64,
+    movie_recorder: Option<MovieRecorder>,
+    movie_player: Option<MoviePlayer>,
+}
+
+/// Cloning is cheap, as the ROM is shared rather than copied. The clone gets its own copy of the emulated state,
+/// debugger and audio. The scanline callback and tracer can't be copied, and the rewind buffer and movies would be
+/// costly to, so those are left unset.
+impl Clone for GameBoy {
+    fn clone(&self) -> Self {
+        Self {
+            lcd: self.lcd.clone_without_callback(),
+            memory: self.memory.clone(),
+            cpu: self.cpu.clone(),
+            timer: self.timer.clone(),
+            debugger: self.debugger.clone(),
+            tracer: None,
+            audio: self.audio.clone(),
+            rewind: None,
+            cycles: self.cycles,
+            movie_recorder: None,
+            movie_player: None,
+        }
+    }
+}
+
+// The outcome of emulating a single line of the frame.
+enum LineOutcome {
+    Visible,
+    VBlank,
+    Stopped(StopReason),
 }
 
 impl GameBoy {
     pub fn new(rom_file_data: &[u8]) -> Self {
-        let mut memory = Memory::new(&rom_file_data);
+        Self::with_shared_rom(rom_file_data.into())
+    }
+
+    /// Like new(), but takes the ROM without copying it, so that many GameBoys can share one.
+    pub fn with_shared_rom(rom: Arc<[u8]>) -> Self {
+        let mut memory = Memory::new(rom);
         let timer = Timer::new(&mut memory);
 
         Self {
@@ -156,6 +244,13 @@ impl GameBoy {
             memory,
             cpu: Cpu::new(),
             timer,
+            debugger: Debugger::default(),
+            tracer: None,
+            audio: None,
+            rewind: None,
+            cycles: 0,
+            movie_recorder: None,
+            movie_player: None,
         }
     }
 
@@ -168,40 +263,709 @@ impl GameBoy {
         &self.memory.serial_buffer
     }
 
-    // rwtodo: returns true if not vblank. not a fan. enum?
-    fn emulate_next_line_of_frame(&mut self, frame: &mut [u8]) -> bool {
-        let previous_lcd_ly = *self.memory.direct_access(address::LCD_LY);
+    /// Collect stereo samples at this sample rate for take_audio_samples(), or stop collecting with None.
+    pub fn record_audio(&mut self, sample_rate: Option<u32>) {
+        self.audio = sample_rate.map(AudioRecorder::new);
+    }
+
+    /// The interleaved left/right samples produced since the last call. Empty unless record_audio() is on.
+    pub fn take_audio_samples(&mut self) -> Vec<i16> {
+        match &mut self.audio {
+            Some(audio) => std::mem::take(&mut audio.samples),
+            None => vec![],
+        }
+    }
+
+    // Returns Some if emulation should stop, either before or after the instruction.
+    fn emulate_next_instruction(&mut self) -> Option<StopReason> {
+        if let Some(player) = &mut self.movie_player {
+            for input in player.take_due_inputs(self.cycles) {
+                self.memory.set_button(&input.button, input.is_down);
+            }
+        }
 
-        let mut total_elapsed_cycles_this_h_blank: u32 = 0;
+        let skip_checks = std::mem::take(&mut self.debugger.skip_next_check);
+
+        // Breakpoints and conditions aren't checked while halted, as PC doesn't move.
+        if !skip_checks && !self.cpu.is_halted() {
+            if let Some(reason) = self.debugger.check(self.cpu.registers(), &self.memory) {
+                self.debugger.skip_next_check = true;
+                return Some(reason);
+            }
+        }
+
+        if let Some(tracer) = &mut self.tracer {
+            // Stop tracing if the sink fails, rather than failing on every instruction.
+            if !self.cpu.is_halted() && tracer.trace(self.cpu.registers(), &self.memory).is_err() {
+                self.tracer = None;
+            }
+        }
+
+        // Check for unexpected addresses for instructions.
+        let pc = self.cpu.registers().get(Register::PC);
+        debug_assert!(
+            self.cpu.is_halted()
+                || pc < 0x8000
+                || (0xff80..0xffff).contains(&pc)
+                || (0xa000..0xfe00).contains(&pc)
+        );
+
+        self.memory.watching = !self.memory.watchpoints.is_empty();
+        let elapsed_cycles = self.cpu.execute_next_instruction(&mut self.memory);
+        self.memory.watching = false;
+        self.cycles += u64::from(elapsed_cycles);
+
+        self.lcd.update(elapsed_cycles, &mut self.memory);
+        self.timer.update(elapsed_cycles, &mut self.memory);
+        if let Some(audio) = &mut self.audio {
+            audio.update(elapsed_cycles);
+        }
+
+        self.memory
+            .take_watchpoint_hit()
+            .map(StopReason::Watchpoint)
+    }
+
+    fn emulate_next_line_of_frame(&mut self) -> LineOutcome {
+        let previous_lcd_ly = *self.memory.direct_access(address::LCD_LY);
 
         // Execute instructions until a horizontal-blank occurs.
         while *self.memory.direct_access(address::LCD_LY) == previous_lcd_ly {
-            let elapsed_cycles = self.cpu.execute_next_instruction(&mut self.memory);
+            if let Some(reason) = self.emulate_next_instruction() {
+                return LineOutcome::Stopped(reason);
+            }
+        }
+
+        if previous_lcd_ly < 144 {
+            LineOutcome::Visible
+        } else {
+            LineOutcome::VBlank
+        }
+    }
+
+    /// Returns StopReason::FrameComplete unless a breakpoint, watchpoint or break condition stopped
+    /// emulation first. Calling again after a stop resumes the same frame. The finished frame is in frame().
+    pub fn emulate_next_frame(&mut self) -> StopReason {
+        // Emulate lines until the vblank phase is exited.
+        loop {
+            match self.emulate_next_line_of_frame() {
+                LineOutcome::VBlank => (),
+                LineOutcome::Visible => break,
+                LineOutcome::Stopped(reason) => return reason,
+            }
+        }
+
+        // Emulate lines until the vblank phase is entered again.
+        loop {
+            match self.emulate_next_line_of_frame() {
+                LineOutcome::Visible => (),
+                LineOutcome::VBlank => break,
+                LineOutcome::Stopped(reason) => return reason,
+            }
+        }
+
+        self.frame_completed();
+        StopReason::FrameComplete
+    }
+
+    // Snapshots and hashes the state for rewinding and movies, if they're on.
+    fn frame_completed(&mut self) {
+        let snapshot_due = self
+            .rewind
+            .as_mut()
+            .is_some_and(RewindBuffer::frame_completed);
+        if self.movie_recorder.is_some() || self.movie_player.is_some() {
+            let emulated_state = self.emulated_state();
+            if let Some(recorder) = &mut self.movie_recorder {
+                recorder.frame_completed(&emulated_state);
+            }
+            if let Some(player) = &mut self.movie_player {
+                player.frame_completed(&emulated_state);
+            }
+        }
+        if snapshot_due {
+            let state = self.save_state();
+            self.rewind.as_mut().unwrap().push(state);
+        }
+    }
+
+    /// Record set_button() calls and a state hash after each frame from now on. The movie starts from
+    /// power-on if nothing has been emulated yet, otherwise from a snapshot of the current state.
+    pub fn start_recording_movie(&mut self) {
+        let start_state = (self.cycles != 0).then(|| self.save_state());
+        self.movie_recorder = Some(MovieRecorder::new(start_state, self.cycles));
+    }
 
-            self.lcd.update(elapsed_cycles, &mut self.memory, frame);
-            self.timer.update(elapsed_cycles, &mut self.memory);
+    pub fn stop_recording_movie(&mut self) -> Option<Movie> {
+        self.movie_recorder.take().map(|recorder| recorder.movie)
+    }
 
-            total_elapsed_cycles_this_h_blank += u32::from(elapsed_cycles);
+    /// Play back a movie's inputs from now on, checking the state after each frame against the recording.
+    /// Movies that start from power-on need a GameBoy that hasn't emulated anything yet.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), String> {
+        match &movie.start_state {
+            Some(state) => {
+                self.check_state(state)?;
+                self.load_state(state);
+            }
+            None if self.cycles != 0 => {
+                return Err(
+                    "The movie starts from power-on, but emulation has already started".to_owned(),
+                )
+            }
+            None => (),
         }
 
-        // rwtodo: update audio right here.
+        self.movie_player = Some(MoviePlayer::new(movie, self.cycles));
+        Ok(())
+    }
+
+    pub fn movie_playback_status(&self) -> Option<PlaybackStatus> {
+        self.movie_player.as_ref().map(MoviePlayer::status)
+    }
+
+    pub fn stop_playing_movie(&mut self) {
+        self.movie_player = None;
+    }
+
+    /// Keep a snapshot every interval_frames frames for rewind(), dropping the oldest to keep them within
+    /// memory_budget bytes. Snapshots are delta-compressed, so each one after the first is usually a few kB.
+    /// Only frames completed by emulate_next_frame() count.
+    pub fn enable_rewind(&mut self, interval_frames: u32, memory_budget: usize) {
+        let mut rewind = RewindBuffer::new(interval_frames, memory_budget);
+        rewind.push(self.save_state());
+        self.rewind = Some(rewind);
+    }
 
-        // Return false if LY has advanced past the vblank stage.
-        previous_lcd_ly < 144
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
     }
 
-    pub fn emulate_next_frame(&mut self, frame: &mut [u8]) {
-        assert!(frame.len() == 160 * 144); // rwtodo constants. i have constants in lcd.
+    /// Go back to the newest snapshot that's at least this many frames old, or the oldest snapshot if there
+    /// isn't one. Newer snapshots are dropped. Returns how many frames were rewound, which is 0 if rewind
+    /// isn't enabled. Breakpoints, serial output and other host settings are left as they are.
+    pub fn rewind(&mut self, frames: u32) -> u64 {
+        let Some(mut rewind) = self.rewind.take() else {
+            return 0;
+        };
+
+        let frames_rewound = match rewind.rewind(frames) {
+            Some((frames_rewound, state)) => {
+                self.load_state(state);
+                frames_rewound
+            }
+            None => 0,
+        };
 
-        // Call the function until the vblank phase is exited.
-        while self.emulate_next_line_of_frame(frame) == false {}
+        self.rewind = Some(rewind);
+        frames_rewound
+    }
 
-        // Call the function until the vblank phase is entered again.
-        while self.emulate_next_line_of_frame(frame) == true {}
+    /// The memory used by rewind snapshots, in bytes.
+    pub fn rewind_buffer_size(&self) -> usize {
+        self.rewind.as_ref().map_or(0, RewindBuffer::size)
+    }
+
+    /// The most recently completed frame: 160x144 pixels row by row, in the format set with
+    /// set_pixel_format (one grayscale byte per pixel by default). It's replaced when V-blank is entered.
+    pub fn frame(&self) -> &[u8] {
+        self.lcd.frame()
+    }
+
+    /// The frame currently being rendered. Lines that haven't been rendered yet are shade 0.
+    pub fn frame_in_progress(&self) -> &[u8] {
+        self.lcd.frame_in_progress()
+    }
+
+    /// True if a frame has been completed since the last call.
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.lcd.frame_ready)
+    }
+
+    /// Set how frame() stores its pixels. Both frame buffers are cleared, so call this before emulating.
+    pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+        let palette = self.lcd.palette();
+        self.lcd.set_output(pixel_format, palette);
+    }
+
+    /// Set the colours of the 4 shades. It's ignored by PixelFormat::Shades. Both frame buffers are cleared.
+    pub fn set_palette(&mut self, palette: Palette) {
+        let pixel_format = self.lcd.pixel_format();
+        self.lcd.set_output(pixel_format, palette);
+    }
+
+    pub fn render_options(&self) -> RenderOptions {
+        self.lcd.render_options()
+    }
+
+    /// Hide layers or draw debug overlays from the next line onwards, without changing LCDC.
+    pub fn set_render_options(&mut self, options: RenderOptions) {
+        self.lcd.set_render_options(options);
+    }
+
+    /// All 384 tiles from 0x8000-0x97ff, 16 to a row, in the current pixel format. Each tile's colour
+    /// numbers 0-3 are shown with the palette's 4 colours.
+    pub fn tile_data_image(&self, palette: &Palette) -> Image {
+        self.lcd.tile_data_image(&self.memory, palette)
+    }
+
+    /// A 256x256 image of a background tile map, in the current pixel format, drawn with BGP, LCDC's
+    /// tile data and the given palette. The viewport at SCX/SCY is outlined.
+    pub fn tile_map_image(&self, map: TileMap, palette: &Palette) -> Image {
+        self.lcd.tile_map_image(&self.memory, map, palette)
+    }
+
+    /// The 40 objects in OAM.
+    pub fn oam(&self) -> [OamEntry; 40] {
+        lcd::oam(&self.memory)
+    }
+
+    /// Call back with LY and the pixels of each line as soon as it's rendered, e.g. to draw raster
+    /// overlays. The pixels are in the output format. Changes to them appear in the frame.
+    pub fn set_scanline_callback(&mut self, callback: Option<ScanlineCallback>) {
+        self.lcd.scanline_callback = callback;
     }
 
     // Inform the emulator of button state with this function. All buttons are up (unpressed) when emulation starts.
     pub fn set_button(&mut self, button: &Button, is_down: bool) {
-        // rwtodo
+        if let Some(recorder) = &mut self.movie_recorder {
+            recorder.record_input(self.cycles, *button, is_down);
+        }
+        self.memory.set_button(button, is_down);
+    }
+
+    /// Execute one instruction, even if a breakpoint is set at PC.
+    pub fn step_instruction(&mut self) -> StopReason {
+        self.debugger.skip_next_check = true;
+        self.emulate_next_instruction()
+            .unwrap_or(StopReason::InstructionComplete)
+    }
+
+    /// Emulate until LY changes, unless a breakpoint, watchpoint or break condition stops emulation first.
+    pub fn step_scanline(&mut self) -> StopReason {
+        match self.emulate_next_line_of_frame() {
+            LineOutcome::Stopped(reason) => reason,
+            _ => StopReason::ScanlineComplete,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.debugger.breakpoints.push(breakpoint);
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.debugger.breakpoints.retain(|b| *b != breakpoint);
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.memory.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.memory.watchpoints.retain(|w| *w != watchpoint);
+    }
+
+    /// Cheats take effect straight away: Game Genie codes on the next read of ROM, and GameShark codes at
+    /// the next V-blank.
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.memory.cheats.push(cheat);
+    }
+
+    pub fn remove_cheat(&mut self, code: &str) {
+        let code = cheats::normalise_code(code);
+        self.memory
+            .cheats
+            .retain(|c| cheats::normalise_code(&c.code) != code);
+    }
+
+    /// Returns false if no cheat has this code.
+    pub fn set_cheat_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        let code = cheats::normalise_code(code);
+        let mut found = false;
+        for cheat in &mut self.memory.cheats {
+            if cheats::normalise_code(&cheat.code) == code {
+                cheat.enabled = enabled;
+                found = true;
+            }
+        }
+        found
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.memory.cheats
+    }
+
+    pub fn add_break_condition(&mut self, condition: BreakCondition) {
+        self.debugger.conditions.push(condition);
+    }
+
+    pub fn remove_break_condition(&mut self, condition: BreakCondition) {
+        self.debugger.conditions.retain(|c| *c != condition);
+    }
+
+    /// Stop before executing LD B,B, the software breakpoint used by test roms such as mooneye's.
+    pub fn set_break_on_ld_b_b(&mut self, enabled: bool) {
+        self.debugger.break_on_ld_b_b = enabled;
+    }
+
+    pub fn register(&self, register: Register) -> u16 {
+        self.cpu.registers().get(register)
+    }
+
+    /// 8-bit registers take the lower byte of value.
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        self.cpu.registers_mut().set(register, value);
+    }
+
+    /// Read a byte without side effects. Unlike the CPU, this can read from anywhere.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.memory.peek(address)
+    }
+
+    /// Write a byte without side effects. This can patch ROM and hardware registers directly.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.memory.poke(address, value);
+    }
+
+    /// Decode the instruction at address, as it's currently mapped.
+    pub fn disassemble(&self, address: u16) -> disasm::Instruction {
+        let bytes = [0, 1, 2].map(|offset| self.peek(address.wrapping_add(offset)));
+        disasm::decode(&bytes, address)
+    }
+
+    /// Log every executed instruction to the tracer's sink, or stop tracing with None.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Make reads of LY return a fixed value. Gameboy Doctor's reference logs are made with LY fixed at 0x90.
+    pub fn set_ly_override(&mut self, ly: Option<u8>) {
+        self.memory.ly_override = ly;
+    }
+
+    /// The ROM bank currently mapped at address, or None if address isn't in ROM.
+    pub fn rom_bank_at(&self, address: u16) -> Option<u8> {
+        self.memory.rom_bank_at(address)
+    }
+}
+
+// Builds a 32kB ROM-only cart with the program at the entry point (0x0100).
+#[cfg(test)]
+fn test_rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_frame_buffer_and_scanline_callback() {
+        // loop: JR loop
+        let mut game_boy = GameBoy::new(&test_rom(&[0x18, 0xfe]));
+        assert!(!game_boy.take_frame_ready());
+
+        // Mark the first pixel of every line as it's rendered.
+        let lines = Arc::new(Mutex::new(vec![]));
+        let callback_lines = lines.clone();
+        game_boy.set_scanline_callback(Some(Box::new(move |ly, pixels| {
+            callback_lines.lock().unwrap().push(ly);
+            pixels[0] = 0x42;
+        })));
+
+        assert_eq!(game_boy.emulate_next_frame(), StopReason::FrameComplete);
+        assert!(game_boy.take_frame_ready());
+        assert!(!game_boy.take_frame_ready());
+
+        assert_eq!(*lines.lock().unwrap(), (0..144).collect::<Vec<u8>>());
+        assert_eq!(game_boy.frame().len(), 160 * 144);
+        assert!(game_boy.frame().chunks(160).all(|line| line[0] == 0x42));
+
+        // Nothing has been rendered into the next frame yet.
+        assert!(game_boy
+            .frame_in_progress()
+            .iter()
+            .all(|&pixel| pixel == 0xff));
+    }
+
+    #[test]
+    fn test_ly_override() {
+        // loop: LDH A,(0x44); LD (0xc000),A; JR loop
+        let mut game_boy = GameBoy::new(&test_rom(&[0xf0, 0x44, 0xea, 0x00, 0xc0, 0x18, 0xf9]));
+        game_boy.set_ly_override(Some(0x90));
+
+        let lines = Arc::new(Mutex::new(vec![]));
+        let callback_lines = lines.clone();
+        game_boy.set_scanline_callback(Some(Box::new(move |ly, _| {
+            callback_lines.lock().unwrap().push(ly);
+        })));
+
+        // The CPU sees the override, but the LCD still renders every line and presents the frame.
+        game_boy.emulate_next_frame();
+        assert!(game_boy.take_frame_ready());
+        assert_eq!(game_boy.peek(0xc000), 0x90);
+        assert_eq!(*lines.lock().unwrap(), (0..144).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_object_flip_y() {
+        // loop: JR loop
+        let mut game_boy = GameBoy::new(&test_rom(&[0x18, 0xfe]));
+        game_boy.set_pixel_format(PixelFormat::Shades);
+
+        // Tile 1 has a dark top line, and tile 2 a dark bottom line.
+        game_boy.poke(0x8010, 0xff);
+        game_boy.poke(0x8011, 0xff);
+        game_boy.poke(0x802e, 0xff);
+        game_boy.poke(0x802f, 0xff);
+        game_boy.poke(0xff48, 0xe4);
+
+        let dark_rows = |game_boy: &mut GameBoy| -> Vec<usize> {
+            game_boy.emulate_next_frame();
+            (0..16)
+                .filter(|row| game_boy.frame()[row * Lcd::WIDTH] != 0)
+                .collect()
+        };
+
+        // An object at the top left, using tile 1.
+        game_boy.poke(0xfe00, 16);
+        game_boy.poke(0xfe01, 8);
+        game_boy.poke(0xfe02, 1);
+        game_boy.poke(0xff40, 0x82);
+        assert_eq!(dark_rows(&mut game_boy), [0]);
+
+        game_boy.poke(0xfe03, 0x40);
+        assert_eq!(dark_rows(&mut game_boy), [7]);
+
+        // 8x16 objects use tiles 0 and 1, or 2 and 3, and flip as a whole.
+        game_boy.poke(0xff40, 0x86);
+        game_boy.poke(0xfe03, 0x00);
+        assert_eq!(dark_rows(&mut game_boy), [8]);
+        game_boy.poke(0xfe03, 0x40);
+        assert_eq!(dark_rows(&mut game_boy), [7]);
+        game_boy.poke(0xfe02, 2);
+        assert_eq!(dark_rows(&mut game_boy), [8]);
+    }
+
+    #[test]
+    fn test_window_line_counter() {
+        // loop: JR loop
+        let mut game_boy = GameBoy::new(&test_rom(&[0x18, 0xfe]));
+        game_boy.set_pixel_format(PixelFormat::Shades);
+
+        // Tile 1 is dark, and makes up the window's second row of tiles. The background is blank.
+        for offset in 0..16 {
+            game_boy.poke(0x8010 + offset, 0xff);
+        }
+        game_boy.poke(0x9c20, 1);
+        game_boy.poke(0xff47, 0xe4);
+        game_boy.poke(0xff4a, 0);
+        game_boy.poke(0xff4b, 7);
+
+        let dark_rows = |game_boy: &GameBoy| -> Vec<usize> {
+            (0..32)
+                .filter(|row| game_boy.frame()[row * Lcd::WIDTH] != 0)
+                .collect()
+        };
+
+        // Hide the window until line 8, so that its first line is drawn there.
+        game_boy.poke(0xff40, 0xd1);
+        game_boy.emulate_next_frame();
+        while game_boy.peek(address::LCD_LY) != 8 {
+            game_boy.step_scanline();
+        }
+        game_boy.poke(0xff40, 0xf1);
+        while game_boy.peek(address::LCD_LY) != 12 {
+            game_boy.step_scanline();
+        }
+        let state = game_boy.save_state();
+        game_boy.emulate_next_frame();
+        assert_eq!(dark_rows(&game_boy), (16..24).collect::<Vec<_>>());
+
+        // The counter is saved, so loading mid-frame carries on from the same window line.
+        game_boy.load_state(&state);
+        game_boy.emulate_next_frame();
+        assert_eq!(dark_rows(&game_boy), (16..24).collect::<Vec<_>>());
+
+        // The counter starts again from the top of the next frame.
+        game_boy.emulate_next_frame();
+        assert_eq!(dark_rows(&game_boy), (8..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_pixel_format_and_palette() {
+        // loop: JR loop
+        let mut game_boy = GameBoy::new(&test_rom(&[0x18, 0xfe]));
+        game_boy.set_pixel_format(PixelFormat::Rgba8888);
+        game_boy.set_palette(Palette::DMG_GREEN);
+        assert_eq!(game_boy.frame().len(), 160 * 144 * 4);
+
+        // The tiles are blank, so every pixel is the lightest colour.
+        game_boy.emulate_next_frame();
+        assert!(game_boy
+            .frame()
+            .chunks(4)
+            .all(|pixel| pixel == [0x9b, 0xbc, 0x0f, 0xff]));
+
+        game_boy.set_pixel_format(PixelFormat::Shades);
+        game_boy.emulate_next_frame();
+        assert_eq!(game_boy.frame().len(), 160 * 144);
+        assert!(game_boy.frame().iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn test_render_options() {
+        // loop: JR loop
+        let mut game_boy = GameBoy::new(&test_rom(&[0x18, 0xfe]));
+
+        // Make tile 0, which fills the background, black.
+        for address in 0x8000..0x8010 {
+            game_boy.poke(address, 0xff);
+        }
+        game_boy.emulate_next_frame();
+        assert!(game_boy.frame().iter().all(|&pixel| pixel == 0x00));
+
+        game_boy.set_render_options(RenderOptions {
+            background: false,
+            ..Default::default()
+        });
+        game_boy.emulate_next_frame();
+        assert!(game_boy.frame().iter().all(|&pixel| pixel == 0xff));
+
+        // Tint the background blue, and outline the window, which is at the top left.
+        game_boy.set_pixel_format(PixelFormat::Rgba8888);
+        game_boy.set_render_options(RenderOptions {
+            window_origin: true,
+            layer_colours: true,
+            ..Default::default()
+        });
+        game_boy.poke(0xff4b, 7);
+        game_boy.emulate_next_frame();
+
+        let frame = game_boy.frame();
+        let pixel = |x: usize, y: usize| &frame[(y * 160 + x) * 4..(y * 160 + x + 1) * 4];
+        assert_eq!(pixel(0, 0), [0x00, 0xff, 0xff, 0xff]);
+        assert_eq!(pixel(0, 100), [0x00, 0xff, 0xff, 0xff]);
+        assert_eq!(pixel(1, 1), [0x00, 0x00, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_rewind() {
+        // LD HL,0xc000; loop: INC (HL); JR loop
+        let program = [0x21, 0x00, 0xc0, 0x34, 0x18, 0xfd];
+        let mut game_boy = GameBoy::new(&test_rom(&program));
+        assert_eq!(game_boy.rewind(1), 0);
+
+        game_boy.enable_rewind(2, 1024 * 1024);
+        let mut counters = vec![game_boy.peek(0xc000)];
+        for _ in 0..10 {
+            game_boy.emulate_next_frame();
+            counters.push(game_boy.peek(0xc000));
+        }
+
+        // Only 11 bytes or so change each frame, so the older snapshots are tiny.
+        let snapshot_size = game_boy.save_state().len();
+        assert!(game_boy.rewind_buffer_size() < snapshot_size + 5 * 1024);
+
+        assert_eq!(game_boy.rewind(4), 4);
+        assert_eq!(game_boy.peek(0xc000), counters[6]);
+        assert_eq!(game_boy.rewind(1), 2);
+        assert_eq!(game_boy.peek(0xc000), counters[4]);
+
+        // Emulation carries on from the rewound state.
+        game_boy.emulate_next_frame();
+        assert_eq!(game_boy.peek(0xc000), counters[5]);
+    }
+
+    #[test]
+    fn test_set_button() {
+        let mut game_boy = GameBoy::new(&test_rom(&[]));
+
+        // Select the direction buttons.
+        game_boy.memory.write(0xff00, 0x20);
+        assert_eq!(game_boy.peek(0xff00) & 0x0f, 0x0f);
+
+        game_boy.set_button(&Button::DOWN, true);
+        game_boy.set_button(&Button::A, true);
+        assert_eq!(game_boy.peek(0xff00) & 0x0f, 0x07);
+
+        game_boy.set_button(&Button::DOWN, false);
+        assert_eq!(game_boy.peek(0xff00) & 0x0f, 0x0f);
+
+        // Select the action buttons.
+        game_boy.memory.write(0xff00, 0x10);
+        assert_eq!(game_boy.peek(0xff00) & 0x0f, 0x0e);
+    }
+
+    #[test]
+    fn test_joypad_interrupt() {
+        let mut game_boy = GameBoy::new(&test_rom(&[]));
+        let joypad_requested = |game_boy: &GameBoy| {
+            game_boy.peek(address::INTERRUPT_FLAGS) & interrupt::FLAG_JOYPAD != 0
+        };
+
+        // Select the action buttons.
+        game_boy.memory.write(0xff00, 0x10);
+        game_boy.poke(address::INTERRUPT_FLAGS, 0xe0);
+
+        // Setting buttons that are already up, or releasing them, doesn't request it.
+        game_boy.set_button(&Button::A, false);
+        game_boy.set_button(&Button::UP, true);
+        assert!(!joypad_requested(&game_boy));
+
+        game_boy.set_button(&Button::A, true);
+        assert!(joypad_requested(&game_boy));
+
+        game_boy.poke(address::INTERRUPT_FLAGS, 0xe0);
+        game_boy.set_button(&Button::A, true);
+        game_boy.set_button(&Button::A, false);
+        assert!(!joypad_requested(&game_boy));
+    }
+
+    #[test]
+    fn test_clone() {
+        fn assert_send<T: Send>() {}
+        assert_send::<GameBoy>();
+
+        // LD HL,0xc000; loop: INC (HL); JR loop
+        let rom: Arc<[u8]> = test_rom(&[0x21, 0x00, 0xc0, 0x34, 0x18, 0xfd]).into();
+        let mut game_boy = GameBoy::with_shared_rom(rom.clone());
+        game_boy.emulate_next_frame();
+
+        game_boy.enable_rewind(1, 1 << 20);
+        game_boy.start_recording_movie();
+        game_boy.emulate_next_frame();
+
+        let mut clone = game_boy.clone();
+        assert_eq!(clone.save_state(), game_boy.save_state());
+        assert_eq!(clone.frame(), game_boy.frame());
+        assert_eq!(clone.rewind_buffer_size(), 0);
+        assert!(clone.stop_recording_movie().is_none());
+        assert_ne!(game_boy.rewind_buffer_size(), 0);
+
+        // They run independently.
+        clone.emulate_next_frame();
+        assert_ne!(clone.peek(0xc000), game_boy.peek(0xc000));
+        game_boy.emulate_next_frame();
+        assert_eq!(clone.save_state(), game_boy.save_state());
+
+        // Poking ROM only changes the GameBoy that was poked.
+        clone.poke(0x0105, 0xfe);
+        assert_eq!(clone.peek(0x0105), 0xfe);
+        assert_eq!(game_boy.peek(0x0105), 0xfd);
+        assert_eq!(rom[0x0105], 0xfd);
+    }
+
+    #[test]
+    fn test_short_rom() {
+        // Half the size the header says, so the second bank reads as 0xff.
+        let mut rom = test_rom(&[0x18, 0xfe]);
+        rom.truncate(0x4000);
+        let game_boy = GameBoy::new(&rom);
+        assert_eq!(game_boy.peek(0x0100), 0x18);
+        assert_eq!(game_boy.peek(0x4000), 0xff);
     }
 }
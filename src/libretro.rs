@@ -0,0 +1,833 @@
+// A libretro core, built with `cargo rustc --release --lib --crate-type cdylib --features libretro`, so that
+// RetroArch and other libretro frontends can run robin_gb. The frontend loads the cdylib (librobin_gb.so on
+// Linux) and drives it through the retro_* functions below, which follow libretro.h from libretro-common. Only
+// the parts of the API that a Game Boy needs are declared here.
+//
+// The API has no handle for a core instance, so the loaded game and the frontend's callbacks are statics.
+// Frontends call everything from one thread, so the mutexes are only there to make the statics safe to share.
+
+use crate::audio::CLOCK_RATE;
+use crate::cheats::Cheat;
+use crate::lcd::Lcd;
+use crate::{Button, GameBoy, Palette, PixelFormat};
+use std::ffi::{c_char, c_void, CStr};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex};
+
+const API_VERSION: u32 = 1;
+const SAMPLE_RATE: u32 = 48_000;
+const CYCLES_PER_FRAME: u32 = 70224;
+
+const LIBRARY_NAME: &CStr = c"robin_gb";
+const LIBRARY_VERSION: &CStr =
+    match CStr::from_bytes_with_nul(concat!(env!("CARGO_PKG_VERSION"), "\0").as_bytes()) {
+        Ok(version) => version,
+        Err(_) => panic!("Invalid package version"),
+    };
+const VALID_EXTENSIONS: &CStr = c"gb|dmg";
+
+// The RETRO_ENVIRONMENT_ commands the core sends.
+mod environment {
+    pub const SET_PIXEL_FORMAT: u32 = 10;
+    pub const GET_VARIABLE: u32 = 15;
+    pub const SET_VARIABLES: u32 = 16;
+    pub const GET_VARIABLE_UPDATE: u32 = 17;
+    pub const SET_MEMORY_MAPS: u32 = 36 | 0x10000; // RETRO_ENVIRONMENT_EXPERIMENTAL
+}
+
+// RETRO_PIXEL_FORMAT_
+const PIXEL_FORMAT_XRGB8888: u32 = 1;
+const PIXEL_FORMAT_RGB565: u32 = 2;
+
+// RETRO_MEMORY_
+const MEMORY_SAVE_RAM: u32 = 0;
+const MEMORY_RTC: u32 = 1;
+const MEMORY_SYSTEM_RAM: u32 = 2;
+const MEMORY_VIDEO_RAM: u32 = 3;
+
+// RETRO_MEMDESC_
+const MEMDESC_SYSTEM_RAM: u64 = 1 << 2;
+const MEMDESC_SAVE_RAM: u64 = 1 << 3;
+const MEMDESC_VIDEO_RAM: u64 = 1 << 4;
+
+const DEVICE_JOYPAD: u32 = 1;
+const REGION_NTSC: u32 = 0;
+
+// RETRO_DEVICE_ID_JOYPAD_ values, and the buttons they map to.
+const JOYPAD_BUTTONS: [(u32, Button); 8] = [
+    (0, Button::B),
+    (2, Button::SELECT),
+    (3, Button::START),
+    (4, Button::UP),
+    (5, Button::DOWN),
+    (6, Button::LEFT),
+    (7, Button::RIGHT),
+    (8, Button::A),
+];
+
+// Core options.
+const PALETTE_KEY: &CStr = c"robin_gb_palette";
+const MODEL_KEY: &CStr = c"robin_gb_model";
+const PALETTES: [(&str, Palette); 4] = [
+    ("Grayscale", Palette::GRAYSCALE),
+    ("DMG green", Palette::DMG_GREEN),
+    ("Pocket", Palette::POCKET),
+    ("Light", Palette::LIGHT),
+];
+
+// RAM regions exposed through SET_MEMORY_MAPS: flags, start and length.
+const MEMORY_MAP: [(u64, u16, usize); 4] = [
+    (MEMDESC_VIDEO_RAM, 0x8000, 0x2000),
+    (MEMDESC_SAVE_RAM, 0xa000, 0x2000),
+    (MEMDESC_SYSTEM_RAM, 0xc000, 0x2000),
+    (MEMDESC_SYSTEM_RAM, 0xff80, 0x7f),
+];
+
+#[repr(C)]
+pub struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    base_width: u32,
+    base_height: u32,
+    max_width: u32,
+    max_height: u32,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    geometry: GameGeometry,
+    timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct Variable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+pub struct MemoryDescriptor {
+    flags: u64,
+    ptr: *mut c_void,
+    offset: usize,
+    start: usize,
+    select: usize,
+    disconnect: usize,
+    len: usize,
+    addrspace: *const c_char,
+}
+
+#[repr(C)]
+pub struct MemoryMap {
+    descriptors: *const MemoryDescriptor,
+    num_descriptors: u32,
+}
+
+type EnvironmentFn = extern "C" fn(command: u32, data: *mut c_void) -> bool;
+type VideoRefreshFn = extern "C" fn(data: *const c_void, width: u32, height: u32, pitch: usize);
+type AudioSampleFn = extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = extern "C" fn();
+type InputStateFn = extern "C" fn(port: u32, device: u32, index: u32, id: u32) -> i16;
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+struct Core {
+    // Boxed so that the pointers handed out by SET_MEMORY_MAPS and retro_get_memory_data() stay valid.
+    game_boy: Box<GameBoy>,
+    rom: Arc<[u8]>,
+    pixel_format: PixelFormat,
+    // Set when emulation panics. The last frame is shown until a state is loaded or the game is reset.
+    crashed: bool,
+}
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap()
+}
+
+fn set_callbacks(set: impl FnOnce(&mut Callbacks)) {
+    set(&mut CALLBACKS.lock().unwrap());
+}
+
+fn environment<T>(command: u32, data: &mut T) -> bool {
+    callbacks()
+        .environment
+        .is_some_and(|environment| environment(command, (data as *mut T).cast()))
+}
+
+fn variable(key: &CStr) -> Option<String> {
+    let mut variable = Variable {
+        key: key.as_ptr(),
+        value: ptr::null(),
+    };
+    if !environment(environment::GET_VARIABLE, &mut variable) || variable.value.is_null() {
+        return None;
+    }
+    // The frontend owns the value, so copy it.
+    Some(
+        unsafe { CStr::from_ptr(variable.value) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+// Applies the settings that GameBoy::new() doesn't know about.
+fn set_up(game_boy: &mut GameBoy, pixel_format: PixelFormat) {
+    game_boy.set_pixel_format(pixel_format);
+    game_boy.record_audio(Some(SAMPLE_RATE));
+    apply_options(game_boy);
+}
+
+fn apply_options(game_boy: &mut GameBoy) {
+    let name = variable(PALETTE_KEY);
+    if let Some((_, palette)) = PALETTES.iter().find(|(n, _)| Some(*n) == name.as_deref()) {
+        game_boy.set_palette(*palette);
+    }
+    // rwtodo: read MODEL_KEY once Game Boy Color emulation exists. Until then it only has one value.
+}
+
+fn memory_region(core: &mut Core, id: u32) -> Option<&mut [u8]> {
+    let memory = &mut core.game_boy.memory;
+    match id {
+        MEMORY_SAVE_RAM if memory.has_battery() => Some(&mut memory.ram_from(0xa000)[..0x2000]),
+        MEMORY_SYSTEM_RAM => Some(&mut memory.ram_from(0xc000)[..0x2000]),
+        MEMORY_VIDEO_RAM => Some(&mut memory.ram_from(0x8000)[..0x2000]),
+        // rwtodo: MEMORY_RTC, once MBC3's clock is emulated.
+        _ => None,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> u32 {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    set_callbacks(|callbacks| callbacks.environment = Some(callback));
+
+    // Options are declared as "Description; default|other values".
+    let mut variables = [
+        Variable {
+            key: PALETTE_KEY.as_ptr(),
+            value: c"Palette; Grayscale|DMG green|Pocket|Light".as_ptr(),
+        },
+        Variable {
+            key: MODEL_KEY.as_ptr(),
+            value: c"Model; Game Boy".as_ptr(),
+        },
+        Variable {
+            key: ptr::null(),
+            value: ptr::null(),
+        },
+    ];
+    environment(environment::SET_VARIABLES, &mut variables);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    set_callbacks(|callbacks| callbacks.video_refresh = Some(callback));
+}
+
+// Audio is sent in batches, so the single sample callback isn't used.
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    set_callbacks(|callbacks| callbacks.audio_sample_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    set_callbacks(|callbacks| callbacks.input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    set_callbacks(|callbacks| callbacks.input_state = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// # Safety
+/// info must point to a retro_system_info.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: LIBRARY_NAME.as_ptr(),
+        library_version: LIBRARY_VERSION.as_ptr(),
+        valid_extensions: VALID_EXTENSIONS.as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// info must point to a retro_system_av_info.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: Lcd::WIDTH as u32,
+            base_height: Lcd::HEIGHT as u32,
+            max_width: Lcd::WIDTH as u32,
+            max_height: Lcd::HEIGHT as u32,
+            aspect_ratio: Lcd::WIDTH as f32 / Lcd::HEIGHT as f32,
+        },
+        timing: SystemTiming {
+            fps: f64::from(CLOCK_RATE) / f64::from(CYCLES_PER_FRAME),
+            sample_rate: f64::from(SAMPLE_RATE),
+        },
+    };
+}
+
+// There's only the one controller.
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: u32, _device: u32) {}
+
+// Power cycles the Game Boy. Battery-backed RAM and cheats survive it.
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    let mut core = CORE.lock().unwrap();
+    let Some(core) = core.as_mut() else {
+        return;
+    };
+
+    let mut game_boy = GameBoy::with_shared_rom(core.rom.clone());
+    set_up(&mut game_boy, core.pixel_format);
+    game_boy.memory.ram_from(0xa000)[..0x2000]
+        .copy_from_slice(&core.game_boy.memory.ram_from(0xa000)[..0x2000]);
+    for cheat in core.game_boy.cheats() {
+        game_boy.add_cheat(cheat.clone());
+    }
+
+    // Replaced in place, so that pointers into its memory stay valid.
+    *core.game_boy = game_boy;
+    core.crashed = false;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    let mut options_changed = false;
+    environment(environment::GET_VARIABLE_UPDATE, &mut options_changed);
+
+    let mut core = CORE.lock().unwrap();
+    let Some(core) = core.as_mut() else {
+        return;
+    };
+    let game_boy = &mut core.game_boy;
+
+    if options_changed {
+        apply_options(game_boy);
+    }
+
+    if !core.crashed {
+        if let Some(input_poll) = callbacks.input_poll {
+            input_poll();
+        }
+        if let Some(input_state) = callbacks.input_state {
+            for (id, button) in &JOYPAD_BUTTONS {
+                game_boy.set_button(button, input_state(0, DEVICE_JOYPAD, 0, *id) != 0);
+            }
+        }
+
+        // Invalid opcodes and unemulated hardware panic, which can't unwind into the frontend.
+        core.crashed =
+            panic::catch_unwind(AssertUnwindSafe(|| game_boy.emulate_next_frame())).is_err();
+    }
+
+    if let Some(video_refresh) = callbacks.video_refresh {
+        let frame = game_boy.frame();
+        let pitch = frame.len() / Lcd::HEIGHT;
+        video_refresh(
+            frame.as_ptr().cast(),
+            Lcd::WIDTH as u32,
+            Lcd::HEIGHT as u32,
+            pitch,
+        );
+    }
+
+    let samples = game_boy.take_audio_samples();
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        // The frontend can take fewer frames than it's given, so keep going until it's had them all.
+        let mut offset = 0;
+        while offset < samples.len() {
+            let frames = (samples.len() - offset) / 2;
+            let taken = audio_sample_batch(samples[offset..].as_ptr(), frames);
+            if taken == 0 {
+                break;
+            }
+            offset += taken * 2;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    CORE.lock()
+        .unwrap()
+        .as_ref()
+        .map_or(0, |core| core.game_boy.save_state().len())
+}
+
+/// # Safety
+/// data must point to size writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = CORE.lock().unwrap();
+    let Some(core) = core.as_ref() else {
+        return false;
+    };
+    // A crashed Game Boy could be part way through an instruction.
+    if core.crashed {
+        return false;
+    }
+    let Ok(state) = panic::catch_unwind(AssertUnwindSafe(|| core.game_boy.save_state())) else {
+        return false;
+    };
+    if data.is_null() || size < state.len() {
+        return false;
+    }
+    slice::from_raw_parts_mut(data.cast::<u8>(), state.len()).copy_from_slice(&state);
+    true
+}
+
+/// # Safety
+/// data must point to size readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = CORE.lock().unwrap();
+    let Some(core) = core.as_mut() else {
+        return false;
+    };
+    if data.is_null() {
+        return false;
+    }
+    let state = slice::from_raw_parts(data.cast::<u8>(), size);
+    if core.game_boy.check_state(state).is_err() {
+        return false;
+    }
+
+    // A state from another build can still fail part way through loading. A state that loads replaces
+    // everything, so it recovers from a crash.
+    let game_boy = &mut core.game_boy;
+    core.crashed = panic::catch_unwind(AssertUnwindSafe(|| game_boy.load_state(state))).is_err();
+    !core.crashed
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    if let Some(core) = CORE.lock().unwrap().as_mut() {
+        core.game_boy.memory.cheats.clear();
+    }
+}
+
+/// Frontends join the codes of multi-code cheats with '+'. Codes that don't decode are ignored.
+///
+/// # Safety
+/// code must be a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(index: u32, enabled: bool, code: *const c_char) {
+    let mut core = CORE.lock().unwrap();
+    let Some(core) = core.as_mut() else {
+        return;
+    };
+    if code.is_null() {
+        return;
+    }
+    let code = CStr::from_ptr(code).to_string_lossy();
+    for code in code.split('+') {
+        if let Ok(mut cheat) = Cheat::new(code, &format!("Cheat {}", index)) {
+            cheat.enabled = enabled;
+            core.game_boy.add_cheat(cheat);
+        }
+    }
+}
+
+/// # Safety
+/// game must point to a retro_game_info, whose data points to size readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    let Some(game) = game.as_ref() else {
+        return false;
+    };
+    if game.data.is_null() {
+        return false;
+    }
+    let rom: Arc<[u8]> = slice::from_raw_parts(game.data.cast::<u8>(), game.size).into();
+
+    // Unsupported or truncated ROMs panic while loading.
+    let Ok(game_boy) =
+        panic::catch_unwind(AssertUnwindSafe(|| GameBoy::with_shared_rom(rom.clone())))
+    else {
+        return false;
+    };
+
+    // libretro's formats are native-endian, which these match on little-endian machines.
+    // rwtodo: swap the bytes on big-endian machines.
+    let pixel_format = if environment(environment::SET_PIXEL_FORMAT, &mut { PIXEL_FORMAT_RGB565 }) {
+        PixelFormat::Rgb565
+    } else if environment(environment::SET_PIXEL_FORMAT, &mut {
+        PIXEL_FORMAT_XRGB8888
+    }) {
+        PixelFormat::Bgra8888
+    } else {
+        return false;
+    };
+
+    let mut core = Core {
+        game_boy: Box::new(game_boy),
+        rom,
+        pixel_format,
+        crashed: false,
+    };
+    set_up(&mut core.game_boy, pixel_format);
+
+    // Lets frontends find game variables, e.g. for achievements. They copy the descriptors.
+    let mut descriptors: Vec<MemoryDescriptor> = MEMORY_MAP
+        .iter()
+        .map(|(flags, start, len)| MemoryDescriptor {
+            flags: *flags,
+            ptr: core.game_boy.memory.ram_from(*start).as_mut_ptr().cast(),
+            offset: 0,
+            start: usize::from(*start),
+            select: 0,
+            disconnect: 0,
+            len: *len,
+            addrspace: ptr::null(),
+        })
+        .collect();
+    let mut map = MemoryMap {
+        descriptors: descriptors.as_mut_ptr(),
+        num_descriptors: descriptors.len() as u32,
+    };
+    environment(environment::SET_MEMORY_MAPS, &mut map);
+
+    *CORE.lock().unwrap() = Some(core);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: u32,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> u32 {
+    REGION_NTSC
+}
+
+/// The frontend reads and writes these in place, e.g. to load and save battery-backed RAM.
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: u32) -> *mut c_void {
+    let mut core = CORE.lock().unwrap();
+    core.as_mut()
+        .and_then(|core| memory_region(core, id))
+        .map_or(ptr::null_mut(), |region| region.as_mut_ptr().cast())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: u32) -> usize {
+    let mut core = CORE.lock().unwrap();
+    core.as_mut()
+        .and_then(|core| memory_region(core, id))
+        .map_or(0, |region| region.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Register;
+
+    // A headless frontend, recording what the core sends it.
+    struct Frontend {
+        pixel_format: Option<u32>,
+        variables: Vec<String>,
+        palette: &'static CStr,
+        options_changed: bool,
+        memory_descriptor_count: u32,
+        frame: Vec<u8>,
+        pitch: usize,
+        audio_frames: usize,
+        poll_count: u32,
+        // Bits are RETRO_DEVICE_ID_JOYPAD_ values.
+        buttons: u16,
+    }
+
+    static FRONTEND: Mutex<Frontend> = Mutex::new(Frontend {
+        pixel_format: None,
+        variables: vec![],
+        palette: c"Grayscale",
+        options_changed: false,
+        memory_descriptor_count: 0,
+        frame: vec![],
+        pitch: 0,
+        audio_frames: 0,
+        poll_count: 0,
+        buttons: 0,
+    });
+
+    fn frontend() -> std::sync::MutexGuard<'static, Frontend> {
+        FRONTEND.lock().unwrap()
+    }
+
+    extern "C" fn environment(command: u32, data: *mut c_void) -> bool {
+        let mut frontend = frontend();
+        unsafe {
+            match command {
+                environment::SET_PIXEL_FORMAT => {
+                    frontend.pixel_format = Some(*data.cast::<u32>());
+                    true
+                }
+                environment::SET_VARIABLES => {
+                    let mut variable = data.cast::<Variable>();
+                    while !(*variable).key.is_null() {
+                        let key = CStr::from_ptr((*variable).key);
+                        frontend.variables.push(key.to_str().unwrap().to_string());
+                        variable = variable.add(1);
+                    }
+                    true
+                }
+                environment::GET_VARIABLE => {
+                    let variable = &mut *data.cast::<Variable>();
+                    if CStr::from_ptr(variable.key) != PALETTE_KEY {
+                        return false;
+                    }
+                    variable.value = frontend.palette.as_ptr();
+                    true
+                }
+                environment::GET_VARIABLE_UPDATE => {
+                    *data.cast::<bool>() = std::mem::take(&mut frontend.options_changed);
+                    true
+                }
+                environment::SET_MEMORY_MAPS => {
+                    frontend.memory_descriptor_count = (*data.cast::<MemoryMap>()).num_descriptors;
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
+    extern "C" fn video_refresh(data: *const c_void, _width: u32, height: u32, pitch: usize) {
+        let mut frontend = frontend();
+        frontend.frame =
+            unsafe { slice::from_raw_parts(data.cast::<u8>(), pitch * height as usize) }.to_vec();
+        frontend.pitch = pitch;
+    }
+
+    extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+        frontend().audio_frames += frames;
+        frames
+    }
+
+    extern "C" fn input_poll() {
+        frontend().poll_count += 1;
+    }
+
+    extern "C" fn input_state(port: u32, device: u32, _index: u32, id: u32) -> i16 {
+        assert_eq!((port, device), (0, DEVICE_JOYPAD));
+        i16::from(frontend().buttons & (1 << id) != 0)
+    }
+
+    fn ram(id: u32, address: usize) -> u8 {
+        unsafe { *retro_get_memory_data(id).cast::<u8>().add(address) }
+    }
+
+    // The core is a singleton, so this is the only test that loads a game.
+    #[test]
+    fn test_frontend() {
+        // Reads the joypad into 0xc000 in a loop, and counts frames in 0xc001 via the V-blank interrupt.
+        let program = [
+            0x21, 0x01, 0xc0, 0xaf, 0xe0, 0x0f, 0x3c, 0xe0, 0xff, 0xfb, 0x3e, 0x10, 0xe0, 0x00,
+            0xf0, 0x00, 0xea, 0x00, 0xc0, 0x18, 0xf9,
+        ];
+        let mut rom = crate::test_rom(&program);
+        rom[0x0040..0x0042].copy_from_slice(&[0x34, 0xd9]);
+        rom[0x0147] = 0x03; // MBC1 with battery-backed RAM.
+
+        assert_eq!(retro_api_version(), 1);
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+        assert_eq!(frontend().variables, ["robin_gb_palette", "robin_gb_model"]);
+
+        let game = GameInfo {
+            path: ptr::null(),
+            data: rom.as_ptr().cast(),
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+        assert!(unsafe { retro_load_game(&game) });
+        assert_eq!(frontend().pixel_format, Some(PIXEL_FORMAT_RGB565));
+        assert_eq!(frontend().memory_descriptor_count, 4);
+
+        let mut av_info = std::mem::MaybeUninit::<SystemAvInfo>::uninit();
+        let av_info = unsafe {
+            retro_get_system_av_info(av_info.as_mut_ptr());
+            av_info.assume_init()
+        };
+        assert!((av_info.timing.fps - 59.7275).abs() < 0.001);
+
+        // Holding A shows up in the joypad register.
+        frontend().buttons = 1 << 8;
+        retro_run();
+        assert_eq!(ram(MEMORY_SYSTEM_RAM, 0) & 0x01, 0x00);
+        frontend().buttons = 0;
+        retro_run();
+        assert_eq!(ram(MEMORY_SYSTEM_RAM, 0) & 0x01, 0x01);
+        {
+            let frontend = frontend();
+            assert_eq!(frontend.poll_count, 2);
+            assert_eq!(frontend.pitch, Lcd::WIDTH * 2);
+            assert_eq!(frontend.frame.len(), Lcd::PIXEL_COUNT * 2);
+            // A sample for every 48kHz tick of the 2 frames. The first frame is short, as the LCD powers on part
+            // way through it.
+            let cycles = CORE.lock().unwrap().as_ref().unwrap().game_boy.cycles;
+            assert_eq!(
+                frontend.audio_frames as u64,
+                cycles * u64::from(SAMPLE_RATE) / u64::from(CLOCK_RATE)
+            );
+        }
+
+        // Save states.
+        let mut state = vec![0; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr().cast(), state.len()) });
+        let frame_count = ram(MEMORY_SYSTEM_RAM, 1);
+        retro_run();
+        assert_ne!(ram(MEMORY_SYSTEM_RAM, 1), frame_count);
+        assert!(unsafe { retro_unserialize(state.as_ptr().cast(), state.len()) });
+        assert_eq!(ram(MEMORY_SYSTEM_RAM, 1), frame_count);
+        assert!(!unsafe { retro_unserialize(state.as_ptr().cast(), 10) });
+
+        // After a panic, the core keeps showing the last frame without emulating, until a state is loaded.
+        {
+            let mut core = CORE.lock().unwrap();
+            let game_boy = &mut core.as_mut().unwrap().game_boy;
+            game_boy.poke(0xc100, 0xd3); // An invalid opcode.
+            game_boy.set_register(Register::PC, 0xc100);
+        }
+        let frame = std::mem::take(&mut frontend().frame);
+        retro_run();
+        assert!(CORE.lock().unwrap().as_ref().unwrap().crashed);
+        let poll_count = frontend().poll_count;
+        retro_run();
+        assert_eq!(frontend().poll_count, poll_count);
+        assert_eq!(frontend().frame, frame);
+        assert!(!unsafe { retro_serialize(state.as_mut_ptr().cast(), state.len()) });
+        assert!(unsafe { retro_unserialize(state.as_ptr().cast(), state.len()) });
+        retro_run();
+        assert_ne!(ram(MEMORY_SYSTEM_RAM, 1), frame_count);
+
+        // Battery-backed RAM is written in place, and survives a reset. RTC isn't emulated.
+        assert_eq!(retro_get_memory_size(MEMORY_SAVE_RAM), 0x2000);
+        assert_eq!(retro_get_memory_size(MEMORY_RTC), 0);
+        unsafe { *retro_get_memory_data(MEMORY_SAVE_RAM).cast::<u8>() = 0x42 };
+        retro_reset();
+        assert_eq!(ram(MEMORY_SAVE_RAM, 0), 0x42);
+        assert_eq!(ram(MEMORY_SYSTEM_RAM, 1), 0);
+
+        // Options are applied when the frontend says they've changed.
+        {
+            let mut frontend = frontend();
+            frontend.palette = c"DMG green";
+            frontend.options_changed = true;
+        }
+        retro_run();
+        let palette = CORE
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .game_boy
+            .lcd
+            .palette();
+        assert_eq!(palette, Palette::DMG_GREEN);
+
+        unsafe { retro_cheat_set(0, true, c"01FF00C0+01FF01C0".as_ptr()) };
+        assert_eq!(
+            CORE.lock()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .game_boy
+                .cheats()
+                .len(),
+            2
+        );
+        retro_cheat_reset();
+        assert!(CORE
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .game_boy
+            .cheats()
+            .is_empty());
+
+        retro_unload_game();
+        assert!(retro_get_memory_data(MEMORY_SYSTEM_RAM).is_null());
+        retro_deinit();
+    }
+}
@@ -1,11 +1,18 @@
 use num_enum::TryFromPrimitive;
+use std::cell::Cell;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
 
 // rwtodo: ensure LY is never written to by the game.
 
 use crate::address;
+use crate::bus::Bus;
+use crate::cheats::{self, Cheat, CheatEffect};
+use crate::debugger::{self, WatchKind, Watchpoint, WatchpointHit};
 use crate::interrupt;
 use crate::make_u16;
-use crate::Joypad;
+use crate::state;
+use crate::{Button, Joypad};
 
 const ROM_BANK_SIZE: usize = 16384; // 16kB // rwtodo rename to just BANK_SIZE?
 
@@ -43,7 +50,27 @@ enum CartKind {
     HuC1RamBattery = 0xff,
 }
 
-#[derive(PartialEq)]
+impl CartKind {
+    fn has_battery(&self) -> bool {
+        use CartKind::*;
+        matches!(
+            self,
+            Mbc1RamBattery
+                | Mbc2Battery
+                | RamBattery
+                | Mmm01RamBattery
+                | Mbc3TimerBattery
+                | Mbc3TimerRamBattery
+                | Mbc3RamBattery
+                | Mbc4RamBattery
+                | Mbc5RamBattery
+                | Mbc5RumbleRamBattery
+                | HuC1RamBattery
+        )
+    }
+}
+
+#[derive(Clone, PartialEq)]
 enum Mbc {
     None,
     Mbc1,
@@ -51,8 +78,6 @@ enum Mbc {
     Mbc3,
 }
 
-type CachedBank = [u8; ROM_BANK_SIZE];
-
 mod bank_ranges {
     use std::ops::RangeInclusive;
     pub const ROM_0: RangeInclusive<u16> = 0x0000..=0x3fff;
@@ -69,78 +94,81 @@ mod bank_ranges {
     pub const INTERRUPT_ENABLE: RangeInclusive<u16> = 0xffff..=0xffff;
 }
 
+#[derive(Clone)]
 struct Banker {
     mbc: Mbc,
+    has_battery: bool,
     has_ram: bool, // rwtodo do I really need this as well as ram_bank_count?
     ram_bank_count: u8,
     ram_is_enabled: bool,
     active_switchable_rom_bank: u8,
-    cached_banks: Vec<CachedBank>,
+    // Shared by every GameBoy running the same ROM, and only copied if the debugger pokes it.
+    rom: Arc<[u8]>,
 }
 
 impl Banker {
-    // rwtodo: maybe Memory API access to banks should redirect through the Banker, and Banker could hold the currently active bank data, then I wouldn't need this ugliness where the Banker is partially responsible for data living outside the Banker.
-    fn new(bank_slots_in_memory: &mut [u8; ROM_BANK_SIZE * 2], file_data: &[u8]) -> Banker {
+    fn new(rom: Arc<[u8]>) -> Banker {
         const CART_KIND_ADDRESS: usize = 0x0147;
 
-        let cart_kind =
-            CartKind::try_from(file_data[CART_KIND_ADDRESS]).expect("Couldn't get cart kind");
+        let cart_kind = CartKind::try_from(rom[CART_KIND_ADDRESS]).expect("Couldn't get cart kind");
+        let has_battery = cart_kind.has_battery();
         let mbc = Self::detect_mbc(cart_kind);
-        let cached_banks = Self::load_cache(file_data);
 
-        let banker = Banker {
+        Banker {
             mbc,
+            has_battery,
             has_ram: false,        // rwtodo
             ram_bank_count: 0,     // rwtodo
             ram_is_enabled: false, // rwtodo
             active_switchable_rom_bank: 1,
-            cached_banks,
-        };
-
-        // Init first 2 banks
-        let banks = &file_data[..(ROM_BANK_SIZE * 2)];
-        bank_slots_in_memory.copy_from_slice(banks);
-
-        banker
+            rom: Self::pad_rom(rom),
+        }
     }
 
-    fn load_cache(file_data: &[u8]) -> Vec<CachedBank> {
+    // Patched ROMs don't always match their header, e.g. when a translation expands the ROM without updating
+    // the bank count, so the ROM is padded to hold every bank in the file as well as every bank the header
+    // claims. The padding is 0xff, which is what reads of a missing ROM chip return. Well-formed ROMs are
+    // used as they are, without copying.
+    fn pad_rom(rom: Arc<[u8]>) -> Arc<[u8]> {
         const BANK_COUNT_ID_ADDRESS: usize = 0x0148;
-        let bank_count_id = file_data[BANK_COUNT_ID_ADDRESS];
+        let bank_count_id = rom[BANK_COUNT_ID_ADDRESS];
 
-        let mut cached_banks = vec![];
-        let total_bank_count: usize;
+        let header_bank_count: usize;
 
         if bank_count_id <= 0x08 {
-            total_bank_count = 2 << bank_count_id;
+            header_bank_count = 2 << bank_count_id;
         } else {
             match bank_count_id {
-                0x52 => total_bank_count = 72,
-                0x53 => total_bank_count = 80,
-                0x54 => total_bank_count = 96,
+                0x52 => header_bank_count = 72,
+                0x53 => header_bank_count = 80,
+                0x54 => header_bank_count = 96,
                 _ => panic!("Unrecognized bank count ID"),
             }
         }
 
-        if total_bank_count > 2 {
-            let cached_bank_count = total_bank_count - 2;
-
-            println!("Loading the remaining {} ROM banks...\n", cached_bank_count);
+        let total_bank_count = header_bank_count.max(rom.len().div_ceil(ROM_BANK_SIZE));
+        if rom.len() == total_bank_count * ROM_BANK_SIZE {
+            return rom;
+        }
 
-            let file_offset: usize = ROM_BANK_SIZE * 2; // Offset of 2, as the first 2 banks are already loaded
+        let mut padded = rom.to_vec();
+        padded.resize(total_bank_count * ROM_BANK_SIZE, 0xff);
+        padded.into()
+    }
 
-            for bank_index in 0..cached_bank_count {
-                let bank_start = file_offset + ROM_BANK_SIZE * bank_index;
-                let mut bank_data: CachedBank = [0; ROM_BANK_SIZE];
-                bank_data.copy_from_slice(&file_data[bank_start..bank_start + ROM_BANK_SIZE]);
-                cached_banks.push(bank_data);
-            }
+    fn rom_bank_count(&self) -> usize {
+        self.rom.len() / ROM_BANK_SIZE
+    }
 
-            assert!(cached_banks.len() == total_bank_count - 2);
-            println!("Done");
-        }
+    // The offset of the byte in the ROM. Banks past the end of the ROM wrap around, as they do on the MBCs.
+    fn rom_offset(&self, bank: u8, address: u16) -> usize {
+        let bank = usize::from(bank) % self.rom_bank_count();
+        bank * ROM_BANK_SIZE + usize::from(address) % ROM_BANK_SIZE
+    }
 
-        cached_banks
+    fn rom_bank(&self, bank: u8) -> &[u8] {
+        let start = self.rom_offset(bank, 0);
+        &self.rom[start..start + ROM_BANK_SIZE]
     }
 
     fn detect_mbc(cart: CartKind) -> Mbc {
@@ -156,6 +184,7 @@ impl Banker {
     }
 }
 
+#[derive(Clone)]
 pub struct Memory {
     bytes: [u8; Self::ADDRESS_SPACE_SIZE],
     joypad: Joypad, // rwtodo: move back to GameBoy struct.
@@ -163,13 +192,27 @@ pub struct Memory {
 
     // This is Some if record_serial_output(true) was called.
     pub serial_buffer: Option<Vec<u8>>,
+
+    // Watchpoints are only checked while `watching` is true, which GameBoy sets while the CPU is
+    // executing. This stops the LCD and timer from triggering them. The CPU's interrupt polling doesn't either.
+    pub watchpoints: Vec<Watchpoint>,
+    pub watching: bool,
+    watchpoint_hit: Cell<Option<WatchpointHit>>,
+
+    // If Some, reads of LY return this instead. For comparing against logs from emulators that do the same.
+    pub ly_override: Option<u8>,
+
+    // Game Genie codes are applied as ROM is read, and GameShark codes by apply_ram_cheats().
+    pub cheats: Vec<Cheat>,
 }
 
 impl Memory {
     const ADDRESS_SPACE_SIZE: usize = 1024 * 64;
+    // bytes covers the whole address space for simple indexing, but ROM is read from the banker, so the bytes
+    // before this are unused.
+    const RAM_START: usize = 0x8000;
 
-    // rwtodo: Supply rom file data here so we can initialise the banks at the same time as the rest of the memory.
-    pub fn new(file_data: &[u8]) -> Self {
+    pub fn new(rom: Arc<[u8]>) -> Self {
         let mut bytes: [u8; Self::ADDRESS_SPACE_SIZE] = [0; Self::ADDRESS_SPACE_SIZE];
 
         // Set all nonzero bytes.
@@ -196,17 +239,39 @@ impl Memory {
         bytes[0xff49] = 0xff;
         bytes[usize::from(address::INTERRUPT_FLAGS)] = 0xe1; // TODO: Might be acceptable for this to be 0xe0
 
-        let bank_slots = &mut bytes[..ROM_BANK_SIZE * 2];
-        let banker = Banker::new(bank_slots.try_into().unwrap(), file_data);
-
-        let new_mem = Self {
+        Self {
             bytes,
             joypad: Joypad::new(),
-            banker,
+            banker: Banker::new(rom),
             serial_buffer: None,
-        };
+            watchpoints: vec![],
+            watching: false,
+            watchpoint_hit: Cell::new(None),
+            ly_override: None,
+            cheats: vec![],
+        }
+    }
 
-        new_mem
+    // ROM isn't saved, as it never changes (the debugger poking it aside).
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.bytes[Self::RAM_START..]);
+        out.extend_from_slice(&[
+            self.joypad.action_buttons,
+            self.joypad.direction_buttons,
+            self.banker.active_switchable_rom_bank,
+            u8::from(self.banker.ram_is_enabled),
+        ]);
+    }
+
+    pub fn load_state(&mut self, input: &mut &[u8]) {
+        self.bytes[Self::RAM_START..]
+            .copy_from_slice(&state::take::<{ Self::ADDRESS_SPACE_SIZE - Self::RAM_START }>(input));
+        let [action_buttons, direction_buttons, active_switchable_rom_bank, ram_is_enabled] =
+            state::take(input);
+        self.joypad.action_buttons = action_buttons;
+        self.joypad.direction_buttons = direction_buttons;
+        self.banker.active_switchable_rom_bank = active_switchable_rom_bank;
+        self.banker.ram_is_enabled = ram_is_enabled != 0;
     }
 
     pub fn record_serial_output(&mut self, record: bool) {
@@ -217,11 +282,141 @@ impl Memory {
         }
     }
 
+    // Whether the cart keeps external RAM powered, so that it should be saved between sessions.
+    pub fn has_battery(&self) -> bool {
+        self.banker.has_battery
+    }
+
+    // Memory from address to 0xffff, for frontends that read and write RAM in place. Writes made this way skip
+    // everything poke() does, including keeping echo RAM in sync. Panics if address is in ROM.
+    pub fn ram_from(&mut self, address: u16) -> &mut [u8] {
+        assert!(
+            usize::from(address) >= Self::RAM_START,
+            "Not RAM: {:#06x}",
+            address
+        );
+        &mut self.bytes[usize::from(address)..]
+    }
+
     pub fn direct_access(&mut self, address: u16) -> &mut u8 {
         &mut self.bytes[address as usize]
     }
 
-    fn get_joypad_register_write_result(&mut self, mut register_value: u8) -> u8 {
+    // Read a byte without panicking or triggering watchpoints, for the debugger.
+    pub fn peek(&self, address: u16) -> u8 {
+        match self.rom_bank_at(address) {
+            Some(bank) => self.banker.rom_bank(bank)[usize::from(address) % ROM_BANK_SIZE],
+            None => self.bytes[address as usize],
+        }
+    }
+
+    // Write a byte without any of the side effects of write(), so the debugger can patch anything. Poking ROM
+    // gives this GameBoy its own copy of it.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        if let Some(bank) = self.rom_bank_at(address) {
+            let offset = self.banker.rom_offset(bank, address);
+            Arc::make_mut(&mut self.banker.rom)[offset] = value;
+            return;
+        }
+
+        self.bytes[address as usize] = value;
+
+        // Keep work RAM and echo RAM in sync.
+        const ECHO_OFFSET: u16 = 0x2000;
+        if (0xc000..=0xddff).contains(&address) {
+            self.bytes[(address + ECHO_OFFSET) as usize] = value;
+        } else if bank_ranges::ECHO_RAM.contains(&address) {
+            self.bytes[(address - ECHO_OFFSET) as usize] = value;
+        }
+    }
+
+    // The ROM bank currently mapped at address, or None if address isn't in ROM.
+    pub fn rom_bank_at(&self, address: u16) -> Option<u8> {
+        match address {
+            x if bank_ranges::ROM_0.contains(&x) => Some(0),
+            x if bank_ranges::ROM_1.contains(&x) => Some(self.banker.active_switchable_rom_bank),
+            _ => None,
+        }
+    }
+
+    // The external RAM bank currently mapped at address, or None if address isn't in external RAM.
+    fn ram_bank_at(&self, address: u16) -> Option<u8> {
+        // rwtodo: RAM banking isn't emulated yet, so bank 0 is always mapped.
+        bank_ranges::EXTERNAL_RAM.contains(&address).then_some(0)
+    }
+
+    // The RAM regions a RAM search looks through, with the bank mapped in each.
+    pub fn ram_regions(&self) -> [(RangeInclusive<u16>, u8); 4] {
+        [
+            (
+                bank_ranges::EXTERNAL_RAM,
+                self.ram_bank_at(*bank_ranges::EXTERNAL_RAM.start())
+                    .unwrap(),
+            ),
+            (bank_ranges::WORK_RAM_STATIC, 0),
+            (bank_ranges::WORK_RAM_SWITCHABLE, 1), // rwtodo: this bank is switchable on the Game Boy Color.
+            (bank_ranges::HIGH_RAM, 0),
+        ]
+    }
+
+    // Writes the values of enabled GameShark codes. Called once per frame, on entering V-blank.
+    pub fn apply_ram_cheats(&mut self) {
+        for index in 0..self.cheats.len() {
+            let cheat = &self.cheats[index];
+            if let (
+                true,
+                CheatEffect::RamWrite {
+                    bank,
+                    address,
+                    value,
+                },
+            ) = (cheat.enabled, cheat.effect)
+            {
+                if bank.is_none_or(|bank| self.ram_bank_at(address) == Some(bank)) {
+                    self.poke(address, value);
+                }
+            }
+        }
+    }
+
+    // Returns the first watchpoint triggered since the last call.
+    pub fn take_watchpoint_hit(&mut self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+
+    fn check_watchpoints(&self, address: u16, access: WatchKind, value: u8) {
+        if self.watchpoint_hit.get().is_none() {
+            let hit = debugger::find_watchpoint(&self.watchpoints, address, access, value);
+            self.watchpoint_hit.set(hit);
+        }
+    }
+
+    pub fn set_button(&mut self, button: &Button, is_down: bool) {
+        let (buttons, mask) = match button {
+            Button::A => (&mut self.joypad.action_buttons, 0x01),
+            Button::B => (&mut self.joypad.action_buttons, 0x02),
+            Button::SELECT => (&mut self.joypad.action_buttons, 0x04),
+            Button::START => (&mut self.joypad.action_buttons, 0x08),
+            Button::RIGHT => (&mut self.joypad.direction_buttons, 0x01),
+            Button::LEFT => (&mut self.joypad.direction_buttons, 0x02),
+            Button::UP => (&mut self.joypad.direction_buttons, 0x04),
+            Button::DOWN => (&mut self.joypad.direction_buttons, 0x08),
+        };
+
+        if is_down {
+            *buttons &= !mask;
+        } else {
+            *buttons |= mask;
+        }
+
+        // Refresh the register so the game sees the change without having to select the buttons again.
+        let register_value = self.bytes[0xff00];
+        self.update_joypad_register(register_value);
+    }
+
+    // The joypad register's value after the game writes register_value to it, with the selected buttons' states
+    // filled in.
+    fn joypad_register_value(&self, mut register_value: u8) -> u8 {
         const ACTION_BUTTON_REQUEST: u8 = 0x20;
         const DIRECTION_BUTTON_REQUEST: u8 = 0x10;
 
@@ -230,24 +425,31 @@ impl Memory {
 
         if (register_value & ACTION_BUTTON_REQUEST) == 0x00 {
             register_value &= self.joypad.action_buttons;
-            interrupt::make_request(interrupt::FLAG_JOYPAD, self);
         }
 
         if (register_value & DIRECTION_BUTTON_REQUEST) == 0x00 {
             register_value &= self.joypad.direction_buttons;
-            interrupt::make_request(interrupt::FLAG_JOYPAD, self);
         }
 
-        return register_value;
+        register_value
+    }
+
+    // The joypad interrupt is requested when any of the button lines goes from 1 (unpressed) to 0.
+    fn update_joypad_register(&mut self, register_value: u8) {
+        let previous = self.bytes[0xff00];
+        let value = self.joypad_register_value(register_value);
+        self.bytes[0xff00] = value;
+
+        if previous & !value & 0x0f != 0 {
+            interrupt::make_request(interrupt::FLAG_JOYPAD, self);
+        }
     }
 
     pub fn write(&mut self, address: u16, value: u8) {
         match address {
             x if bank_ranges::ROM_0.contains(&x) || bank_ranges::ROM_1.contains(&x) => { // perform_cart_control(address, value); rwtodo
             }
-            0xff00 => {
-                self.bytes[address as usize] = self.get_joypad_register_write_result(value);
-            }
+            0xff00 => self.update_joypad_register(value),
             address::SERIAL_CONTROL => {
                 self.bytes[address::SERIAL_CONTROL as usize] = value;
 
@@ -264,23 +466,14 @@ impl Memory {
                 self.bytes[address as usize] = 0x00; // Reset timer DIV register. rwtodo: move this responsibility into Timer struct
             }
             0xff46 => {
-                // Perform OAM DMA transfer. rwtodo: copying twice here, unless the compiler optimizes it out. Use copy_within on self.memory directly.
-                const SIZE_OF_TRANSFER: usize = 160;
-
-                let mut bytes_to_transfer: [u8; SIZE_OF_TRANSFER] = [0; SIZE_OF_TRANSFER];
+                // Perform OAM DMA transfer. The source can be anywhere, including ROM.
+                const SIZE_OF_TRANSFER: u16 = 160;
 
-                {
-                    let src_range_start: usize = (value as usize) * 0x100;
-                    let src_range_end: usize = src_range_start + SIZE_OF_TRANSFER;
-                    let src_slice = &self.bytes[src_range_start..src_range_end];
-                    bytes_to_transfer.copy_from_slice(src_slice);
+                let source_start = u16::from(value) * 0x100;
+                for offset in 0..SIZE_OF_TRANSFER {
+                    let byte = self.peek(source_start + offset);
+                    self.bytes[usize::from(0xfe00 + offset)] = byte;
                 }
-
-                let dst_range_start: usize = 0xfe00;
-                let dst_range_end: usize = dst_range_start + SIZE_OF_TRANSFER;
-                let dst_slice = &mut self.bytes[dst_range_start..dst_range_end];
-
-                dst_slice.copy_from_slice(&bytes_to_transfer);
             }
             x if bank_ranges::WORK_RAM_STATIC.contains(&x) => {
                 self.bytes[address as usize] = value;
@@ -299,6 +492,10 @@ impl Memory {
             _ => self.bytes[address as usize] = value,
         }
 
+        if self.watching {
+            self.check_watchpoints(address, WatchKind::Write, value);
+        }
+
         // rwtodo: implement cart_state stuff so we can do this.
         // rwtodo: Also handle the below for MBC3.
         // if cart_state.mbc_type == MBC_1 && address >= 0xa000 && address < 0xc000 {
@@ -316,15 +513,53 @@ impl Memory {
     pub fn read(&self, address: u16) -> u8 {
         // rwtodo rom bank slot at address >= 0x4000 && address < 0x8000
         // rwtodo match statement?
-        match &address {
+        let value = match &address {
             x if bank_ranges::PROHIBITED.contains(x) => {
                 panic!("Attempted to read from a prohibited region")
             }
+            x if bank_ranges::ROM_0.contains(x) || bank_ranges::ROM_1.contains(x) => {
+                cheats::patch_rom_read(&self.cheats, address, self.peek(address))
+            }
             _ => self.bytes[address as usize],
+        };
+
+        if self.watching {
+            self.check_watchpoints(address, WatchKind::Read, value);
         }
+
+        value
     }
 
     pub fn read_u16(&self, address: u16) -> u16 {
         make_u16(self.read(address), self.read(address + 1))
     }
 }
+
+// The LCD and timer are updated by GameBoy rather than on tick, as they live outside Memory.
+impl Bus for Memory {
+    // The LY override is only seen by the CPU. The LCD keeps using the real LY.
+    fn read(&self, address: u16) -> u8 {
+        let value = Memory::read(self, address);
+        match self.ly_override {
+            Some(ly) if address == address::LCD_LY => ly,
+            _ => value,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        Memory::write(self, address, value)
+    }
+
+    // Interrupt polling bypasses watchpoints, as the program didn't access the registers.
+    fn interrupt_flags(&self) -> u8 {
+        self.bytes[usize::from(address::INTERRUPT_FLAGS)]
+    }
+
+    fn set_interrupt_flags(&mut self, value: u8) {
+        self.bytes[usize::from(address::INTERRUPT_FLAGS)] = value;
+    }
+
+    fn interrupt_enable(&self) -> u8 {
+        self.bytes[usize::from(address::INTERRUPT_ENABLE)]
+    }
+}
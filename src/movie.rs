@@ -0,0 +1,407 @@
+// Input movies: every button change of a run, stamped with the cycle it took effect on, so the run can be
+// played back exactly. A hash of the whole emulator state after each frame detects playback that has drifted.
+//
+// Movie files are text. '#' starts a comment, and the lines are:
+//
+//     start power-on                   # Or "start state <hex>", a snapshot to start from.
+//     input <frame> <cycle> <button> <down|up>
+//     hash <frame> <hex>
+//
+// The cycle is counted from the start of the movie, and the frame is only there for readability. Buttons are
+// named a, b, start, select, up, down, left and right. Hashes are 64-bit FNV-1a over the emulated state after
+// each frame, which leaves out the completed frame, as its bytes depend on the pixel format. Snapshots and
+// hashes are only guaranteed to match on the build that made them.
+
+use crate::{state, Button};
+
+/// One call to GameBoy::set_button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovieInput {
+    pub frame: u64,
+    pub cycle: u64,
+    pub button: Button,
+    pub is_down: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Movie {
+    /// The state to start from, or None to start from power-on.
+    pub start_state: Option<Vec<u8>>,
+    pub inputs: Vec<MovieInput>,
+    /// The state hash after each frame.
+    pub frame_hashes: Vec<u64>,
+}
+
+/// How far playback has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlaybackStatus {
+    /// Frames played so far.
+    pub frame: u64,
+    /// True once every recorded frame has been played.
+    pub finished: bool,
+    /// The first frame whose state didn't match the recording, if any.
+    pub desync_frame: Option<u64>,
+}
+
+const BUTTON_NAMES: [(Button, &str); 8] = [
+    (Button::A, "a"),
+    (Button::B, "b"),
+    (Button::START, "start"),
+    (Button::SELECT, "select"),
+    (Button::UP, "up"),
+    (Button::DOWN, "down"),
+    (Button::LEFT, "left"),
+    (Button::RIGHT, "right"),
+];
+
+fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+    // This also keeps the slicing below on character boundaries, and rejects the signs that from_str_radix
+    // accepts.
+    if !text.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err("Expected only hex digits".to_owned());
+    }
+    if !text.len().is_multiple_of(2) {
+        return Err("Odd number of hex digits".to_owned());
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+impl Movie {
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie::default();
+        let mut has_start = false;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let error = |message: String| format!("Line {}: {}", line_index + 1, message);
+
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |word: &str| {
+                word.parse::<u64>()
+                    .map_err(|e| error(format!("'{}': {}", word, e)))
+            };
+
+            match words.as_slice() {
+                ["start", "power-on"] => {
+                    movie.start_state = None;
+                    has_start = true;
+                }
+                ["start", "state", hex] => {
+                    movie.start_state = Some(parse_hex_bytes(hex).map_err(error)?);
+                    has_start = true;
+                }
+                ["input", frame, cycle, button, state] => {
+                    let button = BUTTON_NAMES
+                        .iter()
+                        .find(|(_, name)| name == button)
+                        .map(|(button, _)| *button)
+                        .ok_or(error(format!("Unknown button '{}'", button)))?;
+                    let is_down = match *state {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(error(format!("Expected down or up, got '{}'", state))),
+                    };
+
+                    movie.inputs.push(MovieInput {
+                        frame: number(frame)?,
+                        cycle: number(cycle)?,
+                        button,
+                        is_down,
+                    });
+                }
+                ["hash", frame, hash] => {
+                    if number(frame)? != movie.frame_hashes.len() as u64 {
+                        return Err(error(format!(
+                            "Expected the hash for frame {}",
+                            movie.frame_hashes.len()
+                        )));
+                    }
+                    let hash = u64::from_str_radix(hash, 16)
+                        .map_err(|e| error(format!("'{}': {}", hash, e)))?;
+                    movie.frame_hashes.push(hash);
+                }
+                _ => return Err(error(format!("Unrecognised line '{}'", line))),
+            }
+        }
+
+        if !has_start {
+            return Err("No start line".to_owned());
+        }
+        if movie
+            .inputs
+            .windows(2)
+            .any(|pair| pair[0].cycle > pair[1].cycle)
+        {
+            return Err("Inputs are out of order".to_owned());
+        }
+
+        Ok(movie)
+    }
+
+    /// The inverse of parse. Inputs and hashes are interleaved in frame order, for readability.
+    pub fn write(&self) -> String {
+        let mut text = String::from("# robin_gb movie\n");
+
+        match &self.start_state {
+            None => text += "start power-on\n",
+            Some(state) => {
+                text += "start state ";
+                for byte in state {
+                    text += &format!("{:02x}", byte);
+                }
+                text += "\n";
+            }
+        }
+
+        let mut inputs = self.inputs.iter().peekable();
+        for (frame, hash) in self.frame_hashes.iter().enumerate() {
+            let frame = frame as u64;
+            while let Some(input) = inputs.next_if(|input| input.frame <= frame) {
+                text += &input_line(input);
+            }
+            text += &format!("hash {} {:016x}\n", frame, hash);
+        }
+        for input in inputs {
+            text += &input_line(input);
+        }
+
+        text
+    }
+}
+
+fn input_line(input: &MovieInput) -> String {
+    let name = BUTTON_NAMES
+        .iter()
+        .find(|(button, _)| *button == input.button)
+        .map(|(_, name)| name)
+        .unwrap();
+    let state = if input.is_down { "down" } else { "up" };
+    format!("input {} {} {} {}\n", input.frame, input.cycle, name, state)
+}
+
+pub(crate) struct MovieRecorder {
+    pub movie: Movie,
+    start_cycle: u64,
+}
+
+impl MovieRecorder {
+    pub fn new(start_state: Option<Vec<u8>>, start_cycle: u64) -> Self {
+        Self {
+            movie: Movie {
+                start_state,
+                ..Default::default()
+            },
+            start_cycle,
+        }
+    }
+
+    pub fn record_input(&mut self, cycle: u64, button: Button, is_down: bool) {
+        self.movie.inputs.push(MovieInput {
+            frame: self.movie.frame_hashes.len() as u64,
+            cycle: cycle - self.start_cycle,
+            button,
+            is_down,
+        });
+    }
+
+    pub fn frame_completed(&mut self, emulated_state: &[u8]) {
+        self.movie.frame_hashes.push(state::hash(emulated_state));
+    }
+}
+
+pub(crate) struct MoviePlayer {
+    movie: Movie,
+    start_cycle: u64,
+    next_input: usize,
+    status: PlaybackStatus,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie, start_cycle: u64) -> Self {
+        let finished = movie.frame_hashes.is_empty();
+
+        Self {
+            movie,
+            start_cycle,
+            next_input: 0,
+            status: PlaybackStatus {
+                frame: 0,
+                finished,
+                desync_frame: None,
+            },
+        }
+    }
+
+    // The inputs that take effect by this cycle and haven't been applied yet.
+    pub fn take_due_inputs(&mut self, cycle: u64) -> &[MovieInput] {
+        let start = self.next_input;
+        let movie_cycle = cycle - self.start_cycle;
+
+        while self
+            .movie
+            .inputs
+            .get(self.next_input)
+            .is_some_and(|input| input.cycle <= movie_cycle)
+        {
+            self.next_input += 1;
+        }
+
+        &self.movie.inputs[start..self.next_input]
+    }
+
+    pub fn frame_completed(&mut self, emulated_state: &[u8]) {
+        let frame = self.status.frame;
+        if let Some(expected_hash) = self.movie.frame_hashes.get(frame as usize) {
+            if self.status.desync_frame.is_none() && state::hash(emulated_state) != *expected_hash {
+                self.status.desync_frame = Some(frame);
+            }
+        }
+
+        self.status.frame += 1;
+        self.status.finished = self.status.frame >= self.movie.frame_hashes.len() as u64;
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        self.status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameBoy, PixelFormat};
+
+    // Reads the joypad into the counter at 0xc000 in a loop, so button presses change the state.
+    // LD A,0x10; LDH (0x00),A; loop: LDH A,(0x00); LD (0xc000),A; JR loop
+    const PROGRAM: [u8; 11] = [
+        0x3e, 0x10, 0xe0, 0x00, 0xf0, 0x00, 0xea, 0x00, 0xc0, 0x18, 0xf9,
+    ];
+
+    fn record() -> (Movie, Vec<u8>) {
+        let mut game_boy = GameBoy::new(&crate::test_rom(&PROGRAM));
+        game_boy.start_recording_movie();
+
+        for frame in 0..10 {
+            game_boy.set_button(&Button::A, frame % 3 == 0);
+            game_boy.emulate_next_frame();
+            if frame == 5 {
+                // Part way through a frame.
+                game_boy.step_scanline();
+                game_boy.set_button(&Button::B, true);
+            }
+        }
+
+        let movie = game_boy.stop_recording_movie().unwrap();
+        (movie, game_boy.save_state())
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        let (movie, final_state) = record();
+        assert_eq!(movie.frame_hashes.len(), 10);
+        assert_eq!(movie.inputs.len(), 11);
+
+        // The movie survives being written and parsed.
+        let movie = Movie::parse(&movie.write()).unwrap();
+
+        let mut game_boy = GameBoy::new(&crate::test_rom(&PROGRAM));
+        game_boy.play_movie(movie).unwrap();
+        for _ in 0..10 {
+            game_boy.emulate_next_frame();
+        }
+
+        let status = game_boy.movie_playback_status().unwrap();
+        assert_eq!(status.desync_frame, None);
+        assert!(status.finished);
+        assert_eq!(game_boy.save_state(), final_state);
+    }
+
+    #[test]
+    fn test_play_in_other_pixel_formats() {
+        let (movie, _) = record();
+        let mut game_boy = GameBoy::new(&crate::test_rom(&PROGRAM));
+        game_boy.set_pixel_format(PixelFormat::Rgba8888);
+        game_boy.play_movie(movie).unwrap();
+        for _ in 0..10 {
+            game_boy.emulate_next_frame();
+        }
+        assert_eq!(game_boy.movie_playback_status().unwrap().desync_frame, None);
+
+        // A movie that starts from a snapshot, whose frame is in the recording's pixel format.
+        let mut game_boy = GameBoy::new(&crate::test_rom(&PROGRAM));
+        game_boy.emulate_next_frame();
+        game_boy.start_recording_movie();
+        for _ in 0..3 {
+            game_boy.emulate_next_frame();
+        }
+        let movie = game_boy.stop_recording_movie().unwrap();
+        assert!(movie.start_state.is_some());
+
+        let mut game_boy = GameBoy::new(&crate::test_rom(&PROGRAM));
+        game_boy.set_pixel_format(PixelFormat::Rgb565);
+        game_boy.play_movie(movie).unwrap();
+        for _ in 0..3 {
+            game_boy.emulate_next_frame();
+        }
+        let status = game_boy.movie_playback_status().unwrap();
+        assert_eq!(status.desync_frame, None);
+        assert!(status.finished);
+    }
+
+    #[test]
+    fn test_bad_start_state() {
+        let (mut movie, _) = record();
+        let mut game_boy = GameBoy::new(&crate::test_rom(&PROGRAM));
+        let mut state = game_boy.save_state();
+
+        state.push(0);
+        movie.start_state = Some(state.clone());
+        assert!(game_boy.play_movie(movie.clone()).is_err());
+
+        state.truncate(10);
+        movie.start_state = Some(state);
+        assert!(game_boy.play_movie(movie).is_err());
+        assert!(game_boy.movie_playback_status().is_none());
+    }
+
+    #[test]
+    fn test_desync() {
+        let (movie, _) = record();
+
+        // Pressing an extra button changes the state from that frame on.
+        let mut game_boy = GameBoy::new(&crate::test_rom(&PROGRAM));
+        game_boy.play_movie(movie).unwrap();
+        for frame in 0..10 {
+            if frame == 7 {
+                game_boy.set_button(&Button::DOWN, true);
+            }
+            game_boy.emulate_next_frame();
+        }
+
+        assert_eq!(
+            game_boy.movie_playback_status().unwrap().desync_frame,
+            Some(7)
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Movie::parse("start power-on\ninput 0 0 z down\n").is_err());
+        assert!(Movie::parse("start power-on\ninput 0 5 a down\ninput 0 4 a up\n").is_err());
+        assert!(Movie::parse("input 0 0 a down\n").is_err());
+        assert!(Movie::parse("start state 0éa\n").is_err());
+        assert!(Movie::parse("start state +a\n").is_err());
+        assert_eq!(
+            Movie::parse("start state 0a0b\n").unwrap().start_state,
+            Some(vec![0x0a, 0x0b])
+        );
+    }
+}
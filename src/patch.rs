@@ -0,0 +1,528 @@
+// ROM patches, for applying fan translations and ROM hacks to ROM bytes before they're given to GameBoy::new.
+// The format is detected from the patch's header:
+//
+// - IPS: a list of (offset, bytes) records, some run-length encoded. There's no checksum, so an IPS patch
+//   applies to any ROM, whether it was made for it or not.
+// - UPS: the XOR of the source and target ROMs, with the runs of zeros skipped.
+// - BPS: instructions to build the target out of ranges of the source, the target so far, and the patch.
+//
+// UPS and BPS patches end with CRC32s of the source ROM, the target ROM and the patch, which are all checked.
+// No patch can make a ROM bigger than the largest cartridge, so a corrupt size can't use up memory.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchFormat {
+    pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+        if patch.starts_with(b"PATCH") {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(b"UPS1") {
+            Some(PatchFormat::Ups)
+        } else if patch.starts_with(b"BPS1") {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+/// The CRC32 used by zip, PNG, UPS and BPS.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+    for byte in data {
+        crc = CRC32_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// The largest Game Boy cartridges are 8 MiB.
+pub const MAX_ROM_SIZE: usize = 8 * 1024 * 1024;
+
+/// Returns the patched ROM, which can be a different size to the original.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(rom, patch),
+        Some(PatchFormat::Ups) => apply_ups(rom, patch),
+        Some(PatchFormat::Bps) => apply_bps(rom, patch),
+        None => Err("Unrecognised patch format".to_owned()),
+    }
+}
+
+/// Sets the header checksum at 0x014d to match the header, as patches that change the title often don't.
+/// The boot ROM refuses to start a cartridge whose header checksum is wrong.
+pub fn fix_header_checksum(rom: &mut [u8]) {
+    const CHECKSUM_ADDRESS: usize = 0x014d;
+
+    if rom.len() > CHECKSUM_ADDRESS {
+        rom[CHECKSUM_ADDRESS] = rom[0x0134..CHECKSUM_ADDRESS]
+            .iter()
+            .fold(0u8, |checksum, byte| {
+                checksum.wrapping_sub(*byte).wrapping_sub(1)
+            });
+    }
+}
+
+// Reads through a patch, failing rather than panicking when it's cut short.
+struct Reader<'a> {
+    patch: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(patch: &'a [u8], position: usize) -> Self {
+        Self { patch, position }
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .patch
+            .get(self.position..self.position + count)
+            .ok_or("The patch ends unexpectedly".to_owned())?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<usize, String> {
+        let bytes = self.bytes(2)?;
+        Ok(usize::from(bytes[0]) << 8 | usize::from(bytes[1]))
+    }
+
+    fn u24_be(&mut self) -> Result<usize, String> {
+        let bytes = self.bytes(3)?;
+        Ok(usize::from(bytes[0]) << 16 | usize::from(bytes[1]) << 8 | usize::from(bytes[2]))
+    }
+
+    // UPS and BPS numbers: 7 bits per byte, least significant first, with the top bit marking the last byte.
+    // Each continuation also adds 1, so that every number has exactly one encoding.
+    fn number(&mut self) -> Result<usize, String> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            value = usize::from(byte & 0x7f)
+                .checked_mul(shift)
+                .and_then(|part| part.checked_add(value))
+                .ok_or("A number in the patch is too large".to_owned())?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift
+                .checked_shl(7)
+                .filter(|shift| *shift != 0)
+                .ok_or("A number in the patch is too large".to_owned())?;
+            value += shift;
+        }
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    const EOF: usize = 0x454f46; // "EOF"
+
+    let mut output = rom.to_vec();
+    let mut reader = Reader::new(patch, 5);
+
+    loop {
+        let offset = reader.u24_be()?;
+        if offset == EOF {
+            break;
+        }
+
+        let size = reader.u16_be()?;
+        let (bytes, size) = if size == 0 {
+            let count = reader.u16_be()?;
+            (vec![reader.byte()?; count], count)
+        } else {
+            (reader.bytes(size)?.to_vec(), size)
+        };
+
+        if offset + size > MAX_ROM_SIZE {
+            return Err(too_large(offset + size));
+        }
+        if output.len() < offset + size {
+            output.resize(offset + size, 0);
+        }
+        output[offset..offset + size].copy_from_slice(&bytes);
+    }
+
+    // An optional extension truncates the output.
+    if let Ok(length) = reader.u24_be() {
+        output.truncate(length);
+    }
+
+    Ok(output)
+}
+
+fn too_large(size: usize) -> String {
+    format!(
+        "The patched ROM would be {} bytes, but ROMs can't be larger than {}",
+        size, MAX_ROM_SIZE
+    )
+}
+
+fn check_target_size(target_size: usize) -> Result<(), String> {
+    if target_size > MAX_ROM_SIZE {
+        return Err(too_large(target_size));
+    }
+    Ok(())
+}
+
+fn past_the_end() -> String {
+    "The patch writes past the end of the patched ROM".to_owned()
+}
+
+// Checks the patch's own CRC32, and returns the source and target CRC32s.
+fn read_footer(patch: &[u8]) -> Result<(u32, u32), String> {
+    const FOOTER_SIZE: usize = 12;
+
+    if patch.len() < 4 + FOOTER_SIZE {
+        return Err("The patch is too short".to_owned());
+    }
+
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let crc =
+        |index: usize| u32::from_le_bytes(footer[index * 4..index * 4 + 4].try_into().unwrap());
+
+    if crc32(&patch[..patch.len() - 4]) != crc(2) {
+        return Err("The patch is corrupt: its CRC32 doesn't match".to_owned());
+    }
+    Ok((crc(0), crc(1)))
+}
+
+fn check_source(rom: &[u8], expected_crc: u32, expected_size: usize) -> Result<(), String> {
+    if rom.len() != expected_size || crc32(rom) != expected_crc {
+        return Err(format!(
+            "The patch is for a different ROM: expected CRC32 {:08x}, got {:08x}",
+            expected_crc,
+            crc32(rom)
+        ));
+    }
+    Ok(())
+}
+
+fn check_target(output: &[u8], expected_crc: u32) -> Result<(), String> {
+    if crc32(output) != expected_crc {
+        return Err(format!(
+            "The patched ROM is wrong: expected CRC32 {:08x}, got {:08x}",
+            expected_crc,
+            crc32(output)
+        ));
+    }
+    Ok(())
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let (source_crc, target_crc) = read_footer(patch)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader::new(&patch[..end], 4);
+
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    check_target_size(target_size)?;
+    check_source(rom, source_crc, source_size)?;
+
+    let mut output = rom.to_vec();
+    output.resize(target_size, 0);
+
+    let mut position: usize = 0;
+    while reader.position < end {
+        position = position
+            .checked_add(reader.number()?)
+            .ok_or_else(past_the_end)?;
+        loop {
+            let xor = reader.byte()?;
+            // Only the terminator can be past the end, when the last byte changes.
+            match output.get_mut(position) {
+                Some(byte) => *byte ^= xor,
+                None if xor == 0 => (),
+                None => return Err(past_the_end()),
+            }
+            position += 1;
+            if xor == 0 {
+                break;
+            }
+        }
+    }
+
+    check_target(&output, target_crc)?;
+    Ok(output)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    const SOURCE_READ: usize = 0;
+    const TARGET_READ: usize = 1;
+    const SOURCE_COPY: usize = 2;
+
+    let (source_crc, target_crc) = read_footer(patch)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader::new(&patch[..end], 4);
+
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    check_target_size(target_size)?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    check_source(rom, source_crc, source_size)?;
+
+    let out_of_range = || "The patch reads outside the ROM".to_owned();
+    let mut output = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    // Copy offsets are relative to the end of the previous copy, with the sign in the lowest bit.
+    let relative_offset = |reader: &mut Reader, offset: usize| -> Result<usize, String> {
+        let delta = reader.number()?;
+        if delta & 1 != 0 {
+            offset.checked_sub(delta >> 1)
+        } else {
+            offset.checked_add(delta >> 1)
+        }
+        .ok_or_else(out_of_range)
+    };
+
+    while reader.position < end {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        if length > target_size - output.len() {
+            return Err(past_the_end());
+        }
+
+        match action & 3 {
+            SOURCE_READ => {
+                let start = output.len();
+                let bytes = rom.get(start..start + length).ok_or_else(out_of_range)?;
+                output.extend_from_slice(bytes);
+            }
+            TARGET_READ => output.extend_from_slice(reader.bytes(length)?),
+            SOURCE_COPY => {
+                source_offset = relative_offset(&mut reader, source_offset)?;
+                let bytes = source_offset
+                    .checked_add(length)
+                    .and_then(|source_end| rom.get(source_offset..source_end))
+                    .ok_or_else(out_of_range)?;
+                output.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            _ => {
+                // Target copies can overlap the bytes they produce, to repeat a pattern, so go a byte at a time.
+                target_offset = relative_offset(&mut reader, target_offset)?;
+                for _ in 0..length {
+                    let byte = *output.get(target_offset).ok_or_else(out_of_range)?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if output.len() != target_size {
+        return Err(format!(
+            "The patched ROM is {} bytes, but should be {}",
+            output.len(),
+            target_size
+        ));
+    }
+    check_target(&output, target_crc)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_number(out: &mut Vec<u8>, mut value: usize) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn add_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_numbers() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1_000_000] {
+            let mut encoded = vec![];
+            write_number(&mut encoded, value);
+            assert_eq!(Reader::new(&encoded, 0).number(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_ips() {
+        let rom = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xaa, 0xbb]);
+        // Run-length encoded, and past the end of the ROM.
+        patch.extend_from_slice(&[0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0xcc]);
+        patch.extend_from_slice(b"EOF");
+
+        assert_eq!(
+            apply(&rom, &patch).unwrap(),
+            [0, 0, 0xaa, 0xbb, 0, 0, 0, 0xcc, 0xcc, 0xcc]
+        );
+
+        // Truncation.
+        patch.extend_from_slice(&[0x00, 0x00, 0x04]);
+        assert_eq!(apply(&rom, &patch).unwrap(), [0, 0, 0xaa, 0xbb]);
+
+        assert!(apply(&rom, b"PATCH\x00\x00").is_err());
+
+        // A record past 8 MiB.
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x80, 0x00, 0x00, 0x00, 0x01, 0xaa]);
+        patch.extend_from_slice(b"EOF");
+        assert!(apply(&rom, &patch).unwrap_err().contains("larger"));
+    }
+
+    #[test]
+    fn test_ups() {
+        let source = b"Hello, world".to_vec();
+        let target = b"Hello, World!!".to_vec();
+
+        let mut patch = b"UPS1".to_vec();
+        write_number(&mut patch, source.len());
+        write_number(&mut patch, target.len());
+        // Skip 7 bytes, then 'w' ^ 'W' and a terminator.
+        write_number(&mut patch, 7);
+        patch.extend_from_slice(&[b'w' ^ b'W', 0x00]);
+        // Skip "rld", then two '!'s past the end of the source.
+        write_number(&mut patch, 3);
+        patch.extend_from_slice(b"!!\x00");
+        let patch = add_footer(patch, &source, &target);
+
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+        assert!(apply(b"Goodbye", &patch)
+            .unwrap_err()
+            .contains("different ROM"));
+
+        let mut corrupt = patch.clone();
+        corrupt[8] ^= 1;
+        assert!(apply(&source, &corrupt).unwrap_err().contains("corrupt"));
+    }
+
+    #[test]
+    fn test_ups_sizes() {
+        let source = b"Hello".to_vec();
+
+        let mut patch = b"UPS1".to_vec();
+        write_number(&mut patch, source.len());
+        write_number(&mut patch, MAX_ROM_SIZE + 1);
+        let patch = add_footer(patch, &source, &source);
+        assert!(apply(&source, &patch).unwrap_err().contains("larger"));
+
+        // Changing a byte past the target size.
+        let mut patch = b"UPS1".to_vec();
+        write_number(&mut patch, source.len());
+        write_number(&mut patch, source.len());
+        write_number(&mut patch, 5);
+        patch.extend_from_slice(b"!\x00");
+        let patch = add_footer(patch, &source, &source);
+        assert!(apply(&source, &patch).unwrap_err().contains("past the end"));
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = b"abcdefgh".to_vec();
+        let target = b"abcXYXYXYdefgh".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        write_number(&mut patch, source.len());
+        write_number(&mut patch, target.len());
+        write_number(&mut patch, 0);
+        // Source read "abc".
+        write_number(&mut patch, (3 - 1) << 2);
+        // Target read "XY".
+        write_number(&mut patch, ((2 - 1) << 2) | 1);
+        patch.extend_from_slice(b"XY");
+        // Target copy "XYXY" from offset 3, overlapping itself.
+        write_number(&mut patch, ((4 - 1) << 2) | 3);
+        write_number(&mut patch, 3 << 1);
+        // Source copy "defgh" from offset 3.
+        write_number(&mut patch, ((5 - 1) << 2) | 2);
+        write_number(&mut patch, 3 << 1);
+        let patch = add_footer(patch, &source, &target);
+
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+        assert!(apply(b"abcdefgX", &patch).is_err());
+    }
+
+    #[test]
+    fn test_bps_sizes() {
+        let source = b"abcdefgh".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        write_number(&mut patch, source.len());
+        write_number(&mut patch, MAX_ROM_SIZE + 1);
+        write_number(&mut patch, 0);
+        let patch = add_footer(patch, &source, &source);
+        assert!(apply(&source, &patch).unwrap_err().contains("larger"));
+
+        // A target copy that would repeat "ab" far past the target size.
+        let mut patch = b"BPS1".to_vec();
+        write_number(&mut patch, source.len());
+        write_number(&mut patch, 4);
+        write_number(&mut patch, 0);
+        write_number(&mut patch, (2 - 1) << 2);
+        write_number(&mut patch, ((1_000_000_000 - 1) << 2) | 3);
+        write_number(&mut patch, 0);
+        let patch = add_footer(patch, &source, b"abab");
+        assert!(apply(&source, &patch).unwrap_err().contains("past the end"));
+    }
+
+    #[test]
+    fn test_header_checksum() {
+        let mut rom = crate::test_rom(&[0x18, 0xfe]);
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        fix_header_checksum(&mut rom);
+        let sum = rom[0x0134..=0x014d]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        // The checksum is defined so that the header plus 0x19 sums to 0.
+        assert_eq!(sum.wrapping_add(0x19), 0);
+    }
+}
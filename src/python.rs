@@ -0,0 +1,155 @@
+// Python bindings, built as an extension module by maturin with the `python` feature. This is only a thin layer
+// over GameBoy, converting arguments and results to and from Python types:
+//
+//     import robin_gb
+//     game_boy = robin_gb.GameBoy(open("game.gb", "rb").read())
+//     game_boy.set_button("start", True)
+//     frame = game_boy.emulate_next_frame()  # A 144x160 NumPy array of grayscale pixels.
+
+// The code #[pymethods] generates converts PyErr into itself, which clippy flags.
+#![allow(clippy::useless_conversion)]
+
+use crate::lcd::Lcd;
+use crate::{Button, GameBoy};
+use numpy::{PyArray1, PyArrayDyn, PyArrayMethods};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::panic::{self, AssertUnwindSafe};
+
+fn parse_button(name: &str) -> PyResult<Button> {
+    match name.to_lowercase().as_str() {
+        "a" => Ok(Button::A),
+        "b" => Ok(Button::B),
+        "start" => Ok(Button::START),
+        "select" => Ok(Button::SELECT),
+        "up" => Ok(Button::UP),
+        "down" => Ok(Button::DOWN),
+        "left" => Ok(Button::LEFT),
+        "right" => Ok(Button::RIGHT),
+        _ => Err(PyValueError::new_err(format!("Unknown button '{}'", name))),
+    }
+}
+
+#[pyclass(name = "GameBoy", module = "robin_gb")]
+struct PyGameBoy {
+    game_boy: GameBoy,
+}
+
+#[pymethods]
+impl PyGameBoy {
+    /// Raises ValueError if the ROM couldn't be loaded.
+    #[new]
+    fn new(rom: &[u8]) -> PyResult<Self> {
+        // Unsupported or truncated ROMs panic while loading, which pyo3 would raise as a PanicException that
+        // `except Exception` doesn't catch.
+        match panic::catch_unwind(AssertUnwindSafe(|| GameBoy::new(rom))) {
+            Ok(game_boy) => Ok(Self { game_boy }),
+            Err(_) => Err(PyValueError::new_err("The ROM couldn't be loaded")),
+        }
+    }
+
+    /// Emulates up to the next V-blank, and returns the completed frame.
+    fn emulate_next_frame<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyArrayDyn<u8>>> {
+        self.game_boy.emulate_next_frame();
+        self.frame(py)
+    }
+
+    /// The most recently completed frame, as a 144x160 array of grayscale pixels. Other pixel formats add a
+    /// third dimension for the bytes of each pixel.
+    fn frame<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArrayDyn<u8>>> {
+        let frame = self.game_boy.frame();
+        let bytes_per_pixel = frame.len() / Lcd::PIXEL_COUNT;
+
+        let mut shape = vec![Lcd::HEIGHT, Lcd::WIDTH];
+        if bytes_per_pixel > 1 {
+            shape.push(bytes_per_pixel);
+        }
+        PyArray1::from_slice_bound(py, frame).reshape(shape)
+    }
+
+    /// Buttons are named a, b, start, select, up, down, left and right.
+    fn set_button(&mut self, button: &str, is_down: bool) -> PyResult<()> {
+        self.game_boy.set_button(&parse_button(button)?, is_down);
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.game_boy.peek(address)
+    }
+
+    fn poke(&mut self, address: u16, value: u8) {
+        self.game_boy.poke(address, value);
+    }
+
+    /// The whole emulator state. It can only be loaded by the same build of the module.
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.game_boy.save_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        // A state from a different build or ROM would fail part way through loading, so check its size first.
+        if state.len() != self.game_boy.save_state().len() {
+            return Err(PyValueError::new_err(
+                "The state doesn't match this GameBoy",
+            ));
+        }
+        self.game_boy.load_state(state);
+        Ok(())
+    }
+
+    fn record_serial_output(&mut self, record: bool) {
+        self.game_boy.record_serial_output(record);
+    }
+
+    /// The bytes sent over the serial port, or None if record_serial_output(True) hasn't been called.
+    fn serial_buffer<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyBytes>> {
+        self.game_boy
+            .serial_buffer()
+            .as_ref()
+            .map(|buffer| PyBytes::new_bound(py, buffer))
+    }
+}
+
+#[pymodule]
+fn robin_gb(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyGameBoy>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only the parts that don't need NumPy, which isn't importable when running the Rust tests.
+    #[test]
+    fn test_game_boy() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let class = py.get_type_bound::<PyGameBoy>();
+
+            let error = class
+                .call1((PyBytes::new_bound(py, &[0; 16]),))
+                .unwrap_err();
+            assert!(error.is_instance_of::<PyValueError>(py));
+
+            let rom = crate::test_rom(&[0x18, 0xfe]);
+            let game_boy = class.call1((PyBytes::new_bound(py, &rom),)).unwrap();
+            game_boy
+                .call_method1("set_button", ("Start", true))
+                .unwrap();
+            let error = game_boy
+                .call_method1("set_button", ("turbo", true))
+                .unwrap_err();
+            assert!(error.is_instance_of::<PyValueError>(py));
+
+            game_boy.call_method1("poke", (0xc000, 0x42)).unwrap();
+            let value: u8 = game_boy
+                .call_method1("peek", (0xc000,))
+                .unwrap()
+                .extract()
+                .unwrap();
+            assert_eq!(value, 0x42);
+        });
+    }
+}
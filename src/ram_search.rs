@@ -0,0 +1,253 @@
+// Searching RAM for where a game keeps a value, like a lives counter, to build cheats from. A search starts
+// with every address in work RAM, high RAM and external RAM as a candidate, and snapshots them. Each filter
+// compares the current values against the last snapshot, or against a given value, keeps the candidates that
+// pass, and takes a new snapshot.
+
+use crate::GameBoy;
+
+/// How the bytes at a candidate address are read. 16-bit values are little-endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    U8,
+    U16,
+    /// Binary-coded decimal, 2 digits. Bytes with a digit above 9 aren't BCD, so never pass a filter.
+    Bcd8,
+    /// Binary-coded decimal, 4 digits.
+    Bcd16,
+}
+
+impl ValueKind {
+    /// Parses u8, u16, bcd8 or bcd16.
+    pub fn parse(text: &str) -> Result<ValueKind, String> {
+        match text {
+            "u8" => Ok(ValueKind::U8),
+            "u16" => Ok(ValueKind::U16),
+            "bcd8" => Ok(ValueKind::Bcd8),
+            "bcd16" => Ok(ValueKind::Bcd16),
+            _ => Err(format!("Unknown value kind '{}'", text)),
+        }
+    }
+
+    fn size(self) -> u16 {
+        match self {
+            ValueKind::U8 | ValueKind::Bcd8 => 1,
+            ValueKind::U16 | ValueKind::Bcd16 => 2,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> Option<u16> {
+        let bcd = |byte: u8| {
+            (byte >> 4 <= 9 && byte & 0x0f <= 9)
+                .then(|| u16::from((byte >> 4) * 10 + (byte & 0x0f)))
+        };
+
+        match self {
+            ValueKind::U8 => Some(u16::from(bytes[0])),
+            ValueKind::U16 => Some(u16::from_le_bytes([bytes[0], bytes[1]])),
+            ValueKind::Bcd8 => bcd(bytes[0]),
+            ValueKind::Bcd16 => Some(bcd(bytes[1])? * 100 + bcd(bytes[0])?),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// The same as in the last snapshot.
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    EqualTo(u16),
+}
+
+impl Comparison {
+    /// Parses equal, changed, increased, decreased, or equal-to=<decimal value>.
+    pub fn parse(text: &str) -> Result<Comparison, String> {
+        match text {
+            "equal" => Ok(Comparison::Equal),
+            "changed" => Ok(Comparison::Changed),
+            "increased" => Ok(Comparison::Increased),
+            "decreased" => Ok(Comparison::Decreased),
+            _ => {
+                let value = text
+                    .strip_prefix("equal-to=")
+                    .ok_or(format!("Unknown comparison '{}'", text))?;
+                let value = value
+                    .parse::<u16>()
+                    .map_err(|e| format!("'{}': {}", value, e))?;
+                Ok(Comparison::EqualTo(value))
+            }
+        }
+    }
+
+    fn passes(self, previous: u16, current: u16) -> bool {
+        match self {
+            Comparison::Equal => current == previous,
+            Comparison::Changed => current != previous,
+            Comparison::Increased => current > previous,
+            Comparison::Decreased => current < previous,
+            Comparison::EqualTo(value) => current == value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    pub address: u16,
+    /// The RAM bank that was mapped at the address when the search started.
+    pub bank: u8,
+    /// The value in the latest snapshot.
+    pub value: u16,
+}
+
+pub struct RamSearch {
+    kind: ValueKind,
+    // (address, bank) pairs, in address order.
+    candidates: Vec<(u16, u8)>,
+    snapshot: Vec<u8>,
+}
+
+// The whole address space, read without side effects. Only the searched regions are ever looked at.
+fn take_snapshot(game_boy: &GameBoy) -> Vec<u8> {
+    (0..=0xffff).map(|address| game_boy.peek(address)).collect()
+}
+
+impl RamSearch {
+    pub fn new(game_boy: &GameBoy, kind: ValueKind) -> Self {
+        let mut candidates = vec![];
+        for (range, bank) in game_boy.memory.ram_regions() {
+            // Multi-byte values have to fit inside the region.
+            let last_start = range.end() + 1 - kind.size();
+            candidates.extend((*range.start()..=last_start).map(|address| (address, bank)));
+        }
+
+        Self {
+            kind,
+            candidates,
+            snapshot: take_snapshot(game_boy),
+        }
+    }
+
+    fn value(&self, snapshot: &[u8], address: u16) -> Option<u16> {
+        let start = usize::from(address);
+        self.kind
+            .decode(&snapshot[start..start + usize::from(self.kind.size())])
+    }
+
+    /// Keeps the candidates whose current value passes the comparison, and takes a new snapshot. Returns the
+    /// number of candidates left.
+    pub fn filter(&mut self, game_boy: &GameBoy, comparison: Comparison) -> usize {
+        let snapshot = take_snapshot(game_boy);
+
+        let candidates = std::mem::take(&mut self.candidates);
+        self.candidates = candidates
+            .into_iter()
+            .filter(|(address, _)| {
+                match (
+                    self.value(&self.snapshot, *address),
+                    self.value(&snapshot, *address),
+                ) {
+                    (Some(previous), Some(current)) => comparison.passes(previous, current),
+                    _ => false,
+                }
+            })
+            .collect();
+
+        self.snapshot = snapshot;
+        self.candidates.len()
+    }
+
+    pub fn kind(&self) -> ValueKind {
+        self.kind
+    }
+
+    pub fn candidate_count(&self) -> usize {
+        self.candidates.len()
+    }
+
+    /// The surviving candidates, in address order. Values that don't decode, like invalid BCD, are 0.
+    pub fn results(&self) -> Vec<SearchResult> {
+        self.candidates
+            .iter()
+            .map(|&(address, bank)| SearchResult {
+                address,
+                bank,
+                value: self.value(&self.snapshot, address).unwrap_or(0),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idle_game_boy() -> GameBoy {
+        // loop: JR loop
+        GameBoy::new(&crate::test_rom(&[0x18, 0xfe]))
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut game_boy = idle_game_boy();
+        game_boy.poke(0xc123, 5);
+        let mut search = RamSearch::new(&game_boy, ValueKind::U8);
+        assert_eq!(search.candidate_count(), 0x2000 * 2 + 0x7f);
+
+        game_boy.poke(0xc123, 4);
+        game_boy.poke(0xa010, 1);
+        game_boy.emulate_next_frame();
+        search.filter(&game_boy, Comparison::Changed);
+        assert_eq!(search.candidate_count(), 2);
+
+        search.filter(&game_boy, Comparison::Equal);
+        assert_eq!(search.candidate_count(), 2);
+
+        game_boy.poke(0xc123, 3);
+        game_boy.poke(0xa010, 2);
+        assert_eq!(search.filter(&game_boy, Comparison::Decreased), 1);
+        assert_eq!(
+            search.results(),
+            [SearchResult {
+                address: 0xc123,
+                bank: 0,
+                value: 3
+            }]
+        );
+
+        assert_eq!(search.filter(&game_boy, Comparison::EqualTo(2)), 0);
+    }
+
+    #[test]
+    fn test_value_kinds() {
+        let mut game_boy = idle_game_boy();
+        game_boy.poke(0xd000, 0x99);
+        game_boy.poke(0xd001, 0x12);
+
+        let mut bcd = RamSearch::new(&game_boy, ValueKind::Bcd16);
+        let mut binary = RamSearch::new(&game_boy, ValueKind::U16);
+        assert_eq!(bcd.filter(&game_boy, Comparison::EqualTo(1299)), 1);
+        assert_eq!(binary.filter(&game_boy, Comparison::EqualTo(0x1299)), 1);
+        assert_eq!(binary.results()[0].bank, 1);
+
+        // 1299 to 1300 is an increase either way.
+        game_boy.poke(0xd000, 0x00);
+        game_boy.poke(0xd001, 0x13);
+        assert_eq!(bcd.filter(&game_boy, Comparison::Increased), 1);
+        assert_eq!(bcd.results()[0].value, 1300);
+        assert_eq!(binary.filter(&game_boy, Comparison::Increased), 1);
+
+        // Invalid BCD never passes.
+        game_boy.poke(0xd000, 0x0a);
+        assert_eq!(bcd.filter(&game_boy, Comparison::Changed), 0);
+
+        assert_eq!(ValueKind::Bcd8.decode(&[0x42]), Some(42));
+        assert_eq!(ValueKind::Bcd8.decode(&[0xa0]), None);
+        assert_eq!(
+            Comparison::parse("equal-to=300"),
+            Ok(Comparison::EqualTo(300))
+        );
+        assert!(Comparison::parse("bigger").is_err());
+        assert!(ValueKind::parse("u32").is_err());
+    }
+}
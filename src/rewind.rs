@@ -0,0 +1,232 @@
+// A ring buffer of periodic snapshots for rewinding. The newest snapshot is kept whole, and each older one is
+// stored as its difference from the next newer one, run-length encoded. Most of the state is unchanged between
+// snapshots, so the differences are mostly runs of zeros. Dropping the oldest snapshot never needs any
+// re-encoding, as nothing depends on it.
+
+use std::collections::VecDeque;
+
+enum Snapshot {
+    // This snapshot XORed with the next newer one, compressed.
+    Delta(Vec<u8>),
+    // Kept whole when the state size changed between snapshots, e.g. because the pixel format did.
+    Full(Vec<u8>),
+}
+
+impl Snapshot {
+    fn size(&self) -> usize {
+        match self {
+            Snapshot::Delta(bytes) | Snapshot::Full(bytes) => bytes.len(),
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input[0];
+        *input = &input[1..];
+        value |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// A series of (zero run length, literal length, literal bytes) over the XOR of the two states.
+fn compress(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    // Short runs of zeros are cheaper to leave in a literal than to split it.
+    const MIN_ZERO_RUN: usize = 4;
+
+    let diff: Vec<u8> = older.iter().zip(newer).map(|(a, b)| a ^ b).collect();
+    let mut compressed = vec![];
+    let mut index = 0;
+
+    while index < diff.len() {
+        let zero_run = diff[index..].iter().take_while(|&&byte| byte == 0).count();
+        index += zero_run;
+
+        let mut literal_end = index;
+        while literal_end < diff.len() && !diff[literal_end..].starts_with(&[0; MIN_ZERO_RUN]) {
+            literal_end += 1;
+        }
+
+        write_varint(&mut compressed, zero_run);
+        write_varint(&mut compressed, literal_end - index);
+        compressed.extend_from_slice(&diff[index..literal_end]);
+        index = literal_end;
+    }
+
+    compressed
+}
+
+fn decompress(mut compressed: &[u8], newer: &[u8]) -> Vec<u8> {
+    let mut state = newer.to_vec();
+    let mut index = 0;
+
+    while !compressed.is_empty() {
+        index += read_varint(&mut compressed);
+        let literal_length = read_varint(&mut compressed);
+
+        let (literal, rest) = compressed.split_at(literal_length);
+        for (byte, diff) in state[index..index + literal_length].iter_mut().zip(literal) {
+            *byte ^= diff;
+        }
+        compressed = rest;
+        index += literal_length;
+    }
+
+    state
+}
+
+pub struct RewindBuffer {
+    interval_frames: u32,
+    memory_budget: usize,
+    // Frames completed since rewinding was enabled, which snapshots are numbered by.
+    frame: u64,
+    newest: Option<(u64, Vec<u8>)>,
+    // Oldest first.
+    older: VecDeque<(u64, Snapshot)>,
+    older_size: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(interval_frames: u32, memory_budget: usize) -> Self {
+        Self {
+            interval_frames: interval_frames.max(1),
+            memory_budget,
+            frame: 0,
+            newest: None,
+            older: VecDeque::new(),
+            older_size: 0,
+        }
+    }
+
+    // Counts a completed frame. Returns true if a snapshot should be pushed for it.
+    pub fn frame_completed(&mut self) -> bool {
+        self.frame += 1;
+        self.frame.is_multiple_of(u64::from(self.interval_frames))
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some((newest_frame, newest)) = self.newest.take() {
+            let snapshot = if newest.len() == state.len() {
+                Snapshot::Delta(compress(&newest, &state))
+            } else {
+                Snapshot::Full(newest)
+            };
+            self.older_size += snapshot.size();
+            self.older.push_back((newest_frame, snapshot));
+        }
+
+        // The newest snapshot is always kept, even if it's over budget by itself.
+        while state.len() + self.older_size > self.memory_budget {
+            let Some((_, snapshot)) = self.older.pop_front() else {
+                break;
+            };
+            self.older_size -= snapshot.size();
+        }
+
+        self.newest = Some((self.frame, state));
+    }
+
+    // Steps back to the newest snapshot that's at least `frames` frames old, or the oldest one there is, and
+    // drops everything newer. Returns the number of frames stepped back and the snapshot's state.
+    pub fn rewind(&mut self, frames: u32) -> Option<(u64, &[u8])> {
+        let target_frame = self.frame.saturating_sub(u64::from(frames));
+        let (mut frame, mut state) = self.newest.take()?;
+
+        while frame > target_frame {
+            let Some((older_frame, snapshot)) = self.older.pop_back() else {
+                break;
+            };
+            self.older_size -= snapshot.size();
+
+            state = match snapshot {
+                Snapshot::Delta(compressed) => decompress(&compressed, &state),
+                Snapshot::Full(older_state) => older_state,
+            };
+            frame = older_frame;
+        }
+
+        let frames_rewound = self.frame - frame;
+        self.frame = frame;
+        let (_, state) = self.newest.insert((frame, state));
+        Some((frames_rewound, state))
+    }
+
+    // The total size of the snapshots, in bytes.
+    pub fn size(&self) -> usize {
+        self.newest.as_ref().map_or(0, |(_, state)| state.len()) + self.older_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression() {
+        let older = [1, 2, 3, 0, 0, 0, 0, 0, 0, 9, 9, 9];
+        let newer = [1, 2, 4, 0, 0, 0, 0, 0, 0, 9, 9, 8];
+        let compressed = compress(&older, &newer);
+        assert_eq!(compressed, [2, 1, 7, 8, 1, 1]);
+        assert_eq!(decompress(&compressed, &newer), older);
+
+        let long = vec![0x55; 1000];
+        assert_eq!(compress(&long, &long), [0xe8, 0x07, 0x00]);
+        assert_eq!(decompress(&compress(&long, &long), &long), long);
+    }
+
+    #[test]
+    fn test_rewind_buffer() {
+        let mut buffer = RewindBuffer::new(2, 1000);
+        buffer.push(vec![0; 100]);
+
+        for frame in 1..=10 {
+            if buffer.frame_completed() {
+                buffer.push(vec![frame; 100]);
+            }
+        }
+
+        // Frame 10 can't step back 3 frames exactly, so it goes to the snapshot from frame 6.
+        let (frames_rewound, state) = buffer.rewind(3).unwrap();
+        assert_eq!(frames_rewound, 4);
+        assert_eq!(state, [6; 100]);
+
+        // Rewinding past the start stops at the first snapshot.
+        let (frames_rewound, state) = buffer.rewind(100).unwrap();
+        assert_eq!(frames_rewound, 6);
+        assert_eq!(state, [0; 100]);
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let mut buffer = RewindBuffer::new(1, 300);
+        for frame in 0..100u8 {
+            let mut state = vec![0; 200];
+            state[..50].fill(frame);
+            buffer.push(state);
+            buffer.frame_completed();
+        }
+        assert!(buffer.size() <= 300);
+
+        // Each delta is 55 bytes, so only 1 fits alongside the newest snapshot, from the frame before.
+        let (frames_rewound, state) = buffer.rewind(100).unwrap();
+        assert_eq!(frames_rewound, 2);
+        assert_eq!(state[0], 98);
+    }
+}
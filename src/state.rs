@@ -0,0 +1,102 @@
+// The emulator's state as a flat byte buffer, for snapshots. Each part appends its own fields in a fixed
+// order and reads them back in the same order. The format is only meant to be loaded by the same build.
+
+use crate::GameBoy;
+
+// Reads the next N bytes. States are only made by save_state(), so running out of input is a bug.
+pub fn take<const N: usize>(input: &mut &[u8]) -> [u8; N] {
+    let (bytes, rest) = input.split_at(N);
+    *input = rest;
+    bytes.try_into().unwrap()
+}
+
+// 64-bit FNV-1a, which is the same on every platform and Rust version.
+pub fn hash(state: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in state {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+impl GameBoy {
+    // Debugger, tracer and host settings like the pixel format aren't part of the state.
+    pub(crate) fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![];
+        self.cpu.save_state(&mut state);
+        self.memory.save_state(&mut state);
+        self.timer.save_state(&mut state);
+        self.lcd.save_state(&mut state);
+        state
+    }
+
+    // The state without the completed frame, which is in the host's pixel format. Movie hashes are of this, so
+    // that they match however the frame is being displayed.
+    pub(crate) fn emulated_state(&self) -> Vec<u8> {
+        let mut state = vec![];
+        self.cpu.save_state(&mut state);
+        self.memory.save_state(&mut state);
+        self.timer.save_state(&mut state);
+        self.lcd.save_emulated_state(&mut state);
+        state
+    }
+
+    // Checks that a state from elsewhere, like a movie file, can be loaded: that it's the emulated state
+    // followed by a frame of the size it says. The frame can be in any pixel format.
+    pub(crate) fn check_state(&self, state: &[u8]) -> Result<(), String> {
+        let emulated_size = self.emulated_state().len();
+        let frame_size = state
+            .get(emulated_size..emulated_size + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize);
+
+        if frame_size.is_some_and(|frame_size| state.len() - emulated_size - 4 == frame_size) {
+            Ok(())
+        } else {
+            Err(format!(
+                "The state is {} bytes, which doesn't fit this game's {} bytes of emulated state and a frame",
+                state.len(),
+                emulated_size
+            ))
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, mut state: &[u8]) {
+        let input = &mut state;
+        self.cpu.load_state(input);
+        self.memory.load_state(input);
+        self.timer.load_state(input);
+        self.lcd.load_state(input);
+        assert!(input.is_empty(), "Unexpected data at the end of the state");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{GameBoy, Register};
+
+    #[test]
+    fn test_save_and_load_state() {
+        // LD HL,0xc000; loop: INC (HL); JR loop
+        let program = [0x21, 0x00, 0xc0, 0x34, 0x18, 0xfd];
+        let mut game_boy = GameBoy::new(&crate::test_rom(&program));
+        game_boy.emulate_next_frame();
+        let state = game_boy.save_state();
+        let frame = game_boy.frame().to_vec();
+
+        let a = game_boy.register(Register::A);
+        let pc = game_boy.register(Register::PC);
+        let counter = game_boy.peek(0xc000);
+
+        game_boy.emulate_next_frame();
+        game_boy.emulate_next_frame();
+        assert_ne!(game_boy.peek(0xc000), counter);
+
+        game_boy.load_state(&state);
+        assert_eq!(game_boy.register(Register::A), a);
+        assert_eq!(game_boy.register(Register::PC), pc);
+        assert_eq!(game_boy.peek(0xc000), counter);
+        assert_eq!(game_boy.frame(), frame);
+        assert_eq!(game_boy.save_state(), state);
+    }
+}
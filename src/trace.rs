@@ -0,0 +1,157 @@
+// Execution tracing in the Gameboy Doctor log format, one line per executed instruction:
+// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//
+// Gameboy Doctor's reference logs are made with LY fixed at 0x90, so pair this with
+// GameBoy::set_ly_override(Some(0x90)) when comparing against them.
+
+use crate::cpu::{Register, Registers};
+use crate::Memory;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+pub struct Tracer {
+    sink: Box<dyn Write + Send>,
+    pc_range: Option<RangeInclusive<u16>>,
+    bank: Option<u8>,
+}
+
+impl Tracer {
+    pub fn new(sink: impl Write + Send + 'static) -> Self {
+        Self {
+            sink: Box::new(sink),
+            pc_range: None,
+            bank: None,
+        }
+    }
+
+    /// Only trace instructions with a PC inside this range.
+    pub fn pc_range(mut self, pc_range: RangeInclusive<u16>) -> Self {
+        self.pc_range = Some(pc_range);
+        self
+    }
+
+    /// Only trace instructions in this ROM bank. Instructions outside ROM are not traced.
+    pub fn bank(mut self, bank: u8) -> Self {
+        self.bank = Some(bank);
+        self
+    }
+
+    // Writes a line for the instruction at PC, if it passes the filters.
+    pub(crate) fn trace(&mut self, registers: &Registers, memory: &Memory) -> io::Result<()> {
+        let pc = registers.get(Register::PC);
+
+        if let Some(pc_range) = &self.pc_range {
+            if !pc_range.contains(&pc) {
+                return Ok(());
+            }
+        }
+
+        if let Some(bank) = self.bank {
+            if memory.rom_bank_at(pc) != Some(bank) {
+                return Ok(());
+            }
+        }
+
+        let pc_memory = [0, 1, 2, 3].map(|offset| memory.peek(pc.wrapping_add(offset)));
+
+        writeln!(
+            self.sink,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            registers.get(Register::A),
+            registers.get(Register::F),
+            registers.get(Register::B),
+            registers.get(Register::C),
+            registers.get(Register::D),
+            registers.get(Register::E),
+            registers.get(Register::H),
+            registers.get(Register::L),
+            registers.get(Register::SP),
+            pc,
+            pc_memory[0],
+            pc_memory[1],
+            pc_memory[2],
+            pc_memory[3],
+        )
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.sink.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_rom, GameBoy};
+    use std::sync::{Arc, Mutex};
+
+    // A sink that can still be read after the tracer has taken ownership of it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<String> {
+            let bytes = self.0.lock().unwrap();
+            String::from_utf8(bytes.clone())
+                .unwrap()
+                .lines()
+                .map(str::to_owned)
+                .collect()
+        }
+    }
+
+    // LD A,5; LD (0xc000),A; loop: INC A; JR loop
+    const PROGRAM: [u8; 8] = [0x3e, 0x05, 0xea, 0x00, 0xc0, 0x3c, 0x18, 0xfd];
+
+    #[test]
+    fn test_trace_format() {
+        let buffer = SharedBuffer::default();
+        let mut game_boy = GameBoy::new(&test_rom(&PROGRAM));
+        game_boy.set_tracer(Some(Tracer::new(buffer.clone())));
+
+        let _ = game_boy.step_instruction();
+        let _ = game_boy.step_instruction();
+
+        assert_eq!(
+            buffer.lines(),
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,05,EA,00",
+                "A:05 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:EA,00,C0,3C",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_filters() {
+        let buffer = SharedBuffer::default();
+        let mut game_boy = GameBoy::new(&test_rom(&PROGRAM));
+        game_boy.set_tracer(Some(Tracer::new(buffer.clone()).pc_range(0x0105..=0x0105)));
+
+        for _ in 0..6 {
+            let _ = game_boy.step_instruction();
+        }
+
+        // The loop visits 0x0105 on every second instruction after the first two.
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.contains("PC:0105")));
+
+        // Nothing executes in ROM bank 1.
+        let buffer = SharedBuffer::default();
+        game_boy.set_tracer(Some(Tracer::new(buffer.clone()).bank(1)));
+        let _ = game_boy.step_instruction();
+        assert!(buffer.lines().is_empty());
+    }
+}
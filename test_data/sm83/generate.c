@@ -0,0 +1,232 @@
+/*
+ * Generates the SM83 single-step test vectors in test_data/sm83/v1.
+ *
+ * The vectors are in the format of the public SingleStepTests/sm83 suite, and its files can replace these as
+ * they are. They're produced here by running each instruction through SameBoy's CPU core
+ * (https://github.com/LIJI32/SameBoy, Expat licence) on a flat 64kB memory, from random states.
+ *
+ * Build against SameBoy's Core directory, and run from the repository root:
+ *
+ *     cc -std=gnu11 -DGB_INTERNAL -D_GNU_SOURCE -I path/to/SameBoy/Core -o /tmp/generate test_data/sm83/generate.c
+ *     /tmp/generate test_data/sm83/v1
+ *
+ * Interrupts are never pending, no buttons are held and the CPU isn't a CGB, so HALT halts and STOP stops.
+ * States whose instruction would access 0xff00-0xff7f are skipped, as SameBoy times writes to the I/O
+ * registers differently and the vectors have no I/O registers.
+ */
+
+#include <stdarg.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "sm83_cpu.c"
+
+#define TESTS_PER_OPCODE 20
+
+typedef struct {
+    uint16_t address;
+    uint8_t value;
+    bool write;
+    unsigned cycle;
+} access_t;
+
+static uint8_t memory[0x10000];
+static access_t accesses[64];
+static unsigned access_count;
+static unsigned elapsed_cycles;
+
+uint8_t GB_read_memory(GB_gameboy_t *gb, uint16_t addr)
+{
+    accesses[access_count++] = (access_t){addr, memory[addr], false, elapsed_cycles / 4};
+    return memory[addr];
+}
+
+void GB_write_memory(GB_gameboy_t *gb, uint16_t addr, uint8_t value)
+{
+    memory[addr] = value;
+    accesses[access_count++] = (access_t){addr, value, true, elapsed_cycles / 4};
+}
+
+void GB_advance_cycles(GB_gameboy_t *gb, uint8_t cycles)
+{
+    elapsed_cycles += cycles;
+}
+
+bool GB_is_cgb(const GB_gameboy_t *gb) { return false; }
+bool GB_is_sgb(GB_gameboy_t *gb) { return false; }
+void GB_log(GB_gameboy_t *gb, const char *fmt, ...) {}
+void GB_display_run(GB_gameboy_t *gb, unsigned cycles, bool force) {}
+void GB_dma_run(GB_gameboy_t *gb) {}
+void GB_hdma_run(GB_gameboy_t *gb) {}
+void GB_trigger_oam_bug(GB_gameboy_t *gb, uint16_t address) {}
+void GB_timing_sync(GB_gameboy_t *gb) {}
+#ifndef GB_DISABLE_DEBUGGER
+void GB_debugger_break(GB_gameboy_t *gb) {}
+void GB_debugger_call_hook(GB_gameboy_t *gb, uint16_t call_addr) {}
+void GB_debugger_ret_hook(GB_gameboy_t *gb) {}
+#endif
+
+// xorshift32, seeded per opcode so that each file can be regenerated on its own.
+static uint32_t random_state;
+
+static uint8_t random_u8(void)
+{
+    random_state ^= random_state << 13;
+    random_state ^= random_state >> 17;
+    random_state ^= random_state << 5;
+    return random_state >> 24;
+}
+
+static uint16_t random_u16(void)
+{
+    return random_u8() | random_u8() << 8;
+}
+
+static bool is_invalid_opcode(uint8_t opcode)
+{
+    static const uint8_t invalid[] = {0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd};
+    for (unsigned i = 0; i < sizeof(invalid); i++) {
+        if (opcode == invalid[i]) return true;
+    }
+    return false;
+}
+
+// The addresses accessed by the instruction, in ascending order and without duplicates.
+static unsigned touched_addresses(const access_t *kept, unsigned kept_count, uint16_t *addresses)
+{
+    unsigned count = 0;
+    for (unsigned i = 0; i < kept_count; i++) {
+        unsigned j = 0;
+        while (j < count && addresses[j] < kept[i].address) j++;
+        if (j < count && addresses[j] == kept[i].address) continue;
+        memmove(&addresses[j + 1], &addresses[j], (count - j) * sizeof(*addresses));
+        addresses[j] = kept[i].address;
+        count++;
+    }
+    return count;
+}
+
+static void print_state(FILE *file, const GB_gameboy_t *gb, const uint8_t *ram, const uint16_t *addresses,
+                        unsigned address_count)
+{
+    fprintf(file, "{\"pc\":%u,\"sp\":%u,\"a\":%u,\"b\":%u,\"c\":%u,\"d\":%u,\"e\":%u,\"f\":%u,\"h\":%u,\"l\":%u,"
+                  "\"ime\":%u,\"ie\":%u,\"ram\":[",
+            gb->pc, gb->sp, gb->af >> 8, gb->bc >> 8, gb->bc & 0xff, gb->de >> 8, gb->de & 0xff, gb->af & 0xff,
+            gb->hl >> 8, gb->hl & 0xff, gb->ime, gb->interrupt_enable);
+    for (unsigned i = 0; i < address_count; i++) {
+        fprintf(file, "%s[%u,%u]", i ? "," : "", addresses[i], ram[addresses[i]]);
+    }
+    fprintf(file, "]}");
+}
+
+// Writes one test, or returns false if the random state isn't usable.
+static bool generate_test(FILE *file, const char *name, bool cb, uint8_t opcode, bool first)
+{
+    static GB_gameboy_t gb;
+    static uint8_t initial_memory[0x10000];
+
+    memset(&gb, 0, sizeof(gb));
+    gb.model = GB_MODEL_DMG_B;
+    gb.io_registers[GB_IO_JOYP] = 0xff;
+    gb.af = random_u16() & 0xfff0;
+    gb.bc = random_u16();
+    gb.de = random_u16();
+    gb.hl = random_u16();
+    gb.sp = random_u16();
+    gb.pc = random_u16();
+    gb.ime = random_u8() & 1;
+
+    for (unsigned i = 0; i < sizeof(memory); i++) {
+        memory[i] = random_u8();
+    }
+    if (cb) {
+        memory[gb.pc] = 0xcb;
+        memory[(uint16_t)(gb.pc + 1)] = opcode;
+    }
+    else {
+        memory[gb.pc] = opcode;
+    }
+    memcpy(initial_memory, memory, sizeof(memory));
+    GB_gameboy_t initial = gb;
+
+    access_count = 0;
+    elapsed_cycles = 0;
+    uint8_t fetched = cycle_read(&gb, gb.pc++);
+    opcodes[fetched](&gb, fetched);
+    flush_pending_cycles(&gb);
+
+    // Keep the last access of each machine cycle, which drops STOP's untimed accesses, and HALT's read of
+    // the next opcode, which belongs to the cycle after the instruction.
+    unsigned cycle_count = elapsed_cycles / 4;
+    access_t kept[64];
+    unsigned kept_count = 0;
+    for (unsigned i = 0; i < access_count; i++) {
+        if (accesses[i].cycle >= cycle_count) continue;
+        if (kept_count && kept[kept_count - 1].cycle == accesses[i].cycle) kept_count--;
+        kept[kept_count++] = accesses[i];
+    }
+    for (unsigned i = 0; i < kept_count; i++) {
+        if ((kept[i].address & 0xff80) == 0xff00) return false;
+    }
+
+    uint16_t addresses[64];
+    unsigned address_count = touched_addresses(kept, kept_count, addresses);
+
+    fprintf(file, "%s{\"name\":\"%s\",\"initial\":", first ? "" : ",\n", name);
+    print_state(file, &initial, initial_memory, addresses, address_count);
+    fprintf(file, ",\"final\":");
+    print_state(file, &gb, memory, addresses, address_count);
+    fprintf(file, ",\"cycles\":[");
+    for (unsigned cycle = 0, i = 0; cycle < cycle_count; cycle++) {
+        fprintf(file, "%s", cycle ? "," : "");
+        if (i < kept_count && kept[i].cycle == cycle) {
+            fprintf(file, "[%u,%u,\"%s\"]", kept[i].address, kept[i].value, kept[i].write ? "-wm" : "r-m");
+            i++;
+        }
+        else {
+            fprintf(file, "null");
+        }
+    }
+    fprintf(file, "]}");
+    return true;
+}
+
+static void generate_file(const char *directory, bool cb, uint8_t opcode)
+{
+    char prefix[8], path[4096];
+    snprintf(prefix, sizeof(prefix), cb ? "cb %02x" : "%02x", opcode);
+    snprintf(path, sizeof(path), "%s/%s.json", directory, prefix);
+
+    FILE *file = fopen(path, "w");
+    if (!file) {
+        perror(path);
+        exit(1);
+    }
+
+    random_state = 0x5eed0000 | cb << 8 | opcode;
+    fprintf(file, "[\n");
+    for (unsigned i = 0; i < TESTS_PER_OPCODE;) {
+        char name[16];
+        snprintf(name, sizeof(name), "%s %04x", prefix, i);
+        if (generate_test(file, name, cb, opcode, i == 0)) i++;
+    }
+    fprintf(file, "\n]\n");
+    fclose(file);
+}
+
+int main(int argc, char **argv)
+{
+    if (argc != 2) {
+        fprintf(stderr, "Usage: %s <output directory>\n", argv[0]);
+        return 1;
+    }
+
+    for (unsigned opcode = 0; opcode < 0x100; opcode++) {
+        if (opcode != 0xcb && !is_invalid_opcode(opcode)) {
+            generate_file(argv[1], false, opcode);
+        }
+        generate_file(argv[1], true, opcode);
+    }
+    return 0;
+}